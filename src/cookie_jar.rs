@@ -0,0 +1,393 @@
+use crate::error::M3u8Error;
+use cookie::{Cookie, Expiration};
+use reqwest::header::HeaderValue;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::{Host, Url};
+
+// 单条 Cookie，字段与 Netscape cookies.txt 格式一致
+#[derive(Debug, Clone, PartialEq)]
+pub struct CookieEntry {
+    pub domain: String,
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    // 过期时间（Unix 秒），0 表示会话 Cookie
+    pub expires: u64,
+    pub name: String,
+    pub value: String,
+    // 仅限 HTTP 访问，cookies.txt 中以 #HttpOnly_ 前缀标记
+    pub http_only: bool,
+}
+
+impl CookieEntry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host() else {
+            return false;
+        };
+
+        let domain_ok = if self.include_subdomains {
+            domain_match(&host, &self.domain)
+        } else {
+            host.to_string().to_ascii_lowercase() == self.domain
+        };
+        let path_ok = path_match(url.path(), &self.path);
+        let secure_ok = !self.secure || url.scheme() == "https";
+
+        domain_ok && path_ok && secure_ok
+    }
+}
+
+// RFC 6265 5.1.3 域名匹配：主机名与域名相同，或主机名以 ".域名" 结尾且不是 IP 地址
+fn domain_match(host: &Host<&str>, domain: &str) -> bool {
+    match host {
+        Host::Domain(host) => {
+            let host = host.to_ascii_lowercase();
+            host == domain
+                || host
+                    .strip_suffix(domain)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        }
+        ip => ip.to_string() == domain,
+    }
+}
+
+// RFC 6265 5.1.4 路径匹配：路径相同，或 Cookie 路径是请求路径的前缀，
+// 且 Cookie 路径以 / 结尾或请求路径中紧随其后的字符为 /
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+// RFC 6265 5.1.4 默认路径：请求路径最后一个 / 之前的部分
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => url.path()[..index].to_string(),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// 共享 Cookie 存储：供客户端池中所有客户端使用，支持 Netscape cookies.txt 导入导出
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: RwLock<Vec<CookieEntry>>,
+    // 下载结束后写回更新的 Cookie 的文件
+    save_path: Option<PathBuf>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 设置 Cookie 持久化文件
+    pub fn with_save_path(mut self, save_path: PathBuf) -> Self {
+        self.save_path = Some(save_path);
+        self
+    }
+
+    // 从 Netscape cookies.txt 文件加载
+    pub async fn load_netscape(path: &Path) -> Result<Self, M3u8Error> {
+        let content = tokio::fs::read_to_string(path).await?;
+        Self::parse_netscape(&content)
+    }
+
+    // 解析 Netscape cookies.txt 内容，# 开头为注释（#HttpOnly_ 前缀除外）
+    pub fn parse_netscape(content: &str) -> Result<Self, M3u8Error> {
+        let mut cookies = Vec::new();
+
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                return Err(M3u8Error::ParseError(format!(
+                    "cookies.txt 第 {} 行格式错误，应为 7 个制表符分隔的字段",
+                    line_number + 1
+                )));
+            }
+
+            let domain = fields[0].trim_start_matches('.').to_ascii_lowercase();
+            cookies.push(CookieEntry {
+                include_subdomains: fields[1].eq_ignore_ascii_case("TRUE")
+                    || fields[0].starts_with('.'),
+                domain,
+                path: fields[2].to_string(),
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                expires: fields[4].parse().unwrap_or(0),
+                name: fields[5].to_string(),
+                value: fields[6].to_string(),
+                http_only,
+            });
+        }
+
+        Ok(Self {
+            cookies: RwLock::new(cookies),
+            save_path: None,
+        })
+    }
+
+    // 导出为 Netscape cookies.txt 内容，已过期的 Cookie 不会写出
+    pub fn to_netscape(&self) -> String {
+        let now = unix_now();
+        let mut content = String::from("# Netscape HTTP Cookie File\n");
+
+        for entry in self.cookies.read().unwrap().iter() {
+            if entry.is_expired(now) {
+                continue;
+            }
+            let domain = if entry.include_subdomains {
+                format!(".{}", entry.domain)
+            } else {
+                entry.domain.clone()
+            };
+            content.push_str(&format!(
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if entry.http_only { "#HttpOnly_" } else { "" },
+                domain,
+                if entry.include_subdomains {
+                    "TRUE"
+                } else {
+                    "FALSE"
+                },
+                entry.path,
+                if entry.secure { "TRUE" } else { "FALSE" },
+                entry.expires,
+                entry.name,
+                entry.value
+            ));
+        }
+
+        content
+    }
+
+    // 保存到 Netscape cookies.txt 文件
+    pub async fn save_netscape(&self, path: &Path) -> Result<(), M3u8Error> {
+        tokio::fs::write(path, self.to_netscape()).await?;
+        Ok(())
+    }
+
+    // 如已设置持久化文件，则将当前 Cookie 写回磁盘
+    pub async fn persist(&self) -> Result<(), M3u8Error> {
+        match &self.save_path {
+            Some(path) => self.save_netscape(path).await,
+            None => Ok(()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 写入一条 Set-Cookie，同域名、路径、名称的 Cookie 会被替换；
+    // Domain 属性与请求主机不匹配的 Cookie 会被忽略，以免写入其他站点的 Cookie；
+    // 只有一级的 Domain（如 com）可能是公共后缀，与主机名相同时按仅限本主机处理，否则忽略
+    fn insert_set_cookie(&self, set_cookie: &str, url: &Url) {
+        let Ok(cookie) = Cookie::parse(set_cookie) else {
+            return;
+        };
+        let Some(host) = url.host() else {
+            return;
+        };
+
+        let domain_attribute = cookie
+            .domain()
+            .map(|domain| domain.trim_start_matches('.').to_ascii_lowercase())
+            .filter(|domain| !domain.is_empty());
+        let host_name = host.to_string().to_ascii_lowercase();
+        let (domain, include_subdomains) = match domain_attribute {
+            Some(domain) if !domain_match(&host, &domain) => return,
+            Some(domain) if matches!(host, Host::Domain(_)) && !domain.contains('.') => {
+                if domain != host_name {
+                    return;
+                }
+                (domain, false)
+            }
+            // IP 地址只能设置仅限本主机的 Cookie
+            Some(domain) => (domain, matches!(host, Host::Domain(_))),
+            None => (host_name, false),
+        };
+        // Path 属性须以 / 开头，否则使用默认路径
+        let path = cookie
+            .path()
+            .filter(|path| path.starts_with('/'))
+            .map(str::to_string)
+            .unwrap_or_else(|| default_path(url));
+
+        let now = unix_now();
+        let expires = match (cookie.max_age(), cookie.expires()) {
+            (Some(max_age), _) => {
+                let seconds = max_age.whole_seconds();
+                if seconds <= 0 {
+                    1
+                } else {
+                    now + seconds as u64
+                }
+            }
+            (None, Some(Expiration::DateTime(datetime))) => datetime.unix_timestamp().max(1) as u64,
+            _ => 0,
+        };
+
+        let entry = CookieEntry {
+            domain,
+            include_subdomains,
+            path,
+            secure: cookie.secure().unwrap_or(false),
+            expires,
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            http_only: cookie.http_only().unwrap_or(false),
+        };
+
+        let mut cookies = self.cookies.write().unwrap();
+        cookies.retain(|c| {
+            !(c.domain == entry.domain && c.path == entry.path && c.name == entry.name)
+        });
+        if !entry.is_expired(now) {
+            cookies.push(entry);
+        }
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        for header in cookie_headers {
+            if let Ok(set_cookie) = header.to_str() {
+                self.insert_set_cookie(set_cookie, url);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let now = unix_now();
+        let cookies = self.cookies.read().unwrap();
+        let header = cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(url))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if header.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&header).ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::cookie::CookieStore;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn cookie_header(jar: &CookieJar, request_url: &str) -> Option<String> {
+        jar.cookies(&url(request_url))
+            .map(|header| header.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn rejects_foreign_domain_attribute() {
+        let jar = CookieJar::new();
+        let origin = url("https://cdn.example.com/live/index.m3u8");
+        jar.insert_set_cookie("a=1; Domain=evil.com", &origin);
+        jar.insert_set_cookie("b=2; Domain=ample.com", &origin);
+        jar.insert_set_cookie("c=3; Domain=other.example.com", &origin);
+        assert!(jar.is_empty());
+
+        jar.insert_set_cookie("d=4; Domain=.example.com", &origin);
+        assert_eq!(
+            cookie_header(&jar, "https://www.example.com/live/seg.ts").as_deref(),
+            Some("d=4")
+        );
+    }
+
+    #[test]
+    fn rejects_single_label_domain_attribute() {
+        let jar = CookieJar::new();
+        jar.insert_set_cookie("a=1; Domain=com", &url("https://example.com/index.m3u8"));
+        jar.insert_set_cookie(
+            "b=2; Domain=.COM",
+            &url("https://cdn.example.com/index.m3u8"),
+        );
+        assert!(jar.is_empty());
+
+        // 与主机名相同时只发送给该主机
+        jar.insert_set_cookie("c=3; Domain=localhost", &url("http://localhost/index.m3u8"));
+        assert_eq!(
+            cookie_header(&jar, "http://localhost/seg.ts").as_deref(),
+            Some("c=3")
+        );
+        assert_eq!(cookie_header(&jar, "http://cdn.localhost/seg.ts"), None);
+    }
+
+    #[test]
+    fn host_only_cookie_skips_subdomains() {
+        let jar = CookieJar::new();
+        jar.insert_set_cookie("a=1", &url("https://example.com/index.m3u8"));
+        assert_eq!(
+            cookie_header(&jar, "https://example.com/seg.ts").as_deref(),
+            Some("a=1")
+        );
+        assert_eq!(cookie_header(&jar, "https://cdn.example.com/seg.ts"), None);
+    }
+
+    #[test]
+    fn path_match_requires_segment_boundary() {
+        assert!(path_match("/video", "/video"));
+        assert!(path_match("/video/seg.ts", "/video"));
+        assert!(path_match("/video/seg.ts", "/video/"));
+        assert!(!path_match("/videos/seg.ts", "/video"));
+        assert!(!path_match("/", "/video"));
+    }
+
+    #[test]
+    fn default_path_is_request_directory() {
+        let jar = CookieJar::new();
+        jar.insert_set_cookie("a=1", &url("https://example.com/live/hd/index.m3u8"));
+        assert_eq!(
+            cookie_header(&jar, "https://example.com/live/hd/seg.ts").as_deref(),
+            Some("a=1")
+        );
+        assert_eq!(cookie_header(&jar, "https://example.com/live/sd.ts"), None);
+    }
+
+    #[test]
+    fn http_only_survives_round_trip() {
+        let content = "# Netscape HTTP Cookie File\n\
+            #HttpOnly_.example.com\tTRUE\t/\tTRUE\t0\tsession\tabc\n\
+            example.com\tFALSE\t/\tFALSE\t0\tplain\txyz\n";
+        let jar = CookieJar::parse_netscape(content).unwrap();
+        assert_eq!(jar.len(), 2);
+        assert_eq!(jar.to_netscape(), content);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::error::M3u8Error;
+use crate::fetcher::{FetchContext, SegmentFetcher};
+use crate::source::SegmentSource;
+use crate::types::M3u8Segment;
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use bytes::Bytes;
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+type Aes128CbcDecryptor = cbc::Decryptor<aes::Aes128>;
+
+// AES-128 片段解密：按密钥地址缓存密钥，密钥请求使用 [key] 小节的请求头
+pub struct SegmentDecryptor {
+    headers: HeaderMap,
+    // 片段所在媒体播放列表的 EXT-X-MEDIA-SEQUENCE，未指定 IV 时据此推算
+    media_sequence: usize,
+    keys: Mutex<HashMap<String, [u8; 16]>>,
+}
+
+impl SegmentDecryptor {
+    pub fn new(headers: HeaderMap, media_sequence: usize) -> Self {
+        Self {
+            headers,
+            media_sequence,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 解密 METHOD=AES-128 的片段，未加密或其他加密方式（如 SAMPLE-AES）的片段原样返回
+    pub async fn decrypt(
+        &self,
+        segment: &M3u8Segment,
+        data: Bytes,
+        fetcher: &impl SegmentFetcher,
+    ) -> Result<Bytes, M3u8Error> {
+        let sequence_iv = ((self.media_sequence + segment.sequence) as u128).to_be_bytes();
+        let name = format!("片段 [{}]", segment.sequence);
+        self.decrypt_with(segment, data, Some(sequence_iv), &name, fetcher)
+            .await
+    }
+
+    // 解密 segment 引用的 EXT-X-MAP 初始化片段；按 RFC 8216 4.3.2.5，
+    // 由媒体序号推算 IV 只适用于媒体片段，加密的初始化片段必须显式指定 IV
+    pub async fn decrypt_init(
+        &self,
+        segment: &M3u8Segment,
+        data: Bytes,
+        fetcher: &impl SegmentFetcher,
+    ) -> Result<Bytes, M3u8Error> {
+        self.decrypt_with(segment, data, None, "初始化片段", fetcher)
+            .await
+    }
+
+    async fn decrypt_with(
+        &self,
+        segment: &M3u8Segment,
+        data: Bytes,
+        default_iv: Option<[u8; 16]>,
+        name: &str,
+        fetcher: &impl SegmentFetcher,
+    ) -> Result<Bytes, M3u8Error> {
+        let Some(key) = segment.key.as_ref().filter(|key| key.method == "AES-128") else {
+            return Ok(data);
+        };
+        let uri = key
+            .uri
+            .as_deref()
+            .ok_or_else(|| M3u8Error::DecryptError("AES-128 密钥缺少 URI".to_string()))?;
+        let iv = match (&key.iv, default_iv) {
+            (Some(iv), _) => parse_iv(iv)?,
+            (None, Some(iv)) => iv,
+            (None, None) => {
+                return Err(M3u8Error::DecryptError(format!(
+                    "{}加密时需要在 EXT-X-KEY 中指定 IV",
+                    name
+                )))
+            }
+        };
+        let key_bytes = self.key(uri, fetcher).await?;
+
+        Aes128CbcDecryptor::new(&key_bytes.into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&data)
+            .map(Bytes::from)
+            .map_err(|_| M3u8Error::DecryptError(format!("{} 解密失败", name)))
+    }
+
+    // 获取密钥，同一地址只请求一次
+    async fn key(&self, uri: &str, fetcher: &impl SegmentFetcher) -> Result<[u8; 16], M3u8Error> {
+        let mut keys = self.keys.lock().await;
+        if let Some(key) = keys.get(uri) {
+            return Ok(*key);
+        }
+
+        let data = match SegmentSource::parse(uri)? {
+            SegmentSource::File(path) => Bytes::from(tokio::fs::read(&path).await?),
+            SegmentSource::Data(data) => Bytes::from(data),
+            SegmentSource::Http(url) => {
+                fetcher
+                    .fetch_segment(&url, &self.headers, &mut FetchContext::default())
+                    .await?
+            }
+        };
+        let key: [u8; 16] = data.as_ref().try_into().map_err(|_| {
+            M3u8Error::DecryptError(format!(
+                "密钥长度应为 16 字节，实际 {} 字节: {}",
+                data.len(),
+                uri
+            ))
+        })?;
+
+        keys.insert(uri.to_string(), key);
+        Ok(key)
+    }
+}
+
+// 解析十六进制 IV，如 0x000102030405060708090a0b0c0d0e0f
+fn parse_iv(iv: &str) -> Result<[u8; 16], M3u8Error> {
+    let hex = iv
+        .strip_prefix("0x")
+        .or_else(|| iv.strip_prefix("0X"))
+        .unwrap_or(iv);
+    u128::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() <= 32)
+        .map(u128::to_be_bytes)
+        .ok_or_else(|| M3u8Error::DecryptError(format!("无效的 IV: {}", iv)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::ReqwestFetcher;
+    use crate::parser::MediaParser;
+    use aes::cipher::BlockEncryptMut;
+
+    #[test]
+    fn parses_hex_iv() {
+        let mut expected = [0u8; 16];
+        expected[15] = 0x2a;
+        assert_eq!(parse_iv("0x2A").unwrap(), expected);
+        assert_eq!(
+            parse_iv("0000000000000000000000000000002a").unwrap(),
+            expected
+        );
+        assert!(parse_iv("0xzz").is_err());
+        assert!(parse_iv(&format!("0x{}", "0".repeat(33))).is_err());
+    }
+
+    #[tokio::test]
+    async fn decrypts_with_media_sequence_iv() {
+        let key = [7u8; 16];
+        let key_path = std::env::temp_dir().join(format!("m3u8-key-{}.bin", std::process::id()));
+        std::fs::write(&key_path, key).unwrap();
+
+        let content = format!(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:2\n#EXT-X-KEY:METHOD=AES-128,URI=\"{}\"\n\
+             #EXTINF:2,\nhttp://example.com/0.ts\n#EXTINF:2,\nhttp://example.com/1.ts\n",
+            url::Url::from_file_path(&key_path).unwrap()
+        );
+        let playlist = MediaParser::new(Vec::new())
+            .unwrap()
+            .parse(&content, None)
+            .unwrap();
+        let segment = &playlist.segments[1];
+        let plain = b"0123456789abcdef-tail".to_vec();
+        let iv = 11u128.to_be_bytes();
+        let encrypted = cbc::Encryptor::<aes::Aes128>::new(&key.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(&plain);
+
+        let decryptor = SegmentDecryptor::new(HeaderMap::new(), 10);
+        let fetcher = ReqwestFetcher::new(None, None, 1).unwrap();
+        let decrypted = decryptor
+            .decrypt(segment, Bytes::from(encrypted), &fetcher)
+            .await
+            .unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+        assert_eq!(decrypted.as_ref(), plain.as_slice());
+    }
+
+    #[tokio::test]
+    async fn init_segment_requires_explicit_iv() {
+        let content = "#EXTM3U\n#EXT-X-TARGETDURATION:2\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"data:text/plain;base64,AAAAAAAAAAAAAAAAAAAAAA==\"\n\
+             #EXT-X-MAP:URI=\"http://example.com/init.mp4\"\n\
+             #EXTINF:2,\nhttp://example.com/0.m4s\n";
+        let playlist = MediaParser::new(Vec::new())
+            .unwrap()
+            .parse(content, None)
+            .unwrap();
+        let decryptor = SegmentDecryptor::new(HeaderMap::new(), 0);
+        let fetcher = ReqwestFetcher::new(None, None, 1).unwrap();
+        let result = decryptor
+            .decrypt_init(&playlist.segments[0], Bytes::from(vec![0u8; 16]), &fetcher)
+            .await;
+        assert!(matches!(result, Err(M3u8Error::DecryptError(_))));
+    }
+}
@@ -1,15 +1,26 @@
+use crate::cookie_jar::CookieJar;
+use crate::decrypt::SegmentDecryptor;
 use crate::error::M3u8Error;
-use crate::merger::VideoMerger;
+use crate::fetcher::{FetchContext, PlaylistFetcher, ReqwestFetcher, SegmentFetcher};
+use crate::headers::{HeaderProfile, RequestKind};
+use crate::inspect::{estimate_size, probe_segments_size};
+use crate::merger::ts::TrackLabel;
+use crate::merger::{MergeInput, MergeMode, OutputFormat, VideoMerger};
+use crate::parser::content_parser::QueryPropagation;
 use crate::parser::nested_parser::NestedParser;
+use crate::parser::{AdDetector, ParseMode};
 use crate::proxy::ProxyConfig;
-use crate::types::M3u8Segment;
+use crate::source::SegmentSource;
+use crate::time_range::TimeRange;
 use crate::types::NestedM3u8;
+use crate::types::{M3u8Map, M3u8Rendition, M3u8Segment};
+use bytes::Bytes;
 use indicatif::{ProgressBar, ProgressStyle};
 use md5::Digest;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, USER_AGENT};
-use std::path::PathBuf;
-use std::str::FromStr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use reqwest::header::{HeaderMap, HeaderValue, RANGE};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs;
@@ -26,8 +37,10 @@ fn generate_temp_dir_name(url: &str) -> String {
 }
 
 fn format_duration(segments: &[M3u8Segment]) -> String {
-    let total_seconds = segments.iter().map(|s| s.duration).sum::<f64>();
+    format_seconds(segments.iter().map(|s| s.duration).sum())
+}
 
+pub(crate) fn format_seconds(total_seconds: f64) -> String {
     if total_seconds < 60.0 {
         format!("00:00:{:02} s", total_seconds as u32)
     } else if total_seconds < 3600.0 {
@@ -42,7 +55,95 @@ fn format_duration(segments: &[M3u8Segment]) -> String {
     }
 }
 
-fn format_size(size: u64, suffix: Option<&str>) -> String {
+// 移除 EXT-X-GAP 标记的缺失片段，其后的片段视为不连续
+fn skip_gaps(segments: Vec<M3u8Segment>) -> Vec<M3u8Segment> {
+    let mut result: Vec<M3u8Segment> = Vec::with_capacity(segments.len());
+    let mut after_gap = false;
+    for mut segment in segments {
+        if segment.gap {
+            after_gap = !result.is_empty();
+            continue;
+        }
+        segment.discontinuity |= after_gap;
+        after_gap = false;
+        result.push(segment);
+    }
+    result
+}
+
+// 带字节范围 (偏移, 长度) 的请求附加 Range 请求头
+fn range_headers(headers: &HeaderMap, byte_range: Option<(usize, usize)>) -> HeaderMap {
+    let mut headers = headers.clone();
+    if let Some((offset, length)) = byte_range.filter(|(_, length)| *length > 0) {
+        if let Ok(value) =
+            HeaderValue::from_str(&format!("bytes={}-{}", offset, offset + length - 1))
+        {
+            headers.insert(RANGE, value);
+        }
+    }
+    headers
+}
+
+// 截取字节范围：服务器按 Range 返回时长度一致，返回完整内容（或读取本地文件）时在本地截取
+fn slice_byte_range(data: Bytes, byte_range: Option<(usize, usize)>) -> Result<Bytes, M3u8Error> {
+    match byte_range {
+        None => Ok(data),
+        Some((_, length)) if data.len() == length => Ok(data),
+        Some((offset, length)) if offset + length <= data.len() => {
+            Ok(data.slice(offset..offset + length))
+        }
+        Some((offset, length)) => Err(M3u8Error::DownloadError(format!(
+            "字节范围 {}@{} 超出内容长度 {}",
+            length,
+            offset,
+            data.len()
+        ))),
+    }
+}
+
+// 按初始化片段 (地址, 字节范围) 去重编号，返回各片段使用的序号及每个序号首次出现的片段
+fn number_init_segments(segments: &[M3u8Segment]) -> (Vec<Option<usize>>, Vec<&M3u8Segment>) {
+    let mut maps: Vec<&M3u8Map> = Vec::new();
+    let mut firsts = Vec::new();
+    let indices = segments
+        .iter()
+        .map(|segment| {
+            let map = segment.map.as_ref()?;
+            Some(match maps.iter().position(|known| *known == map) {
+                Some(index) => index,
+                None => {
+                    maps.push(map);
+                    firsts.push(segment);
+                    maps.len() - 1
+                }
+            })
+        })
+        .collect();
+    (indices, firsts)
+}
+
+// 查询路径所在文件系统的 (标识, 可用字节数)，用标识判断两个路径是否位于同一文件系统；
+// 无法查询时返回 None
+fn available_space(path: &Path) -> Option<(String, u64)> {
+    let available = fs4::available_space(path).ok()?;
+    #[cfg(unix)]
+    let filesystem = {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(path).ok()?.dev().to_string()
+    };
+    // 其他系统按盘符区分
+    #[cfg(not(unix))]
+    let filesystem = std::fs::canonicalize(path)
+        .ok()?
+        .components()
+        .next()?
+        .as_os_str()
+        .to_string_lossy()
+        .into_owned();
+    Some((filesystem, available))
+}
+
+pub(crate) fn format_size(size: u64, suffix: Option<&str>) -> String {
     let suffix = suffix.unwrap_or("");
     if size >= 1024 * 1024 * 1024 {
         format!(
@@ -59,54 +160,111 @@ fn format_size(size: u64, suffix: Option<&str>) -> String {
     }
 }
 
-fn create_client_pool(
-    proxy_config: &Option<ProxyConfig>,
-    headers: &HeaderMap,
-    pool_size: usize,
-) -> Result<Arc<Vec<reqwest::Client>>, M3u8Error> {
-    let mut clients = Vec::with_capacity(pool_size);
-
-    for _ in 0..pool_size {
-        let client_builder = reqwest::Client::builder()
-            .default_headers(headers.clone())
-            .timeout(Duration::from_secs(30))
-            .pool_max_idle_per_host(10)
-            .pool_idle_timeout(Duration::from_secs(30));
-
-        let client = if let Some(proxy_config) = proxy_config {
-            // 如果有代理配置，为每个客户端随机选择一个代理
-            if let Some(proxy_url) = proxy_config.get_random_proxy() {
-                let proxy = reqwest::Proxy::all(proxy_url)
-                    .map_err(|e| M3u8Error::ProxyError(e.to_string()))?;
-                client_builder.proxy(proxy).build()?
-            } else {
-                client_builder.build()?
-            }
-        } else {
-            client_builder.build()?
-        };
+// 签名地址刷新器：片段地址过期时重新获取媒体播放列表，按媒体序号换取新地址
+// 并发任务同时遇到过期时只刷新一次，刷新次数受预算限制
+struct UrlRefresher<F> {
+    parser: NestedParser,
+    playlist_url: Option<String>,
+    media_sequence: usize,
+    fetcher: Arc<F>,
+    budget: AtomicUsize,
+    generation: AtomicUsize,
+    // 最近一次刷新得到的 媒体序号 -> 片段地址
+    fresh_urls: tokio::sync::Mutex<HashMap<usize, String>>,
+}
+
+impl<F: PlaylistFetcher> UrlRefresher<F> {
+    fn new(
+        parser: NestedParser,
+        playlist_url: Option<String>,
+        media_sequence: usize,
+        fetcher: Arc<F>,
+        budget: usize,
+    ) -> Self {
+        Self {
+            parser,
+            playlist_url,
+            media_sequence,
+            fetcher,
+            budget: AtomicUsize::new(budget),
+            generation: AtomicUsize::new(0),
+            fresh_urls: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
 
-        clients.push(client);
+    fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
     }
 
-    Ok(Arc::new(clients))
+    // 获取片段的新地址；seen_generation 之后已有其他任务刷新过时直接复用结果
+    async fn refresh(&self, sequence: usize, seen_generation: usize) -> Option<(String, usize)> {
+        let playlist_url = self.playlist_url.as_ref()?;
+        let media_sequence = self.media_sequence + sequence;
+
+        let mut fresh_urls = self.fresh_urls.lock().await;
+        if self.generation() == seen_generation {
+            let remaining = self.budget.load(Ordering::Acquire);
+            if remaining == 0 {
+                return None;
+            }
+            self.budget.store(remaining - 1, Ordering::Release);
+
+            let playlist = match self
+                .parser
+                .parse_variant_playlist(playlist_url, self.fetcher.as_ref())
+                .await
+            {
+                Ok(playlist) => playlist,
+                Err(e) => {
+                    eprintln!("⚠️  刷新播放列表失败: {}", e);
+                    return None;
+                }
+            };
+
+            *fresh_urls = playlist
+                .segments
+                .iter()
+                .map(|s| (playlist.segment_media_sequence(s), s.url.clone()))
+                .collect();
+            self.generation.fetch_add(1, Ordering::AcqRel);
+        }
+
+        fresh_urls
+            .get(&media_sequence)
+            .map(|url| (url.clone(), self.generation()))
+    }
 }
 
-pub struct M3u8Downloader {
+pub struct M3u8Downloader<F = ReqwestFetcher> {
     url: String,
     output_path: PathBuf,
     temp_dir: PathBuf,
     keep_temp: bool,
     max_retries: usize,
     base_url: Option<String>,
-    headers: HeaderMap,
+    playlist_headers: HeaderMap,
+    segment_headers: HeaderMap,
+    key_headers: HeaderMap,
     ad_filters: Vec<String>,
-    simple: bool,
-    client_pool: Arc<Vec<reqwest::Client>>,
-    client_semaphore: Arc<Semaphore>,
+    ad_detector: Option<AdDetector>,
+    output_format: OutputFormat,
+    merge_mode: MergeMode,
+    refresh_budget: usize,
+    query_propagation: QueryPropagation,
+    time_range: TimeRange,
+    parse_mode: ParseMode,
+    // 下载前探测大小的抽样片段数量，0 表示不探测
+    probe_samples: usize,
+    // 是否在下载前检查磁盘空间
+    space_check: bool,
+    fetcher: Arc<F>,
+    // 同时进行的片段请求数量限制
+    semaphore: Arc<Semaphore>,
+    concurrent_limit: usize,
 }
 
-impl M3u8Downloader {
+impl M3u8Downloader<ReqwestFetcher> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         url: String,
         output_path: PathBuf,
@@ -115,35 +273,79 @@ impl M3u8Downloader {
         proxy_config: Option<ProxyConfig>,
         max_retries: usize,
         base_url: Option<String>,
-        custom_headers: Vec<String>,
+        header_profile: HeaderProfile,
         ad_filters: Vec<String>,
-        simple: bool,
+        ad_detector: Option<AdDetector>,
+        output_format: OutputFormat,
+        merge_mode: MergeMode,
+        cookie_jar: Option<CookieJar>,
+        refresh_budget: usize,
+        query_propagation: QueryPropagation,
+        time_range: TimeRange,
+        parse_mode: ParseMode,
+        probe_samples: usize,
+        space_check: bool,
     ) -> Result<Self, M3u8Error> {
-        // 创建默认请求头
-        let mut headers = HeaderMap::new();
-        headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36"));
-        headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
-        headers.insert(
-            ACCEPT_ENCODING,
-            HeaderValue::from_static("gzip, deflate, br"),
-        );
+        // 创建默认的 reqwest 请求实现
+        let fetcher = ReqwestFetcher::new(proxy_config, cookie_jar, concurrent_limit + 1)?;
 
-        // 解析并添加自定义请求头
-        for header_str in custom_headers {
-            if let Some((key, value)) = header_str.split_once(':') {
-                let key = key.trim();
-                let value = value.trim();
-                if let (Ok(header_name), Ok(header_value)) =
-                    (HeaderName::from_str(key), HeaderValue::from_str(value))
-                {
-                    headers.insert(header_name, header_value);
-                }
-            }
-        }
+        Self::with_custom_fetcher(
+            fetcher,
+            url,
+            output_path,
+            concurrent_limit,
+            keep_temp,
+            max_retries,
+            base_url,
+            header_profile,
+            ad_filters,
+            ad_detector,
+            output_format,
+            merge_mode,
+            refresh_budget,
+            query_propagation,
+            time_range,
+            parse_mode,
+            probe_samples,
+            space_check,
+        )
+    }
+}
+
+impl<F: SegmentFetcher + PlaylistFetcher + 'static> M3u8Downloader<F> {
+    // 使用自定义的网络请求实现创建下载器，代理与 Cookie 由该实现自行处理
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_custom_fetcher(
+        fetcher: F,
+        url: String,
+        output_path: PathBuf,
+        concurrent_limit: usize,
+        keep_temp: bool,
+        max_retries: usize,
+        base_url: Option<String>,
+        header_profile: HeaderProfile,
+        ad_filters: Vec<String>,
+        ad_detector: Option<AdDetector>,
+        output_format: OutputFormat,
+        merge_mode: MergeMode,
+        refresh_budget: usize,
+        query_propagation: QueryPropagation,
+        time_range: TimeRange,
+        parse_mode: ParseMode,
+        probe_samples: usize,
+        space_check: bool,
+    ) -> Result<Self, M3u8Error> {
+        // 按请求类型生成自定义请求头，本地文件以 base_url 作为占位符来源
+        let template_url = if url.starts_with("http") {
+            url.as_str()
+        } else {
+            base_url.as_deref().unwrap_or(&url)
+        };
+        let playlist_headers = header_profile.render(RequestKind::Playlist, template_url)?;
+        let segment_headers = header_profile.render(RequestKind::Segment, template_url)?;
+        let key_headers = header_profile.render(RequestKind::Key, template_url)?;
 
-        // 创建客户端池
-        let client_pool = create_client_pool(&proxy_config, &headers, concurrent_limit + 1)?;
-        let client_semaphore = Arc::new(Semaphore::new(concurrent_limit + 1));
+        let semaphore = Arc::new(Semaphore::new(concurrent_limit + 1));
 
         // 生成临时目录
         let temp_dir = PathBuf::from(generate_temp_dir_name(&url));
@@ -155,41 +357,155 @@ impl M3u8Downloader {
             keep_temp,
             max_retries,
             base_url,
-            headers,
+            playlist_headers,
+            segment_headers,
+            key_headers,
             ad_filters,
-            simple,
-            client_pool,
-            client_semaphore,
+            ad_detector,
+            output_format,
+            merge_mode,
+            refresh_budget,
+            query_propagation,
+            time_range,
+            parse_mode,
+            probe_samples,
+            space_check,
+            fetcher: Arc::new(fetcher),
+            semaphore,
+            concurrent_limit,
         })
     }
 
+    // 替换网络请求实现，如自定义缓存层、认证传输或测试用的模拟实现；
+    // 原有实现会被丢弃，新建下载器时应直接使用 with_custom_fetcher
+    pub fn with_fetcher<G: SegmentFetcher + PlaylistFetcher + 'static>(
+        self,
+        fetcher: G,
+    ) -> M3u8Downloader<G> {
+        M3u8Downloader {
+            url: self.url,
+            output_path: self.output_path,
+            temp_dir: self.temp_dir,
+            keep_temp: self.keep_temp,
+            max_retries: self.max_retries,
+            base_url: self.base_url,
+            playlist_headers: self.playlist_headers,
+            segment_headers: self.segment_headers,
+            key_headers: self.key_headers,
+            ad_filters: self.ad_filters,
+            ad_detector: self.ad_detector,
+            output_format: self.output_format,
+            merge_mode: self.merge_mode,
+            refresh_budget: self.refresh_budget,
+            query_propagation: self.query_propagation,
+            time_range: self.time_range,
+            parse_mode: self.parse_mode,
+            probe_samples: self.probe_samples,
+            space_check: self.space_check,
+            fetcher: Arc::new(fetcher),
+            semaphore: self.semaphore,
+            concurrent_limit: self.concurrent_limit,
+        }
+    }
+
+    pub fn fetcher(&self) -> &F {
+        &self.fetcher
+    }
+
+    // 获取指定类型请求使用的自定义请求头
+    pub fn request_headers(&self, kind: RequestKind) -> &HeaderMap {
+        match kind {
+            RequestKind::Playlist => &self.playlist_headers,
+            RequestKind::Segment => &self.segment_headers,
+            RequestKind::Key => &self.key_headers,
+        }
+    }
+
     pub async fn download(&self) -> Result<(), M3u8Error> {
+        let result = self.download_and_merge().await;
+        // 无论下载是否成功都要收尾，失败时也写回已更新的 Cookie
+        let finished = self.fetcher.on_finish().await;
+        result.and(finished)
+    }
+
+    async fn download_and_merge(&self) -> Result<(), M3u8Error> {
         // 创建临时目录
         fs::create_dir_all(&self.temp_dir).await?;
 
-        let parser = NestedParser::new(self.ad_filters.clone())?;
-        let nested = if self.url.starts_with("http") {
-            parser
-                .parse_from_url(&self.url, &self.client_pool[0])
-                .await?
-        } else {
-            parser
-                .parse_from_file(&self.url, self.base_url.as_deref(), &self.client_pool[0])
-                .await?
-        };
+        let parser = self.build_parser()?;
+        let nested = parser
+            .parse_location(&self.url, self.base_url.as_deref(), self.fetcher.as_ref())
+            .await?;
 
         self.display_playlist_info(&nested);
-        let segments = nested
+        let playlist = nested
             .get_selected_variant()
-            .map(|playlist| &playlist.segments)
             .ok_or_else(|| M3u8Error::EmptyError("无效的视频列表".to_string()))?;
-        self.download_segments(segments).await?;
-        let merger = VideoMerger::new(&self.temp_dir, &self.output_path, segments.len()).await?;
-        if self.simple {
-            merger.merge_with_rust().await?;
-        } else {
-            merger.merge_with_ffmpeg().await?;
+        let time_range = self.time_range.resolve(&playlist.segments)?;
+        let selected = time_range.select(&playlist.segments)?;
+        let gaps = selected.iter().filter(|s| s.gap).count();
+        let segments = &skip_gaps(selected);
+        if gaps > 0 {
+            println!("⏭️  跳过 {} 个 EXT-X-GAP 缺失片段", gaps);
+        }
+        if segments.is_empty() {
+            return Err(M3u8Error::EmptyError("未找到有效的视频片段".to_string()));
         }
+        let mut clip = None;
+        if !time_range.is_empty() {
+            let window = time_range.clip_window(&playlist.segments, segments)?;
+            println!(
+                "⏱️  时间范围 {}: 选中 {} 个片段, {}, 裁剪 {}\n",
+                time_range,
+                segments.len(),
+                format_duration(segments),
+                window
+            );
+            clip = Some(window);
+        }
+
+        let bandwidth = nested
+            .selected_variant_index
+            .and_then(|index| nested.master_playlist.variants.get(index))
+            .and_then(|variant| variant.bandwidth);
+        self.check_disk_space(segments, bandwidth).await?;
+
+        // 媒体播放列表地址，用于签名地址过期后刷新
+        let playlist_url = match nested.selected_variant_index {
+            Some(index) if !nested.master_playlist.variants.is_empty() => nested
+                .master_playlist
+                .variants
+                .get(index)
+                .map(|variant| variant.url.clone()),
+            _ => Some(self.url.clone()).filter(|url| url.starts_with("http")),
+        };
+        let refresher = Arc::new(UrlRefresher::new(
+            parser,
+            playlist_url,
+            playlist.media_sequence,
+            Arc::clone(&self.fetcher),
+            self.refresh_budget,
+        ));
+        let decryptor = Arc::new(SegmentDecryptor::new(
+            self.key_headers.clone(),
+            playlist.media_sequence,
+        ));
+        self.fetcher.on_start();
+        let init_segments = self
+            .download_init_segments(segments, "", &decryptor)
+            .await?;
+        self.download_segments(segments, "", &refresher, &decryptor)
+            .await?;
+        let renditions = self.download_renditions(&nested, &time_range).await?;
+        let sequences: Vec<usize> = segments.iter().map(|s| s.sequence).collect();
+        let merger = VideoMerger::new(&self.temp_dir, &self.output_path, &sequences)
+            .await?
+            .with_segment_durations(segments.iter().map(|s| s.duration).collect())
+            .with_discontinuities(segments.iter().map(|s| s.discontinuity).collect())
+            .with_init_segments(init_segments)
+            .with_renditions(renditions)
+            .with_clip(clip);
+        merger.merge(self.output_format, self.merge_mode).await?;
         if !self.keep_temp {
             fs::remove_dir_all(&self.temp_dir).await?;
         }
@@ -197,6 +513,252 @@ impl M3u8Downloader {
         Ok(())
     }
 
+    fn build_parser(&self) -> Result<NestedParser, M3u8Error> {
+        Ok(NestedParser::new(self.ad_filters.clone())?
+            .with_headers(self.playlist_headers.clone())
+            .with_ad_detector(self.ad_detector.clone())
+            .with_mode(self.parse_mode)
+            .with_query_propagation(self.query_propagation.clone()))
+    }
+
+    // 所选变体流引用的备选媒体：AUDIO/VIDEO 组中带 URI 的 EXT-X-MEDIA
+    fn selected_renditions<'a>(&self, nested: &'a NestedM3u8) -> Vec<&'a M3u8Rendition> {
+        let Some(variant) = nested
+            .selected_variant_index
+            .and_then(|index| nested.master_playlist.variants.get(index))
+        else {
+            return Vec::new();
+        };
+        nested
+            .master_playlist
+            .renditions
+            .iter()
+            .filter(|rendition| {
+                let group = match rendition.media_type.as_str() {
+                    "AUDIO" => variant.audio.as_ref(),
+                    "VIDEO" => variant.video.as_ref(),
+                    _ => None,
+                };
+                group == Some(&rendition.group_id)
+                    && rendition
+                        .uri
+                        .as_ref()
+                        .is_some_and(|uri| *uri != variant.url)
+            })
+            .collect()
+    }
+
+    // 下载备选媒体的片段，封装时作为额外的轨道；直接拼接的输出无法容纳多路输入，不下载
+    async fn download_renditions(
+        &self,
+        nested: &NestedM3u8,
+        time_range: &TimeRange,
+    ) -> Result<Vec<MergeInput>, M3u8Error> {
+        let renditions = self.selected_renditions(nested);
+        if renditions.is_empty() {
+            return Ok(Vec::new());
+        }
+        if matches!(self.output_format, OutputFormat::Ts | OutputFormat::HlsDir) {
+            println!(
+                "⚠️  {} 输出不重新封装，忽略 {} 个备选媒体",
+                self.output_format,
+                renditions.len()
+            );
+            return Ok(Vec::new());
+        }
+
+        let mut inputs = Vec::new();
+        for (index, rendition) in renditions.into_iter().enumerate() {
+            let Some(uri) = rendition.uri.as_ref() else {
+                continue;
+            };
+            let parser = self.build_parser()?;
+            let playlist = match parser
+                .parse_variant_playlist(uri, self.fetcher.as_ref())
+                .await
+            {
+                Ok(playlist) => playlist,
+                Err(e) => {
+                    eprintln!("⚠️  备选媒体 {} 不可用，已忽略: {}", rendition.name, e);
+                    continue;
+                }
+            };
+            let segments = skip_gaps(time_range.select(&playlist.segments)?);
+            if segments.is_empty() {
+                continue;
+            }
+            println!(
+                "🎧 备选媒体 {} ({}, {}): {} 个片段",
+                rendition.name,
+                rendition.media_type,
+                rendition.language.as_deref().unwrap_or("und"),
+                segments.len()
+            );
+
+            let prefix = format!("r{}_", index);
+            let refresher = Arc::new(UrlRefresher::new(
+                parser,
+                Some(uri.clone()).filter(|uri| uri.starts_with("http")),
+                playlist.media_sequence,
+                Arc::clone(&self.fetcher),
+                self.refresh_budget,
+            ));
+            let decryptor = Arc::new(SegmentDecryptor::new(
+                self.key_headers.clone(),
+                playlist.media_sequence,
+            ));
+            let init_segments = self
+                .download_init_segments(&segments, &prefix, &decryptor)
+                .await?;
+            self.download_segments(&segments, &prefix, &refresher, &decryptor)
+                .await?;
+
+            inputs.push(MergeInput {
+                prefix,
+                sequences: segments.iter().map(|s| s.sequence).collect(),
+                segment_durations: segments.iter().map(|s| s.duration).collect(),
+                discontinuities: segments.iter().map(|s| s.discontinuity).collect(),
+                init_segments,
+                label: Some(TrackLabel {
+                    name: rendition.name.clone(),
+                    language: rendition.language.clone(),
+                    default: rendition.default,
+                }),
+            });
+        }
+        Ok(inputs)
+    }
+
+    // 下载 fMP4 片段的初始化片段（EXT-X-MAP）到 {前缀}init{序号}.mp4，返回各片段使用的序号
+    // 加密时使用第一个引用它的片段的密钥及显式指定的 IV 解密
+    async fn download_init_segments(
+        &self,
+        segments: &[M3u8Segment],
+        prefix: &str,
+        decryptor: &SegmentDecryptor,
+    ) -> Result<Vec<Option<usize>>, M3u8Error> {
+        let (indices, firsts) = number_init_segments(segments);
+        for (index, segment) in firsts.into_iter().enumerate() {
+            let file_path = self
+                .temp_dir
+                .join(format!("{}init{:04}.mp4", prefix, index));
+            if file_path.exists() {
+                continue;
+            }
+            let Some(map) = segment.map.as_ref() else {
+                continue;
+            };
+
+            let mut context = FetchContext::default();
+            let mut retry_count = 0;
+            let data = loop {
+                match Self::fetch_source(
+                    &map.uri,
+                    map.byte_range,
+                    self.fetcher.as_ref(),
+                    &self.segment_headers,
+                    &mut context,
+                )
+                .await
+                {
+                    Ok(data) => break data,
+                    Err(e) => {
+                        retry_count += 1;
+                        if retry_count >= self.max_retries {
+                            eprintln!("❌ 初始化片段 {}", e);
+                            return Err(e);
+                        }
+                        tokio::time::sleep(Duration::from_millis(1000 * retry_count as u64)).await;
+                    }
+                }
+            };
+            let data = decryptor
+                .decrypt_init(segment, data, self.fetcher.as_ref())
+                .await?;
+            Self::write_segment(&file_path, &data).await?;
+        }
+        Ok(indices)
+    }
+
+    // 下载前检查临时目录与输出位置的可用空间，两者位于同一文件系统时合并期间需要两倍空间。
+    // 探测或字节范围得到的估算不足时报错；按 BANDWIDTH（峰值码率）估算的结果偏大，不足时只提示。
+    // 关闭检查时仍输出探测得到的估算
+    async fn check_disk_space(
+        &self,
+        segments: &[M3u8Segment],
+        bandwidth: Option<u32>,
+    ) -> Result<(), M3u8Error> {
+        let probed = if self.probe_samples > 0 {
+            probe_segments_size(
+                segments,
+                self.probe_samples,
+                self.concurrent_limit,
+                self.fetcher.as_ref(),
+                &self.segment_headers,
+            )
+            .await
+        } else {
+            None
+        };
+        let (size, reliable) = match probed {
+            Some((size, samples)) => {
+                println!(
+                    "📦 探测 {} 个片段，预计下载 {}",
+                    samples,
+                    format_size(size, Some("~"))
+                );
+                (size, true)
+            }
+            None => match estimate_size(segments, bandwidth) {
+                Some(size) => (size, segments.iter().all(|s| s.byte_range.is_some())),
+                None => return Ok(()),
+            },
+        };
+        if !self.space_check {
+            return Ok(());
+        }
+
+        let output_dir = self
+            .output_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        // (文件系统标识, 目录, 可用空间, 需要空间)
+        let mut filesystems: Vec<(String, &Path, u64, u64)> = Vec::new();
+        for dir in [self.temp_dir.as_path(), output_dir] {
+            let Some((filesystem, available)) = available_space(dir) else {
+                continue;
+            };
+            match filesystems.iter_mut().find(|(f, _, _, _)| *f == filesystem) {
+                Some(filesystem) => filesystem.3 += size,
+                None => filesystems.push((filesystem, dir, available, size)),
+            }
+        }
+
+        for (_, dir, available, required) in filesystems {
+            if available >= required {
+                continue;
+            }
+            let message = format!(
+                "{} 所在磁盘可用 {}，预计需要 {}",
+                dir.display(),
+                format_size(available, None),
+                format_size(required, None)
+            );
+            if reliable {
+                return Err(M3u8Error::DownloadError(format!(
+                    "磁盘空间不足: {}，可使用 --no-space-check 跳过检查",
+                    message
+                )));
+            }
+            println!(
+                "⚠️  磁盘空间可能不足: {}（按 BANDWIDTH 估算，可能偏大）",
+                message
+            );
+        }
+        Ok(())
+    }
+
     fn display_playlist_info(&self, nested: &NestedM3u8) {
         if let Some(selected_playlist) = nested.get_selected_variant() {
             let mut info_parts = Vec::new();
@@ -210,15 +772,38 @@ impl M3u8Downloader {
                 info_parts.push(format!("🚫 广告 {} 个", selected_playlist.ads_count));
             }
 
+            let markers = selected_playlist.date_ranges.len() + selected_playlist.cue_events.len();
+            if markers > 0 {
+                info_parts.push(format!("🏷️  DATERANGE/CUE 标记 {} 个", markers));
+            }
+
             if selected_playlist.is_live {
                 info_parts.push("📡 直播流".to_string());
             }
 
-            println!("{}\n", info_parts.join(", "));
+            println!("{}", info_parts.join(", "));
+            for ad_break in &selected_playlist.ad_breaks {
+                println!("  🚫 移除广告 {}", ad_break);
+            }
+            for warning in nested
+                .master_playlist
+                .warnings
+                .iter()
+                .chain(&selected_playlist.warnings)
+            {
+                println!("  ⚠️  {}", warning);
+            }
+            println!();
         }
     }
 
-    async fn download_segments(&self, segments: &[M3u8Segment]) -> Result<(), M3u8Error> {
+    async fn download_segments(
+        &self,
+        segments: &[M3u8Segment],
+        prefix: &str,
+        refresher: &Arc<UrlRefresher<F>>,
+        decryptor: &Arc<SegmentDecryptor>,
+    ) -> Result<(), M3u8Error> {
         let total_bytes = Arc::new(AtomicU64::new(0));
         let last_update = Arc::new(AtomicU64::new(0));
         let progress_bar = ProgressBar::new(segments.len() as u64);
@@ -255,10 +840,13 @@ impl M3u8Downloader {
         let mut join_set = JoinSet::new();
 
         let temp_dir = self.temp_dir.clone();
+        let prefix = prefix.to_string();
         let max_retries = self.max_retries;
-        let client_pool = Arc::clone(&self.client_pool);
-        let client_semaphore = Arc::clone(&self.client_semaphore);
-        let headers = self.headers.clone();
+        let fetcher = Arc::clone(&self.fetcher);
+        let semaphore = Arc::clone(&self.semaphore);
+        let headers = self.segment_headers.clone();
+        let refresher = Arc::clone(refresher);
+        let decryptor = Arc::clone(decryptor);
 
         // 为每个分片创建下载任务
         for segment in segments.iter() {
@@ -266,19 +854,25 @@ impl M3u8Downloader {
             let progress_bar_task = Arc::clone(&segment_bar);
             let segment_clone = segment.clone();
             let temp_dir_clone = temp_dir.clone();
+            let prefix_clone = prefix.clone();
             let headers_clone = headers.clone();
-            let client_pool_clone = Arc::clone(&client_pool);
-            let client_semaphore_clone = Arc::clone(&client_semaphore);
+            let fetcher_clone = Arc::clone(&fetcher);
+            let semaphore_clone = Arc::clone(&semaphore);
+            let refresher_clone = Arc::clone(&refresher);
+            let decryptor_clone = Arc::clone(&decryptor);
 
             join_set.spawn(async move {
                 let result = Self::download_single_segment(
                     &segment_clone,
                     &total_bytes_task,
                     &temp_dir_clone,
+                    &prefix_clone,
                     max_retries,
-                    &client_pool_clone,
-                    &client_semaphore_clone,
+                    &fetcher_clone,
+                    &semaphore_clone,
                     &headers_clone,
+                    &refresher_clone,
+                    &decryptor_clone,
                 )
                 .await;
 
@@ -317,35 +911,86 @@ impl M3u8Downloader {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn download_single_segment(
         segment: &M3u8Segment,
         total_bytes: &Arc<AtomicU64>,
-        temp_dir: &PathBuf,
+        temp_dir: &Path,
+        prefix: &str,
         max_retries: usize,
-        client_pool: &Arc<Vec<reqwest::Client>>,
-        client_semaphore: &Arc<Semaphore>,
+        fetcher: &Arc<F>,
+        semaphore: &Arc<Semaphore>,
         headers: &HeaderMap,
+        refresher: &Arc<UrlRefresher<F>>,
+        decryptor: &SegmentDecryptor,
     ) -> Result<(), M3u8Error> {
-        let file_name = format!("seg{:06}.ts", segment.sequence);
+        let file_name = format!("{}seg{:06}.ts", prefix, segment.sequence);
         let file_path = temp_dir.join(&file_name);
         if file_path.exists() {
             return Ok(());
         }
 
+        // 主地址在前，备份流地址在后，失败时轮换
+        let mut sources: Vec<String> = std::iter::once(segment.url.clone())
+            .chain(segment.backup_urls.iter().cloned())
+            .collect();
+
+        // 每轮依次尝试主地址和全部备份流，整轮失败才消耗一次重试
         let mut retry_count = 0;
+        let mut source_index = 0;
+        let mut context = FetchContext::default();
+        let mut generation = refresher.generation();
         while retry_count < max_retries {
-            match Self::try_download_segment(
-                &segment.url,
-                &file_path,
-                total_bytes,
-                client_pool,
-                client_semaphore,
+            let url = &sources[source_index];
+
+            // 获取信号量许可
+            let permit = semaphore
+                .acquire()
+                .await
+                .map_err(|e| M3u8Error::DownloadError(format!("无法获取信号量: {}", e)))?;
+            let result = Self::fetch_source(
+                url,
+                segment.byte_range,
+                fetcher.as_ref(),
                 headers,
+                &mut context,
             )
-            .await
-            {
+            .await;
+            let result = match result {
+                Ok(data) => {
+                    total_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+                    match decryptor.decrypt(segment, data, fetcher.as_ref()).await {
+                        Ok(data) => Self::write_segment(&file_path, &data).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e),
+            };
+            drop(permit);
+
+            match result {
                 Ok(_) => return Ok(()),
                 Err(e) => {
+                    // 主地址签名过期时刷新播放列表换取新地址，不消耗重试次数
+                    if source_index == 0 && e.is_url_expired() {
+                        if let Some((fresh_url, fresh_generation)) =
+                            refresher.refresh(segment.sequence, generation).await
+                        {
+                            generation = fresh_generation;
+                            if fresh_url != sources[0] {
+                                sources[0] = fresh_url;
+                                continue;
+                            }
+                        }
+                    }
+
+                    // 本轮尚未尝试过的备份流直接切换，无需等待
+                    source_index += 1;
+                    if source_index < sources.len() {
+                        continue;
+                    }
+                    source_index = 0;
+
                     retry_count += 1;
                     if retry_count >= max_retries {
                         eprintln!("❌ 片段 [{}] {}", segment.sequence, e);
@@ -362,38 +1007,174 @@ impl M3u8Downloader {
         Ok(())
     }
 
-    async fn try_download_segment(
+    // 读取片段或初始化片段，有字节范围时只取其中的部分
+    async fn fetch_source(
         url: &str,
-        file_path: &PathBuf,
-        total_bytes: &Arc<AtomicU64>,
-        client_pool: &Arc<Vec<reqwest::Client>>,
-        client_semaphore: &Arc<Semaphore>,
+        byte_range: Option<(usize, usize)>,
+        fetcher: &F,
         headers: &HeaderMap,
-    ) -> Result<(), M3u8Error> {
-        // 获取客户端信号量许可
-        let _permit = client_semaphore
-            .acquire()
+        context: &mut FetchContext,
+    ) -> Result<Bytes, M3u8Error> {
+        let data = match SegmentSource::parse(url)? {
+            // 本地文件片段直接读取，无需经过网络请求
+            SegmentSource::File(path) => Self::read_local_segment(&path).await?,
+            SegmentSource::Data(data) => Bytes::from(data),
+            SegmentSource::Http(_) => {
+                let headers = range_headers(headers, byte_range);
+                fetcher.fetch_segment(url, &headers, context).await?
+            }
+        };
+        slice_byte_range(data, byte_range)
+    }
+
+    async fn read_local_segment(source_path: &Path) -> Result<Bytes, M3u8Error> {
+        fs::read(source_path)
             .await
-            .map_err(|e| M3u8Error::DownloadError(format!("无法获取信号量: {}", e)))?;
+            .map(Bytes::from)
+            .map_err(|e| M3u8Error::DownloadError(format!("{} {:?}", e, source_path)))
+    }
+
+    // 片段内容完整后才写入，避免重新运行时把不完整的文件当作已下载
+    async fn write_segment(file_path: &Path, data: &[u8]) -> Result<(), M3u8Error> {
+        let mut file = fs::File::create(file_path).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
 
-        // 从客户端池中随机选择一个客户端
-        let client_index = rand::random::<usize>() % client_pool.len();
-        let client = &client_pool[client_index];
-        let response = client.get(url).headers(headers.clone()).send().await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::MediaParser;
+    use url::Url;
+
+    // 远超任何磁盘可用空间的大小
+    const HUGE: u64 = 1 << 60;
+
+    // 只支持探测片段大小的请求实现，probed 为 None 时探测失败
+    struct Probe {
+        probed: Option<u64>,
+    }
 
-        if !response.status().is_success() {
-            return Err(M3u8Error::DownloadError(format!(
-                "{} \"{}\"",
-                response.status(),
+    impl PlaylistFetcher for Probe {
+        async fn fetch_playlist(&self, url: &str, _: &HeaderMap) -> Result<String, M3u8Error> {
+            Err(M3u8Error::DownloadError(format!(
+                "不应获取播放列表: {}",
                 url
-            )));
+            )))
         }
+    }
 
-        let bytes = response.bytes().await?;
-        total_bytes.fetch_add(bytes.len() as u64, Ordering::Relaxed);
-        let mut file = fs::File::create(file_path).await?;
-        file.write_all(&bytes).await?;
-        file.flush().await?;
-        return Ok(());
+    impl SegmentFetcher for Probe {
+        async fn fetch_segment(
+            &self,
+            url: &str,
+            _: &HeaderMap,
+            _: &mut FetchContext,
+        ) -> Result<Bytes, M3u8Error> {
+            Err(M3u8Error::DownloadError(format!("不应下载片段: {}", url)))
+        }
+
+        async fn probe_size(&self, _: &str, _: &HeaderMap) -> Result<Option<u64>, M3u8Error> {
+            Ok(self.probed)
+        }
+    }
+
+    fn downloader(probed: Option<u64>, probe_samples: usize) -> M3u8Downloader<Probe> {
+        M3u8Downloader::with_custom_fetcher(
+            Probe { probed },
+            "https://example.com/index.m3u8".to_string(),
+            std::env::temp_dir().join("output.mp4"),
+            4,
+            false,
+            0,
+            None,
+            HeaderProfile::new(),
+            Vec::new(),
+            None,
+            OutputFormat::Mp4,
+            MergeMode::Native,
+            0,
+            QueryPropagation::default(),
+            TimeRange::default(),
+            ParseMode::Lenient,
+            probe_samples,
+            true,
+        )
+        .unwrap()
+    }
+
+    fn segments(content: &str) -> Vec<M3u8Segment> {
+        let base_url = Url::parse("https://example.com/index.m3u8").unwrap();
+        MediaParser::new(Vec::new())
+            .unwrap()
+            .parse(content, Some(&base_url))
+            .unwrap()
+            .segments
+    }
+
+    fn plain() -> Vec<M3u8Segment> {
+        segments("#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:10,\na.ts\n#EXT-X-ENDLIST\n")
+    }
+
+    fn byte_range(length: u64) -> Vec<M3u8Segment> {
+        segments(&format!(
+            "#EXTM3U\n#EXT-X-VERSION:4\n#EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\n#EXT-X-BYTERANGE:{}@0\nall.ts\n#EXT-X-ENDLIST\n",
+            length
+        ))
+    }
+
+    #[tokio::test]
+    async fn probed_size_larger_than_disk_is_an_error() {
+        let result = downloader(Some(HUGE), 1)
+            .check_disk_space(&plain(), None)
+            .await;
+        assert!(matches!(result, Err(M3u8Error::DownloadError(_))));
+        assert!(downloader(Some(1000), 1)
+            .check_disk_space(&plain(), None)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn failed_probe_falls_back_to_byte_ranges() {
+        let result = downloader(None, 1)
+            .check_disk_space(&byte_range(HUGE), None)
+            .await;
+        assert!(matches!(result, Err(M3u8Error::DownloadError(_))));
+        assert!(downloader(None, 1)
+            .check_disk_space(&byte_range(1000), None)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn bandwidth_estimate_only_warns() {
+        // 一百万秒 × u32::MAX bps 约 500TB，超出磁盘空间；按 BANDWIDTH 估算时只提示
+        let segments = segments(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:1000000\n#EXTINF:1000000,\na.ts\n#EXT-X-ENDLIST\n",
+        );
+        assert!(estimate_size(&segments, Some(u32::MAX)).unwrap() > 1 << 48);
+        assert!(downloader(None, 1)
+            .check_disk_space(&segments, Some(u32::MAX))
+            .await
+            .is_ok());
+        // 无法估算时跳过检查
+        assert!(downloader(None, 0)
+            .check_disk_space(&plain(), None)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn disabled_space_check_still_probes() {
+        let mut downloader = downloader(Some(HUGE), 1);
+        downloader.space_check = false;
+        assert!(downloader
+            .check_disk_space(&byte_range(HUGE), None)
+            .await
+            .is_ok());
     }
 }
@@ -1,3 +1,5 @@
+use crate::parser::ParseWarning;
+use reqwest::StatusCode;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -20,6 +22,9 @@ pub enum M3u8Error {
     #[error("下载错误: {0}")]
     DownloadError(String),
 
+    #[error("{0} \"{1}\"")]
+    HttpStatusError(StatusCode, String),
+
     #[error("文件不存在: {0}")]
     FileNotFoundError(std::path::PathBuf),
 
@@ -28,4 +33,30 @@ pub enum M3u8Error {
 
     #[error("M3U8 解析错误: {0}")]
     ParseError(String),
+
+    // 严格模式下发现的违反规范之处，保留行号供调用方定位
+    #[error("M3U8 解析错误: {0}")]
+    InvalidPlaylist(ParseWarning),
+
+    #[error("请求头错误: {0}")]
+    HeaderError(String),
+
+    #[error("合并错误: {0}")]
+    MergeError(String),
+
+    #[error("解密错误: {0}")]
+    DecryptError(String),
+}
+
+impl M3u8Error {
+    // 是否为签名地址过期类错误（401/403/410），刷新播放列表获取新地址后可能恢复
+    pub fn is_url_expired(&self) -> bool {
+        matches!(
+            self,
+            M3u8Error::HttpStatusError(status, _)
+                if *status == StatusCode::UNAUTHORIZED
+                    || *status == StatusCode::FORBIDDEN
+                    || *status == StatusCode::GONE
+        )
+    }
 }
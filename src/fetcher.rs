@@ -0,0 +1,319 @@
+use crate::cookie_jar::CookieJar;
+use crate::error::M3u8Error;
+use crate::proxy::{mask_proxy_url, ProxyConfig, ProxyStrategy};
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, RANGE,
+    USER_AGENT,
+};
+use reqwest::Method;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+// 单个片段在多次重试之间保留的请求上下文
+#[derive(Debug, Clone, Default)]
+pub struct FetchContext {
+    // 上次请求失败时使用的线路（如代理序号），实现可据此换用其他线路
+    pub failed_route: Option<usize>,
+}
+
+// 播放列表获取接口
+pub trait PlaylistFetcher: Send + Sync {
+    fn fetch_playlist(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+    ) -> impl Future<Output = Result<String, M3u8Error>> + Send;
+}
+
+// 片段获取接口，下载器通过它完成所有片段请求
+pub trait SegmentFetcher: Send + Sync {
+    fn fetch_segment(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        context: &mut FetchContext,
+    ) -> impl Future<Output = Result<Bytes, M3u8Error>> + Send;
+
+    // 获取片段大小而不下载内容，用于估算下载总量；无法获取时返回 None
+    fn probe_size(
+        &self,
+        _url: &str,
+        _headers: &HeaderMap,
+    ) -> impl Future<Output = Result<Option<u64>, M3u8Error>> + Send {
+        async { Ok(None) }
+    }
+
+    // 开始下载片段前调用，可用于启动后台任务
+    fn on_start(&self) {}
+
+    // 下载结束后调用（无论成功与否），可用于输出统计或持久化状态
+    fn on_finish(&self) -> impl Future<Output = Result<(), M3u8Error>> + Send {
+        async { Ok(()) }
+    }
+}
+
+// reuse_connections 为 false 时不保留空闲连接，每次请求都建立新连接
+fn build_client(
+    headers: &HeaderMap,
+    cookie_jar: &Arc<CookieJar>,
+    proxy_url: Option<&str>,
+    reuse_connections: bool,
+) -> Result<reqwest::Client, M3u8Error> {
+    let client_builder = reqwest::Client::builder()
+        .default_headers(headers.clone())
+        .cookie_provider(Arc::clone(cookie_jar))
+        .timeout(Duration::from_secs(30))
+        .pool_max_idle_per_host(if reuse_connections { 10 } else { 0 })
+        .pool_idle_timeout(Duration::from_secs(30));
+
+    let client = if let Some(proxy_url) = proxy_url {
+        let proxy = reqwest::Proxy::all(proxy_url)
+            .map_err(|e| M3u8Error::ProxyError(format!("{}: {}", mask_proxy_url(proxy_url), e)))?;
+        client_builder.proxy(proxy).build()?
+    } else {
+        client_builder.build()?
+    };
+
+    Ok(client)
+}
+
+// 响应头中的 Content-Length，HEAD 响应的 Response::content_length 始终为 0，因此直接读取响应头
+fn content_length(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|length| *length > 0)
+}
+
+// 基于 reqwest 的默认实现：配置代理时每个代理对应一个客户端，由代理配置负责选择与健康统计
+pub struct ReqwestFetcher {
+    clients: Vec<reqwest::Client>,
+    proxy_config: Option<ProxyConfig>,
+    // 所有客户端共享的 Cookie 存储
+    cookie_jar: Arc<CookieJar>,
+    reprobe_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ReqwestFetcher {
+    pub fn new(
+        proxy_config: Option<ProxyConfig>,
+        cookie_jar: Option<CookieJar>,
+        pool_size: usize,
+    ) -> Result<Self, M3u8Error> {
+        // 创建默认请求头
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36"));
+        headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, deflate, br"),
+        );
+
+        let cookie_jar = Arc::new(cookie_jar.unwrap_or_default());
+        let clients = match &proxy_config {
+            // per-request 策略仍为每个代理复用同一个客户端，只是不保留空闲连接，
+            // 使每次请求都经由新连接换用代理网关的出口
+            Some(proxy_config) if !proxy_config.is_empty() => {
+                let reuse_connections = proxy_config.strategy() != ProxyStrategy::PerRequest;
+                proxy_config
+                    .proxies()
+                    .iter()
+                    .map(|proxy| {
+                        build_client(&headers, &cookie_jar, Some(&proxy.url), reuse_connections)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            _ => (0..pool_size.max(1))
+                .map(|_| build_client(&headers, &cookie_jar, None, true))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        Ok(Self {
+            clients,
+            proxy_config,
+            cookie_jar,
+            reprobe_handle: Mutex::new(None),
+        })
+    }
+
+    pub fn cookie_jar(&self) -> &Arc<CookieJar> {
+        &self.cookie_jar
+    }
+
+    pub fn proxy_config(&self) -> Option<&ProxyConfig> {
+        self.proxy_config.as_ref()
+    }
+
+    // 为请求地址选择一个客户端，尽量避开上次失败的客户端；
+    // 片段请求在持有下载并发许可后才调用，使最少并发策略只统计实际发出的请求
+    fn pick(&self, url: &str, exclude: Option<usize>) -> usize {
+        if let Some(config) = &self.proxy_config {
+            let host = url::Url::parse(url)
+                .ok()
+                .and_then(|u| u.host_str().map(str::to_string));
+            if let Some(index) = config.begin_request(host.as_deref(), exclude) {
+                return index;
+            }
+        }
+
+        let mut index = rand::random::<usize>() % self.clients.len();
+        if Some(index) == exclude && self.clients.len() > 1 {
+            index = (index + 1) % self.clients.len();
+        }
+        index
+    }
+
+    // 上报请求结果，用于代理健康统计
+    fn report(&self, index: usize, result: Result<Duration, ()>) {
+        if let Some(proxy_config) = &self.proxy_config {
+            match result {
+                Ok(latency) => proxy_config.record_success(index, latency),
+                Err(()) => proxy_config.record_failure(index),
+            }
+        }
+    }
+
+    // 通过选中的客户端发送请求，并上报代理健康统计
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &HeaderMap,
+        exclude: Option<usize>,
+    ) -> (usize, Result<reqwest::Response, M3u8Error>) {
+        let index = self.pick(url, exclude);
+        let started = Instant::now();
+
+        let result = match self.clients[index]
+            .request(method, url)
+            .headers(headers.clone())
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => Ok(response),
+            Ok(response) => Err(M3u8Error::HttpStatusError(
+                response.status(),
+                url.to_string(),
+            )),
+            Err(e) => Err(M3u8Error::NetworkError(e)),
+        };
+
+        // 4xx 说明代理本身工作正常，不计入代理失败
+        let proxy_result = match &result {
+            Err(M3u8Error::HttpStatusError(status, _)) if status.is_client_error() => {
+                Ok(started.elapsed())
+            }
+            Err(_) => Err(()),
+            Ok(_) => Ok(started.elapsed()),
+        };
+        self.report(index, proxy_result);
+
+        (index, result)
+    }
+
+    fn display_proxy_stats(&self) {
+        let Some(proxy_config) = &self.proxy_config else {
+            return;
+        };
+
+        for (url, stats) in proxy_config.stats() {
+            let latency = stats
+                .avg_latency
+                .map(|latency| format!("{} ms", latency.as_millis()))
+                .unwrap_or_else(|| "-".to_string());
+            let state = if stats.is_ejected() {
+                ", 已停用"
+            } else {
+                ""
+            };
+            println!(
+                "🌐 代理[{}]: 成功 {}, 失败 {}, 平均延迟 {}{}",
+                url, stats.successes, stats.failures, latency, state
+            );
+        }
+    }
+}
+
+impl PlaylistFetcher for ReqwestFetcher {
+    async fn fetch_playlist(&self, url: &str, headers: &HeaderMap) -> Result<String, M3u8Error> {
+        let (_, result) = self.send(Method::GET, url, headers, None).await;
+        Ok(result?.text().await?)
+    }
+}
+
+impl SegmentFetcher for ReqwestFetcher {
+    async fn fetch_segment(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        context: &mut FetchContext,
+    ) -> Result<Bytes, M3u8Error> {
+        // 重试时换用与上次失败不同的客户端（代理）
+        let (index, result) = self
+            .send(Method::GET, url, headers, context.failed_route)
+            .await;
+        let result = match result {
+            Ok(response) => response.bytes().await.map_err(M3u8Error::NetworkError),
+            Err(e) => Err(e),
+        };
+
+        if result.is_err() {
+            context.failed_route = Some(index);
+        }
+        result
+    }
+
+    // 先发送 HEAD 请求读取 Content-Length；服务端不支持 HEAD 或未返回长度时，
+    // 改用 Range: bytes=0-0 请求，从 Content-Range 中读取总长度
+    async fn probe_size(&self, url: &str, headers: &HeaderMap) -> Result<Option<u64>, M3u8Error> {
+        let (_, result) = self.send(Method::HEAD, url, headers, None).await;
+        if let Some(length) = result.ok().and_then(|response| content_length(&response)) {
+            return Ok(Some(length));
+        }
+
+        let mut range_headers = headers.clone();
+        range_headers.insert(RANGE, HeaderValue::from_static("bytes=0-0"));
+        let (_, result) = self.send(Method::GET, url, &range_headers, None).await;
+        let response = result?;
+        // 服务端忽略 Range 时返回完整内容，此时 Content-Length 即为片段大小，不读取响应体
+        let total = match response.headers().get(CONTENT_RANGE) {
+            Some(range) => range
+                .to_str()
+                .ok()
+                .and_then(|range| range.rsplit_once('/'))
+                .and_then(|(_, total)| total.parse().ok()),
+            None => content_length(&response),
+        };
+        Ok(total)
+    }
+
+    // 启动代理重新探测任务，恢复停用期已满且探测通过的代理
+    fn on_start(&self) {
+        if let Some(proxy_config) = self.proxy_config.clone() {
+            let handle = tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(10));
+                loop {
+                    interval.tick().await;
+                    proxy_config.reprobe_ejected().await;
+                }
+            });
+            *self.reprobe_handle.lock().unwrap() = Some(handle);
+        }
+    }
+
+    async fn on_finish(&self) -> Result<(), M3u8Error> {
+        let handle = self.reprobe_handle.lock().unwrap().take();
+        if let Some(handle) = handle {
+            handle.abort();
+        }
+        self.display_proxy_stats();
+        self.cookie_jar.persist().await
+    }
+}
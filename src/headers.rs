@@ -0,0 +1,212 @@
+use crate::error::M3u8Error;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::path::Path;
+use std::str::FromStr;
+use url::Url;
+
+// 请求类型，不同类型的请求可使用不同的请求头
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestKind {
+    Playlist,
+    Segment,
+    Key,
+}
+
+// 请求头配置：公共请求头 + 按请求类型区分的请求头 + 命令行请求头，后者依次覆盖前者
+// 值中可使用占位符 {url}、{host}、{origin}，分别替换为播放列表地址、主机名和源
+#[derive(Debug, Clone, Default)]
+pub struct HeaderProfile {
+    common: Vec<(String, String)>,
+    playlist: Vec<(String, String)>,
+    segment: Vec<(String, String)>,
+    key: Vec<(String, String)>,
+    // 命令行 -H 指定的请求头，优先级最高，应用于所有类型的请求
+    overrides: Vec<(String, String)>,
+}
+
+// 解析 "Name: Value" 格式的请求头
+fn parse_header_line(line: &str) -> Result<(String, String), String> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| format!("缺少 ':' 分隔符: {}", line))?;
+
+    let name = name.trim();
+    HeaderName::from_str(name).map_err(|_| format!("无效的请求头名称: {}", name))?;
+
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+impl HeaderProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 从命令行参数解析请求头，格式: "Name: Value"，覆盖配置文件中任何小节的同名请求头
+    pub fn from_args(header_args: &[String]) -> Result<Self, M3u8Error> {
+        let mut profile = Self::new();
+        for header_arg in header_args {
+            let header = parse_header_line(header_arg).map_err(M3u8Error::HeaderError)?;
+            profile.overrides.push(header);
+        }
+        Ok(profile)
+    }
+
+    // 从请求头配置文件加载
+    pub async fn load(path: &Path) -> Result<Self, M3u8Error> {
+        let content = tokio::fs::read_to_string(path).await?;
+        Self::parse(&content)
+    }
+
+    // 解析请求头配置文件，每行一个 "Name: Value"，# 开头为注释
+    // [all]、[playlist]、[segment]、[key] 小节分别对应公共及各类请求，默认为 [all]
+    pub fn parse(content: &str) -> Result<Self, M3u8Error> {
+        let mut profile = Self::new();
+        let mut section = None;
+
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name.trim() {
+                    "all" => None,
+                    "playlist" => Some(RequestKind::Playlist),
+                    "segment" => Some(RequestKind::Segment),
+                    "key" => Some(RequestKind::Key),
+                    other => {
+                        return Err(M3u8Error::HeaderError(format!(
+                            "第 {} 行: 未知的小节 [{}]",
+                            line_number + 1,
+                            other
+                        )))
+                    }
+                };
+                continue;
+            }
+
+            let header = parse_header_line(line)
+                .map_err(|e| M3u8Error::HeaderError(format!("第 {} 行: {}", line_number + 1, e)))?;
+            profile.section_mut(section).push(header);
+        }
+
+        Ok(profile)
+    }
+
+    fn section_mut(&mut self, kind: Option<RequestKind>) -> &mut Vec<(String, String)> {
+        match kind {
+            None => &mut self.common,
+            Some(RequestKind::Playlist) => &mut self.playlist,
+            Some(RequestKind::Segment) => &mut self.segment,
+            Some(RequestKind::Key) => &mut self.key,
+        }
+    }
+
+    // 合并另一份配置，同名请求头以后者为准
+    pub fn merge(&mut self, other: HeaderProfile) {
+        self.common.extend(other.common);
+        self.playlist.extend(other.playlist);
+        self.segment.extend(other.segment);
+        self.key.extend(other.key);
+        self.overrides.extend(other.overrides);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.common.is_empty()
+            && self.playlist.is_empty()
+            && self.segment.is_empty()
+            && self.key.is_empty()
+            && self.overrides.is_empty()
+    }
+
+    // 生成指定类型请求的请求头，并替换占位符
+    pub fn render(&self, kind: RequestKind, playlist_url: &str) -> Result<HeaderMap, M3u8Error> {
+        let parsed = Url::parse(playlist_url).ok();
+        let host = parsed
+            .as_ref()
+            .and_then(|u| u.host_str())
+            .unwrap_or_default()
+            .to_string();
+        let origin = parsed
+            .as_ref()
+            .filter(|u| u.has_host())
+            .map(|u| u.origin().ascii_serialization())
+            .unwrap_or_default();
+
+        let specific = match kind {
+            RequestKind::Playlist => &self.playlist,
+            RequestKind::Segment => &self.segment,
+            RequestKind::Key => &self.key,
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.common.iter().chain(specific).chain(&self.overrides) {
+            let value = value
+                .replace("{url}", playlist_url)
+                .replace("{host}", &host)
+                .replace("{origin}", &origin);
+
+            let header_name = HeaderName::from_str(name)
+                .map_err(|_| M3u8Error::HeaderError(format!("无效的请求头名称: {}", name)))?;
+            let header_value = HeaderValue::from_str(&value).map_err(|_| {
+                M3u8Error::HeaderError(format!("请求头 {} 的值无效: {}", name, value))
+            })?;
+            headers.insert(header_name, header_value);
+        }
+
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_headers_override_every_section() {
+        let mut profile = HeaderProfile::parse(
+            "Referer: {origin}/\n[segment]\nReferer: https://file.example/\n[key]\nX-Token: file\n",
+        )
+        .unwrap();
+        profile.merge(
+            HeaderProfile::from_args(&["Referer: https://cli.example/".to_string()]).unwrap(),
+        );
+
+        let url = "https://cdn.example.com/live/index.m3u8";
+        for kind in [
+            RequestKind::Playlist,
+            RequestKind::Segment,
+            RequestKind::Key,
+        ] {
+            let headers = profile.render(kind, url).unwrap();
+            assert_eq!(headers["referer"], "https://cli.example/");
+        }
+        assert_eq!(
+            profile.render(RequestKind::Key, url).unwrap()["x-token"],
+            "file"
+        );
+    }
+
+    #[test]
+    fn section_headers_override_common_and_expand_placeholders() {
+        let profile =
+            HeaderProfile::parse("Origin: https://a.example\n[playlist]\nOrigin: {origin}\n")
+                .unwrap();
+        let url = "https://cdn.example.com/live/index.m3u8";
+        assert_eq!(
+            profile.render(RequestKind::Playlist, url).unwrap()["origin"],
+            "https://cdn.example.com"
+        );
+        assert_eq!(
+            profile.render(RequestKind::Segment, url).unwrap()["origin"],
+            "https://a.example"
+        );
+    }
+
+    #[test]
+    fn malformed_header_is_an_error() {
+        assert!(HeaderProfile::from_args(&["NoColon".to_string()]).is_err());
+        assert!(HeaderProfile::parse("[bogus]\n").is_err());
+    }
+}
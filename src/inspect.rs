@@ -0,0 +1,519 @@
+use crate::downloader::{format_seconds, format_size};
+use crate::fetcher::SegmentFetcher;
+use crate::source::SegmentSource;
+use crate::types::{M3u8Playlist, M3u8Segment, M3u8Variant, NestedM3u8};
+use futures::stream::{self, StreamExt};
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::fmt;
+
+// 单个变体流的概要信息
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantInfo {
+    pub index: usize,
+    // 变体流播放列表地址，单个媒体播放列表时为空
+    pub url: Option<String>,
+    pub bandwidth: Option<u32>,
+    pub resolution: Option<(u32, u32)>,
+    pub codecs: Option<String>,
+    pub audio: Option<String>,
+    pub video: Option<String>,
+    pub subtitles: Option<String>,
+    pub segments: usize,
+    // 总时长（秒）
+    pub duration: f64,
+    pub is_live: bool,
+    // 使用的加密方式，如 AES-128、SAMPLE-AES，未加密时为空
+    pub encryption: Vec<String>,
+    // 带 EXT-X-BYTERANGE 的片段数量
+    pub byte_range_segments: usize,
+    // 估算的下载大小（字节）
+    pub estimated_size: Option<u64>,
+    // 全部片段都带字节范围时 estimated_size 为精确值
+    pub exact_size: bool,
+    // 探测抽样片段大小后推算的下载大小（字节）
+    pub probed_size: Option<u64>,
+    // 成功探测的片段数量
+    pub probed_segments: usize,
+    pub selected: bool,
+}
+
+impl VariantInfo {
+    fn new(index: usize, variant: Option<&M3u8Variant>, playlist: &M3u8Playlist) -> Self {
+        let mut encryption: Vec<String> = Vec::new();
+        for key in playlist.segments.iter().filter_map(|s| s.key.as_ref()) {
+            if key.method != "NONE" && !encryption.contains(&key.method) {
+                encryption.push(key.method.clone());
+            }
+        }
+
+        let byte_range_segments = playlist
+            .segments
+            .iter()
+            .filter(|s| s.byte_range.is_some())
+            .count();
+        let exact_size =
+            !playlist.segments.is_empty() && byte_range_segments == playlist.segments.len();
+        let bandwidth = variant.and_then(|v| v.bandwidth);
+
+        Self {
+            index,
+            url: variant.map(|v| v.url.clone()),
+            bandwidth,
+            resolution: variant.and_then(|v| v.resolution),
+            codecs: variant.and_then(|v| v.codecs.clone()),
+            audio: variant.and_then(|v| v.audio.clone()),
+            video: variant.and_then(|v| v.video.clone()),
+            subtitles: variant.and_then(|v| v.subtitles.clone()),
+            segments: playlist.segments.len(),
+            duration: playlist.segments.iter().map(|s| s.duration).sum(),
+            is_live: playlist.is_live,
+            encryption,
+            byte_range_segments,
+            estimated_size: estimate_size(&playlist.segments, bandwidth),
+            exact_size,
+            probed_size: None,
+            probed_segments: 0,
+            selected: false,
+        }
+    }
+}
+
+// 估算播放列表的下载大小：优先使用字节范围长度，其次 EXT-X-BITRATE，最后按 BANDWIDTH
+// （峰值码率，结果偏大）乘以时长；有片段无法估算时返回 None
+pub(crate) fn estimate_size(segments: &[M3u8Segment], bandwidth: Option<u32>) -> Option<u64> {
+    segments
+        .iter()
+        .map(|segment| match segment.byte_range {
+            Some((_, length)) => Some(length as u64),
+            None => {
+                let bits_per_second = segment
+                    .bitrate
+                    .map(|kbps| kbps as f64 * 1000.0)
+                    .or(bandwidth.map(f64::from))?;
+                Some((bits_per_second * segment.duration / 8.0) as u64)
+            }
+        })
+        .sum()
+}
+
+// 探测均匀抽取的 samples 个片段的大小，按抽样片段的平均码率推算全部片段的大小，
+// 返回 (推算大小, 成功探测的片段数)；带字节范围的片段直接使用范围长度，本地片段读取文件大小，data: URI 使用内容长度。
+// 同时进行的探测请求不超过 max_concurrent 个
+pub async fn probe_segments_size(
+    segments: &[M3u8Segment],
+    samples: usize,
+    max_concurrent: usize,
+    fetcher: &impl SegmentFetcher,
+    headers: &HeaderMap,
+) -> Option<(u64, usize)> {
+    if segments.is_empty() || samples == 0 {
+        return None;
+    }
+    let samples = samples.min(segments.len());
+    let sampled = (0..samples).map(|i| &segments[i * segments.len() / samples]);
+
+    let probes = sampled.map(|segment| async move {
+        if let Some((_, length)) = segment.byte_range {
+            return Some((segment.duration, length as u64));
+        }
+        let size = match SegmentSource::parse(&segment.url).ok()? {
+            SegmentSource::File(path) => tokio::fs::metadata(path).await.ok().map(|m| m.len()),
+            SegmentSource::Data(data) => Some(data.len() as u64),
+            SegmentSource::Http(url) => fetcher.probe_size(&url, headers).await.ok().flatten(),
+        };
+        size.map(|size| (segment.duration, size))
+    });
+    let probed: Vec<(f64, u64)> = stream::iter(probes)
+        .buffer_unordered(max_concurrent.max(1))
+        .filter_map(|probe| async move { probe })
+        .collect()
+        .await;
+    if probed.is_empty() {
+        return None;
+    }
+
+    let probed_duration: f64 = probed.iter().map(|(duration, _)| duration).sum();
+    let probed_bytes: u64 = probed.iter().map(|(_, size)| size).sum();
+    let total = if probed_duration > 0.0 {
+        let total_duration: f64 = segments.iter().map(|s| s.duration).sum();
+        probed_bytes as f64 / probed_duration * total_duration
+    } else {
+        probed_bytes as f64 / probed.len() as f64 * segments.len() as f64
+    };
+    Some((total as u64, probed.len()))
+}
+
+// 播放列表树的概要信息，只获取播放列表，不下载片段
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaylistInfo {
+    pub playlist: NestedM3u8,
+    pub variants: Vec<VariantInfo>,
+}
+
+impl PlaylistInfo {
+    pub fn new(playlist: NestedM3u8) -> Self {
+        let master = &playlist.master_playlist;
+        let mut variants: Vec<VariantInfo> = playlist
+            .media_playlists
+            .iter()
+            .enumerate()
+            .map(|(index, media)| VariantInfo::new(index, master.variants.get(index), media))
+            .collect();
+        if let Some(selected) = playlist
+            .selected_variant_index
+            .and_then(|index| variants.get_mut(index))
+        {
+            selected.selected = true;
+        }
+
+        Self { playlist, variants }
+    }
+
+    // 为每个变体流探测抽样片段的大小，全部片段带字节范围时大小已精确，不再探测
+    pub async fn probe_sizes(
+        &mut self,
+        samples: usize,
+        max_concurrent: usize,
+        fetcher: &impl SegmentFetcher,
+        headers: &HeaderMap,
+    ) {
+        for (variant, media) in self.variants.iter_mut().zip(&self.playlist.media_playlists) {
+            if variant.exact_size {
+                continue;
+            }
+            if let Some((size, probed)) =
+                probe_segments_size(&media.segments, samples, max_concurrent, fetcher, headers)
+                    .await
+            {
+                variant.probed_size = Some(size);
+                variant.probed_segments = probed;
+            }
+        }
+    }
+}
+
+impl fmt::Display for PlaylistInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "📺 变体流 {} 个（* 为默认下载的变体流）",
+            self.variants.len()
+        )?;
+        writeln!(
+            f,
+            "  {:<3} {:>10} {:>10} {:<28} {:>12} {:>6} {:<10} {:>9} {:>12}",
+            "#",
+            "BANDWIDTH",
+            "RESOLUTION",
+            "CODECS",
+            "DURATION",
+            "SEGS",
+            "ENCRYPTION",
+            "BYTERANGE",
+            "SIZE"
+        )?;
+        for variant in &self.variants {
+            let marker = if variant.selected { '*' } else { ' ' };
+            let bandwidth = variant
+                .bandwidth
+                .map_or("-".to_string(), |b| format!("{}k", b / 1000));
+            let resolution = variant
+                .resolution
+                .map_or("-".to_string(), |(w, h)| format!("{}x{}", w, h));
+            let duration = if variant.is_live {
+                format!("{} 直播", format_seconds(variant.duration))
+            } else {
+                format_seconds(variant.duration)
+            };
+            let encryption = if variant.encryption.is_empty() {
+                "-".to_string()
+            } else {
+                variant.encryption.join("/")
+            };
+            let size = match variant.estimated_size {
+                Some(size) if variant.exact_size => format_size(size, None),
+                Some(size) => format_size(size, Some("~")),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{} {:<3} {:>10} {:>10} {:<28} {:>12} {:>6} {:<10} {:>9} {:>12}",
+                marker,
+                variant.index,
+                bandwidth,
+                resolution,
+                variant.codecs.as_deref().unwrap_or("-"),
+                duration,
+                variant.segments,
+                encryption,
+                format!("{}/{}", variant.byte_range_segments, variant.segments),
+                size
+            )?;
+            if let Some(size) = variant.probed_size {
+                writeln!(
+                    f,
+                    "      探测大小: {}（采样 {} 个片段）",
+                    format_size(size, Some("~")),
+                    variant.probed_segments
+                )?;
+            }
+            let groups: Vec<String> = [
+                ("AUDIO", &variant.audio),
+                ("VIDEO", &variant.video),
+                ("SUBTITLES", &variant.subtitles),
+            ]
+            .into_iter()
+            .filter_map(|(kind, group)| group.as_ref().map(|g| format!("{}={}", kind, g)))
+            .collect();
+            if !groups.is_empty() {
+                writeln!(f, "      媒体组: {}", groups.join(", "))?;
+            }
+        }
+
+        let renditions = &self.playlist.master_playlist.renditions;
+        if !renditions.is_empty() {
+            writeln!(f, "🎧 备选媒体 {} 个", renditions.len())?;
+            for rendition in renditions {
+                let mut flags = Vec::new();
+                if rendition.default {
+                    flags.push("默认");
+                }
+                if rendition.autoselect {
+                    flags.push("自动选择");
+                }
+                writeln!(
+                    f,
+                    "  {:<15} {:<12} {:<20} {:<6} {:<12} {}",
+                    rendition.media_type,
+                    rendition.group_id,
+                    rendition.name,
+                    rendition.language.as_deref().unwrap_or("-"),
+                    flags.join(","),
+                    rendition.uri.as_deref().unwrap_or("（包含在变体流中）")
+                )?;
+            }
+        }
+
+        let warnings = self.playlist.master_playlist.warnings.iter().chain(
+            self.playlist
+                .media_playlists
+                .iter()
+                .flat_map(|p| &p.warnings),
+        );
+        for warning in warnings {
+            writeln!(f, "  ⚠️  {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::M3u8Error;
+    use crate::fetcher::FetchContext;
+    use crate::parser::{MasterParser, MediaParser};
+    use bytes::Bytes;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use url::Url;
+
+    // 按地址返回预设的片段大小，并记录同时进行的探测请求数量的峰值
+    #[derive(Default)]
+    struct Probe {
+        sizes: HashMap<String, u64>,
+        requests: AtomicUsize,
+        in_flight: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    impl Probe {
+        fn with(mut self, url: &str, size: u64) -> Self {
+            self.sizes.insert(url.to_string(), size);
+            self
+        }
+    }
+
+    impl SegmentFetcher for Probe {
+        async fn fetch_segment(
+            &self,
+            url: &str,
+            _: &HeaderMap,
+            _: &mut FetchContext,
+        ) -> Result<Bytes, M3u8Error> {
+            Err(M3u8Error::DownloadError(format!("不应下载片段: {}", url)))
+        }
+
+        async fn probe_size(&self, url: &str, _: &HeaderMap) -> Result<Option<u64>, M3u8Error> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(self.sizes.get(url).copied())
+        }
+    }
+
+    fn media(content: &str) -> M3u8Playlist {
+        let base_url = Url::parse("https://example.com/video/index.m3u8").unwrap();
+        MediaParser::new(Vec::new())
+            .unwrap()
+            .parse(content, Some(&base_url))
+            .unwrap()
+    }
+
+    #[test]
+    fn estimate_prefers_byte_range_then_bitrate_then_bandwidth() {
+        let playlist = media(
+            "#EXTM3U\n#EXT-X-VERSION:4\n#EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\n#EXT-X-BYTERANGE:5000@0\nall.ts\n\
+             #EXTINF:4,\nb.ts\n\
+             #EXT-X-BITRATE:800\n#EXTINF:10,\nc.ts\n#EXT-X-ENDLIST\n",
+        );
+        // 5000 + 2Mbps * 4s / 8 + 800kbps * 10s / 8
+        assert_eq!(
+            estimate_size(&playlist.segments, Some(2_000_000)),
+            Some(5000 + 1_000_000 + 1_000_000)
+        );
+    }
+
+    #[test]
+    fn estimate_requires_a_rate_for_every_segment() {
+        let playlist = media(
+            "#EXTM3U\n#EXT-X-VERSION:4\n#EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\n#EXT-X-BYTERANGE:5000@0\nall.ts\n\
+             #EXTINF:10,\nb.ts\n#EXT-X-ENDLIST\n",
+        );
+        assert_eq!(estimate_size(&playlist.segments[..1], None), Some(5000));
+        assert_eq!(estimate_size(&playlist.segments, None), None);
+        assert_eq!(estimate_size(&[], None), Some(0));
+    }
+
+    #[test]
+    fn summarizes_variants_and_renditions() {
+        let base_url = Url::parse("https://example.com/master.m3u8").unwrap();
+        let master = MasterParser::new(Vec::new())
+            .unwrap()
+            .parse(
+                "#EXTM3U\n\
+                 #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"English\",LANGUAGE=\"en\",DEFAULT=YES,AUTOSELECT=YES,URI=\"audio/en.m3u8\"\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\",AUDIO=\"aac\"\n\
+                 video/360.m3u8\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1280x720\n\
+                 video/720.m3u8\n",
+                Some(&base_url),
+            )
+            .unwrap();
+        let low = media(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:10\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+             #EXTINF:10,\na.ts\n#EXTINF:10,\nb.ts\n#EXT-X-ENDLIST\n",
+        );
+        let high = media(
+            "#EXTM3U\n#EXT-X-VERSION:4\n#EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\n#EXT-X-BYTERANGE:3000@0\nall.ts\n\
+             #EXTINF:5,\n#EXT-X-BYTERANGE:1000\nall.ts\n",
+        );
+        let info = PlaylistInfo::new(NestedM3u8 {
+            master_playlist: master,
+            media_playlists: vec![low, high],
+            selected_variant_index: Some(1),
+        });
+
+        let low = &info.variants[0];
+        assert_eq!(
+            low.url.as_deref(),
+            Some("https://example.com/video/360.m3u8")
+        );
+        assert_eq!(low.resolution, Some((640, 360)));
+        assert_eq!(low.audio.as_deref(), Some("aac"));
+        assert_eq!((low.segments, low.duration), (2, 20.0));
+        assert_eq!(low.encryption, vec!["AES-128".to_string()]);
+        assert_eq!(low.estimated_size, Some(2_000_000));
+        assert!(!low.exact_size && !low.selected && !low.is_live);
+
+        let high = &info.variants[1];
+        assert_eq!(high.byte_range_segments, 2);
+        assert_eq!(high.estimated_size, Some(4000));
+        assert!(high.exact_size && high.selected && high.is_live);
+        assert!(high.encryption.is_empty());
+
+        let text = info.to_string();
+        assert!(text.contains("📺 变体流 2 个"), "{}", text);
+        assert!(text.contains("      媒体组: AUDIO=aac"), "{}", text);
+        assert!(text.contains("🎧 备选媒体 1 个"), "{}", text);
+        assert!(
+            text.contains("English") && text.contains("默认,自动选择"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("https://example.com/audio/en.m3u8"),
+            "{}",
+            text
+        );
+        assert!(text.lines().any(|line| line.starts_with("* 1")), "{}", text);
+    }
+
+    fn segments(count: usize) -> Vec<M3u8Segment> {
+        let mut content = String::from("#EXTM3U\n#EXT-X-TARGETDURATION:10\n");
+        for i in 0..count {
+            content.push_str(&format!("#EXTINF:10,\n{}.ts\n", i));
+        }
+        content.push_str("#EXT-X-ENDLIST\n");
+        media(&content).segments
+    }
+
+    #[tokio::test]
+    async fn probe_extrapolates_from_evenly_spaced_samples() {
+        let fetcher = Probe::default()
+            .with("https://example.com/video/0.ts", 1000)
+            .with("https://example.com/video/2.ts", 3000);
+        let probed = probe_segments_size(&segments(4), 2, 4, &fetcher, &HeaderMap::new()).await;
+        // 抽样 20 秒共 4000 字节，全部 40 秒推算为 8000 字节
+        assert_eq!(probed, Some((8000, 2)));
+    }
+
+    #[tokio::test]
+    async fn probe_skips_failed_samples() {
+        let fetcher = Probe::default().with("https://example.com/video/0.ts", 1000);
+        let probed = probe_segments_size(&segments(4), 2, 4, &fetcher, &HeaderMap::new()).await;
+        assert_eq!(probed, Some((4000, 1)));
+
+        let probed =
+            probe_segments_size(&segments(4), 2, 4, &Probe::default(), &HeaderMap::new()).await;
+        assert_eq!(probed, None);
+        assert_eq!(
+            probe_segments_size(&segments(4), 0, 4, &fetcher, &HeaderMap::new()).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_uses_byte_ranges_without_requests() {
+        let playlist = media(
+            "#EXTM3U\n#EXT-X-VERSION:4\n#EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\n#EXT-X-BYTERANGE:3000@0\nall.ts\n\
+             #EXTINF:10,\n#EXT-X-BYTERANGE:1000\nall.ts\n#EXT-X-ENDLIST\n",
+        );
+        let fetcher = Probe::default();
+        let probed =
+            probe_segments_size(&playlist.segments, 5, 4, &fetcher, &HeaderMap::new()).await;
+        assert_eq!(probed, Some((4000, 2)));
+        assert_eq!(fetcher.requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn probe_limits_concurrent_requests() {
+        let segments = segments(8);
+        let fetcher = segments.iter().fold(Probe::default(), |fetcher, segment| {
+            fetcher.with(&segment.url, 1000)
+        });
+        let probed = probe_segments_size(&segments, 8, 3, &fetcher, &HeaderMap::new()).await;
+        assert_eq!(probed, Some((8000, 8)));
+        assert_eq!(fetcher.requests.load(Ordering::SeqCst), 8);
+        assert_eq!(fetcher.peak.load(Ordering::SeqCst), 3);
+    }
+}
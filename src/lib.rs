@@ -1,14 +1,35 @@
+pub mod cookie_jar;
+pub mod decrypt;
 pub mod downloader;
 pub mod error;
+pub mod fetcher;
+pub mod headers;
+pub mod inspect;
 pub mod merger;
 pub mod parser;
 pub mod proxy;
+pub mod scte35;
+pub mod source;
+pub mod time_range;
 pub mod types;
+pub mod validator;
 
+pub use cookie_jar::CookieJar;
+pub use decrypt::SegmentDecryptor;
 pub use downloader::M3u8Downloader;
 pub use error::M3u8Error;
-pub use merger::VideoMerger;
+pub use fetcher::{FetchContext, PlaylistFetcher, ReqwestFetcher, SegmentFetcher};
+pub use headers::{HeaderProfile, RequestKind};
+pub use inspect::{PlaylistInfo, VariantInfo};
+pub use merger::{MergeMode, OutputFormat, VideoMerger};
 pub use parser::*;
-pub use proxy::ProxyConfig;
+pub use proxy::{ProxyConfig, ProxyHealthCheck, ProxyStrategy};
+pub use scte35::SpliceInfo;
+pub use source::SegmentSource;
+pub use time_range::{ClipWindow, TimePoint, TimeRange};
 pub use types::M3u8Segment;
-pub use types::{M3u8Playlist, NestedM3u8, PlaylistType};
+pub use types::{
+    AdBreak, CueEvent, CueMarker, DateRange, M3u8Key, M3u8Map, M3u8Playlist, M3u8Rendition,
+    M3u8Start, MediaPlaylistType, NestedM3u8, PlaylistType,
+};
+pub use validator::{PlaylistValidator, Severity, ValidationIssue, ValidationReport};
@@ -1,15 +1,26 @@
-use clap::Parser;
-use rust_m3u8::{M3u8Downloader, M3u8Error, ProxyConfig};
+use clap::{Parser, Subcommand};
+use rust_m3u8::{
+    AdDetector, CookieJar, HeaderProfile, M3u8Downloader, M3u8Error, MergeMode, NestedParser,
+    OutputFormat, ParseMode, PlaylistInfo, PlaylistValidator, ProxyConfig, ProxyHealthCheck,
+    ProxyStrategy, QueryPropagation, RequestKind, ReqwestFetcher, Severity, TimePoint, TimeRange,
+    DEFAULT_AD_SCORE_THRESHOLD,
+};
 use std::path::PathBuf;
+use url::Url;
 
 #[derive(Parser)]
 #[command(name = "rust-m3u8")]
 #[command(about = "一个用 Rust 编写的 M3U8 下载器", version)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    // M3U8 播放列表的 URL 或本地文件路径
-    url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    // 输出文件名
+    // M3U8 播放列表的 URL、file:// 地址或本地文件路径
+    #[arg(required = true)]
+    url: Option<String>,
+
+    // 输出文件名，未带扩展名时按输出格式补上
     #[arg(short, long, default_value = "output")]
     output: String,
 
@@ -21,86 +32,430 @@ struct Args {
     #[arg(short, long, default_value = "3")]
     retry: usize,
 
-    // 使用 简单方式 合并视频片段，默认使用 ffmpeg
-    #[arg(short, long)]
+    // 使用 简单方式 合并视频片段，等同于 --format ts
+    #[arg(short, long, conflicts_with_all = ["format", "merge_mode"])]
     simple: bool,
 
-    // 代理配置，格式: "weight,proxy_url"，可多次指定
-    #[arg(short, long, action = clap::ArgAction::Append)]
-    proxy: Vec<String>,
+    // 输出格式: ts/mp4/mkv/fmp4/hls-dir，未指定时按输出文件扩展名推断，默认 mp4
+    #[arg(long)]
+    format: Option<OutputFormat>,
+
+    // 合并方式: ffmpeg/native，native 使用内置封装，无需 ffmpeg，封装失败时报错；
+    // ffmpeg 失败时改用内置封装，仍失败则直接拼接片段
+    #[arg(long, default_value = "ffmpeg")]
+    merge_mode: MergeMode,
+
+    #[command(flatten)]
+    request: RequestArgs,
 
-    // 基础 URL（当使用本地 M3U8 文件且包含相对路径时必需）
+    // 基础 URL 或本地目录，用于解析相对路径（本地 M3U8 文件默认以文件所在目录为基础）
     #[arg(short, long)]
     base: Option<String>,
 
-    // 自定义请求头，格式: "Name: Value"，可多次指定
-    #[arg(short = 'H', long, action = clap::ArgAction::Append)]
-    header: Vec<String>,
-
     // 广告过滤正则表达式，可多次指定
     #[arg(short, long, action = clap::ArgAction::Append)]
     filter: Vec<String>,
 
+    // 根据广告标记、片段域名与时长特征检测并移除广告段落，不连续点与总时长只作为辅助特征
+    #[arg(long)]
+    detect_ads: bool,
+
+    // 广告检测判定阈值，得分不低于该值的段落被移除
+    #[arg(long, default_value_t = DEFAULT_AD_SCORE_THRESHOLD, requires = "detect_ads")]
+    ad_score: u32,
+
+    // 下载结束后将更新的 Cookie 写回 --cookies 指定的文件
+    #[arg(long, requires = "cookies")]
+    save_cookies: bool,
+
+    // 将播放列表地址中的查询参数传递给变体流、片段、密钥等子地址
+    // 取值 "all" 表示全部，或逗号分隔的参数名，如 "token,expires"
+    #[arg(long)]
+    propagate_query: Option<String>,
+
+    // 片段签名地址过期时允许刷新播放列表的最大次数，0 表示不刷新
+    #[arg(long, default_value = "5")]
+    refresh_budget: usize,
+
+    // 起始时间：相对开头的媒体时间（+HH:MM:SS、+MM:SS 或秒数），
+    // 或依据 EXT-X-PROGRAM-DATE-TIME 的时刻（14:00 取第一个片段所在日期，
+    // 也可写完整日期如 2024-01-01T14:00:00+08:00）；
+    // 播放列表没有 EXT-X-PROGRAM-DATE-TIME 时 HH:MM:SS、MM:SS 按媒体时间处理
+    #[arg(long)]
+    from: Option<TimePoint>,
+
+    // 结束时间，格式同 --from
+    #[arg(long)]
+    to: Option<TimePoint>,
+
+    // 播放列表解析模式: strict/lenient，strict 遇到不符合规范的内容即报错，
+    // lenient 容忍 BOM、多余空白等常见问题并输出警告
+    #[arg(long, default_value = "lenient")]
+    parse_mode: ParseMode,
+
+    // 下载前探测 N 个抽样片段的大小（HEAD 或 0-0 范围请求）以估算下载总量并检查磁盘空间，
+    // 0 表示只按字节范围或 BANDWIDTH 估算
+    #[arg(long, default_value = "0")]
+    probe_samples: usize,
+
+    // 跳过下载前的磁盘空间检查
+    #[arg(long)]
+    no_space_check: bool,
+
     // 下载完成后是否保留临时文件
     #[arg(long)]
     keep_temp: bool,
 }
 
+// 下载与子命令共用的请求参数：请求头、Cookie 与代理
+#[derive(clap::Args)]
+struct RequestArgs {
+    // 自定义请求头，格式: "Name: Value"，可多次指定
+    #[arg(short = 'H', long, action = clap::ArgAction::Append)]
+    header: Vec<String>,
+
+    // 请求头配置文件，可按 [playlist]/[segment]/[key] 小节区分请求类型
+    // 值中可使用 {url}、{host}、{origin} 占位符
+    #[arg(long)]
+    header_profile: Option<PathBuf>,
+
+    // Netscape 格式的 cookies.txt 文件，所有请求共享其中的 Cookie
+    #[arg(long)]
+    cookies: Option<PathBuf>,
+
+    // 代理配置，格式: "[weight,]proxy_url"，可多次指定
+    // 支持 http/https/socks5/socks5h 协议及 user:pass@ 认证信息
+    #[arg(short, long, action = clap::ArgAction::Append)]
+    proxy: Vec<String>,
+
+    // 代理列表文件，每行一个代理，格式同 --proxy
+    #[arg(long)]
+    proxy_file: Option<PathBuf>,
+
+    // 代理健康检查地址，默认使用播放列表所在站点的根地址
+    #[arg(long)]
+    proxy_check_url: Option<String>,
+
+    // 代理健康检查期望的响应状态码，默认收到任意 HTTP 响应即视为可用
+    #[arg(long)]
+    proxy_check_status: Option<u16>,
+
+    // 代理健康检查期望响应内容中包含的文本
+    #[arg(long)]
+    proxy_check_body: Option<String>,
+
+    // 跳过代理健康检查
+    #[arg(long)]
+    no_proxy_check: bool,
+
+    // 代理轮换策略: weighted/round-robin/least-in-flight/sticky/per-request
+    #[arg(long, default_value = "weighted")]
+    proxy_strategy: ProxyStrategy,
+}
+
+impl RequestArgs {
+    // 合并命令行与代理列表文件中的代理并检查可用性，未指定代理时返回 None
+    async fn proxy_config(&self, url: &str) -> Result<Option<ProxyConfig>, M3u8Error> {
+        let mut proxy_args = self.proxy.clone();
+        if let Some(proxy_file) = &self.proxy_file {
+            let list = ProxyConfig::read_proxy_list(proxy_file)
+                .await
+                .map_err(M3u8Error::ProxyError)?;
+            if list.is_empty() {
+                return Err(M3u8Error::ProxyError(format!(
+                    "代理列表文件中没有代理: {}",
+                    proxy_file.display()
+                )));
+            }
+            proxy_args.extend(list);
+        }
+        if proxy_args.is_empty() {
+            return Ok(None);
+        }
+
+        // 默认通过代理请求播放列表所在站点的根地址来检查代理是否可用；
+        // 播放列表本身可能需要签名、Referer 或 Cookie，直接请求会误判代理不可用
+        let health_check = ProxyHealthCheck {
+            enabled: !self.no_proxy_check,
+            target: self.proxy_check_url.clone().or_else(|| {
+                Url::parse(url)
+                    .ok()
+                    .filter(|url| url.scheme().starts_with("http"))
+                    .map(|url| format!("{}/", url.origin().ascii_serialization()))
+            }),
+            expect_status: self.proxy_check_status,
+            expect_body: self.proxy_check_body.clone(),
+            ..ProxyHealthCheck::default()
+        };
+        // 指定了代理却没有可用的代理时不能直接连接，否则会暴露真实地址
+        let mut config = ProxyConfig::from_args(&proxy_args, health_check)
+            .await
+            .map_err(M3u8Error::ProxyError)?;
+        config.set_strategy(self.proxy_strategy);
+        Ok(Some(config))
+    }
+
+    // 加载 --cookies 指定的 Cookie 文件
+    async fn cookie_jar(&self) -> Result<Option<CookieJar>, M3u8Error> {
+        match &self.cookies {
+            Some(path) => Ok(Some(CookieJar::load_netscape(path).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // 先加载请求头配置文件，命令行指定的请求头优先
+    async fn header_profile(&self) -> Result<HeaderProfile, M3u8Error> {
+        let mut header_profile = match &self.header_profile {
+            Some(path) => HeaderProfile::load(path).await?,
+            None => HeaderProfile::new(),
+        };
+        header_profile.merge(HeaderProfile::from_args(&self.header)?);
+        Ok(header_profile)
+    }
+
+    // 按与下载相同的方式创建请求器，子命令也能使用请求头配置、Cookie 与代理
+    async fn fetcher(
+        &self,
+        url: &str,
+        pool_size: usize,
+    ) -> Result<(ReqwestFetcher, HeaderProfile), M3u8Error> {
+        let proxy_config = self.proxy_config(url).await?;
+        let cookie_jar = self.cookie_jar().await?;
+        let fetcher = ReqwestFetcher::new(proxy_config, cookie_jar, pool_size)?;
+        Ok((fetcher, self.header_profile().await?))
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    // 以严格模式检查播放列表是否符合 RFC 8216，存在错误时以非零状态退出
+    Validate {
+        // M3U8 播放列表的 URL、file:// 地址或本地文件路径
+        url: String,
+
+        // 基础 URL 或本地目录，用于解析相对路径
+        #[arg(short, long)]
+        base: Option<String>,
+
+        #[command(flatten)]
+        request: RequestArgs,
+
+        // 不重新获取直播播放列表检查媒体序号
+        #[arg(long)]
+        no_reload: bool,
+    },
+    // 获取播放列表树并列出所有变体流、备选媒体、时长、片段数、加密与估算大小，不下载片段
+    Info {
+        // M3U8 播放列表的 URL、file:// 地址或本地文件路径
+        url: String,
+
+        // 基础 URL 或本地目录，用于解析相对路径
+        #[arg(short, long)]
+        base: Option<String>,
+
+        #[command(flatten)]
+        request: RequestArgs,
+
+        // 播放列表解析模式: strict/lenient
+        #[arg(long, default_value = "lenient")]
+        parse_mode: ParseMode,
+
+        // 探测每个变体流 N 个抽样片段的大小（HEAD 或 0-0 范围请求）以估算下载总量，0 表示不探测
+        #[arg(long, default_value = "0")]
+        probe_samples: usize,
+
+        // 同时进行的探测请求数量
+        #[arg(short, long, default_value = "20")]
+        concurrent: usize,
+
+        // 以 JSON 格式输出完整的播放列表树
+        #[arg(long)]
+        json: bool,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<(), M3u8Error> {
     let args = Args::parse();
 
-    // 解析代理配置
-    let mut proxy_count: u32 = 0;
-    let proxy_config = if !args.proxy.is_empty() {
-        match ProxyConfig::from_args(&args.proxy).await {
-            Ok(config) => {
-                proxy_count = config.len() as u32;
-                Some(config)
-            }
-            Err(e) => {
-                eprintln!("❌ 代理配置错误: {}", e);
-                None
-            }
+    match args.command {
+        Some(Command::Validate {
+            url,
+            base,
+            request,
+            no_reload,
+        }) => return validate(&url, base, &request, !no_reload).await,
+        Some(Command::Info {
+            url,
+            base,
+            request,
+            parse_mode,
+            probe_samples,
+            concurrent,
+            json,
+        }) => {
+            return info(
+                &url,
+                base,
+                &request,
+                parse_mode,
+                probe_samples,
+                concurrent,
+                json,
+            )
+            .await
         }
-    } else {
-        None
-    };
+        None => {}
+    }
+    // 未使用子命令时 url 为必填参数
+    let url = args.url.unwrap_or_default();
+
+    let proxy_config = args.request.proxy_config(&url).await?;
+    let proxy_count = proxy_config.as_ref().map_or(0, |config| config.len());
 
     let mut config_info = Vec::new();
     if proxy_count > 0 {
-        config_info.push(format!("🌐 代理 {} 个", proxy_count));
+        config_info.push(format!(
+            "🌐 代理 {} 个 ({})",
+            proxy_count, args.request.proxy_strategy
+        ));
     }
     if !args.filter.is_empty() {
         config_info.push(format!("🚫 广告过滤规则 {} 条", args.filter.len()));
     }
+    if args.detect_ads {
+        config_info.push(format!("🔍 广告检测 (阈值 {})", args.ad_score));
+    }
+
+    let format = if args.simple {
+        Some(OutputFormat::Ts)
+    } else {
+        args.format
+    };
+    let (output_format, output_path) = OutputFormat::resolve_output(&args.output, format);
 
     if !config_info.is_empty() {
         println!("已配置: {}", config_info.join(", "));
         println!(
-            "📁 输出文件: {}.mp4, 🔄 并发数量: {}, 🔁 最大重试: {} 次",
-            args.output, args.concurrent, args.retry
+            "📁 输出文件: {} ({}), 🔄 并发数量: {}, 🔁 最大重试: {} 次",
+            output_path.display(),
+            output_format,
+            args.concurrent,
+            args.retry
         );
     } else {
         println!(
-            "📁 输出文件: {}.mp4, 🔄 并发数量: {}, 🔁 最大重试: {} 次",
-            args.output, args.concurrent, args.retry
+            "📁 输出文件: {} ({}), 🔄 并发数量: {}, 🔁 最大重试: {} 次",
+            output_path.display(),
+            output_format,
+            args.concurrent,
+            args.retry
         );
     }
 
+    let mut cookie_jar = args.request.cookie_jar().await?;
+    if let Some(jar) = &cookie_jar {
+        println!("🍪 已加载 {} 个 Cookie", jar.len());
+    }
+    if args.save_cookies {
+        cookie_jar = cookie_jar
+            .zip(args.request.cookies.clone())
+            .map(|(jar, path)| jar.with_save_path(path));
+    }
+    let header_profile = args.request.header_profile().await?;
+
     let downloader = M3u8Downloader::new(
-        args.url,
-        PathBuf::from(args.output).with_extension("mp4"),
+        url,
+        output_path,
         args.concurrent,
         args.keep_temp,
         proxy_config,
         args.retry,
         args.base,
-        args.header,
+        header_profile,
         args.filter,
-        args.simple,
+        args.detect_ads.then(|| AdDetector::new(args.ad_score)),
+        output_format,
+        args.merge_mode,
+        cookie_jar,
+        args.refresh_budget,
+        args.propagate_query
+            .as_deref()
+            .map(QueryPropagation::from_arg)
+            .unwrap_or_default(),
+        TimeRange::new(args.from, args.to),
+        args.parse_mode,
+        args.probe_samples,
+        !args.no_space_check,
     )?;
 
     downloader.download().await?;
     Ok(())
 }
+
+// 检查播放列表并打印报告
+async fn validate(
+    url: &str,
+    base: Option<String>,
+    request: &RequestArgs,
+    reload_live: bool,
+) -> Result<(), M3u8Error> {
+    let (fetcher, profile) = request.fetcher(url, 1).await?;
+    let headers = profile.render(RequestKind::Playlist, url)?;
+    let validator = PlaylistValidator::new(headers, base, reload_live);
+    let report = validator.validate(url, &fetcher).await?;
+
+    for issue in &report.issues {
+        match issue.severity {
+            Severity::Error => println!("❌ {}", issue),
+            Severity::Warning => println!("⚠️  {}", issue),
+        }
+    }
+    println!(
+        "📋 已检查 {} 个播放列表: {} 个错误, {} 个警告",
+        report.playlists,
+        report.error_count(),
+        report.warning_count()
+    );
+
+    if report.has_errors() {
+        std::process::exit(1);
+    }
+    println!("✅ 播放列表符合规范");
+    Ok(())
+}
+
+// 打印播放列表树的概要信息
+async fn info(
+    url: &str,
+    base: Option<String>,
+    request: &RequestArgs,
+    parse_mode: ParseMode,
+    probe_samples: usize,
+    concurrent: usize,
+    json: bool,
+) -> Result<(), M3u8Error> {
+    let pool_size = probe_samples.clamp(1, concurrent.max(1));
+    let (fetcher, profile) = request.fetcher(url, pool_size).await?;
+    let parser = NestedParser::new(Vec::new())?
+        .with_headers(profile.render(RequestKind::Playlist, url)?)
+        .with_mode(parse_mode);
+    let nested = parser
+        .parse_location(url, base.as_deref(), &fetcher)
+        .await?;
+    let mut info = PlaylistInfo::new(nested);
+    if probe_samples > 0 {
+        let headers = profile.render(RequestKind::Segment, url)?;
+        info.probe_sizes(probe_samples, concurrent, &fetcher, &headers)
+            .await;
+    }
+
+    if json {
+        let output = serde_json::to_string_pretty(&info)
+            .map_err(|e| M3u8Error::ParseError(format!("JSON 序列化失败: {}", e)))?;
+        println!("{}", output);
+    } else {
+        print!("{}", info);
+    }
+    Ok(())
+}
@@ -0,0 +1,668 @@
+// 编码参数解析：H.264/H.265 NAL 单元与 SPS、AAC ADTS 帧头
+
+use crate::error::M3u8Error;
+
+// AAC 采样率索引表
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// 每个 AAC 帧包含的采样数
+pub const AAC_FRAME_SAMPLES: u32 = 1024;
+
+// 轨道编码参数，供 MP4/MKV 写出解码器配置
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecConfig {
+    Avc {
+        sps: Vec<u8>,
+        pps: Vec<u8>,
+        width: u32,
+        height: u32,
+    },
+    Hevc {
+        vps: Vec<u8>,
+        sps: Vec<u8>,
+        pps: Vec<u8>,
+        width: u32,
+        height: u32,
+        info: HevcSpsInfo,
+    },
+    Aac {
+        object_type: u8,
+        frequency_index: u8,
+        sample_rate: u32,
+        channels: u8,
+    },
+}
+
+impl CodecConfig {
+    pub fn is_video(&self) -> bool {
+        !matches!(self, CodecConfig::Aac { .. })
+    }
+
+    // 视频宽高，音频返回 None
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        match self {
+            CodecConfig::Avc { width, height, .. } | CodecConfig::Hevc { width, height, .. } => {
+                Some((*width, *height))
+            }
+            CodecConfig::Aac { .. } => None,
+        }
+    }
+
+    // 视频参数集（VPS/SPS/PPS），音频返回空列表
+    pub fn parameter_sets(&self) -> Vec<&[u8]> {
+        match self {
+            CodecConfig::Avc { sps, pps, .. } => vec![sps, pps],
+            CodecConfig::Hevc { vps, sps, pps, .. } => vec![vps, sps, pps],
+            CodecConfig::Aac { .. } => Vec::new(),
+        }
+    }
+
+    // 在关键帧前插入 4 字节长度前缀的参数集，用于编码参数变化后的样本
+    pub fn with_parameter_sets(&self, data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(data.len() + 64);
+        for parameter_set in self.parameter_sets() {
+            result.extend_from_slice(&(parameter_set.len() as u32).to_be_bytes());
+            result.extend_from_slice(parameter_set);
+        }
+        result.extend_from_slice(data);
+        result
+    }
+
+    // 生成解码器配置：avcC、hvcC 或 AudioSpecificConfig
+    pub fn decoder_config(&self) -> Vec<u8> {
+        match self {
+            CodecConfig::Avc { sps, pps, .. } => {
+                let mut config = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+                config.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+                config.extend_from_slice(sps);
+                config.push(1);
+                config.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+                config.extend_from_slice(pps);
+                config
+            }
+            CodecConfig::Hevc {
+                vps,
+                sps,
+                pps,
+                info,
+                ..
+            } => {
+                let mut config = vec![1];
+                config.extend_from_slice(&info.profile_tier_level);
+                config.extend_from_slice(&0xF000u16.to_be_bytes());
+                config.push(0xFC);
+                config.push(0xFC | info.chroma_format_idc);
+                config.push(0xF8 | info.bit_depth_luma_minus8);
+                config.push(0xF8 | info.bit_depth_chroma_minus8);
+                config.extend_from_slice(&0u16.to_be_bytes());
+                config.push((info.temporal_layers << 3) | (info.temporal_id_nested << 2) | 3);
+                config.push(3);
+                for (nal_type, nal) in [(32u8, vps), (33, sps), (34, pps)] {
+                    config.push(0x80 | nal_type);
+                    config.extend_from_slice(&1u16.to_be_bytes());
+                    config.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+                    config.extend_from_slice(nal);
+                }
+                config
+            }
+            CodecConfig::Aac {
+                object_type,
+                frequency_index,
+                channels,
+                ..
+            } => {
+                let config = ((*object_type as u16) << 11)
+                    | ((*frequency_index as u16) << 7)
+                    | ((*channels as u16) << 3);
+                config.to_be_bytes().to_vec()
+            }
+        }
+    }
+}
+
+// H.265 SPS 中写入 hvcC 所需的字段
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcSpsInfo {
+    pub profile_tier_level: [u8; 12],
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub temporal_layers: u8,
+    pub temporal_id_nested: u8,
+}
+
+// 按位读取器，用于解析指数哥伦布编码
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Some(bit as u32)
+    }
+
+    fn read_bits(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()?;
+        }
+        Some(value)
+    }
+
+    fn skip_bits(&mut self, count: usize) -> Option<()> {
+        if self.position + count > self.data.len() * 8 {
+            return None;
+        }
+        self.position += count;
+        Some(())
+    }
+
+    fn read_ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.read_bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    fn read_se(&mut self) -> Option<i32> {
+        let value = self.read_ue()?;
+        Some(if value % 2 == 1 {
+            value.div_ceil(2) as i32
+        } else {
+            -((value / 2) as i32)
+        })
+    }
+}
+
+// 去除防竞争字节（00 00 03 中的 03）
+fn remove_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+// 按起始码切分 Annex B 字节流
+pub fn split_nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 2 < data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                units.push(trim_trailing_zeros(&data[start..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        units.push(trim_trailing_zeros(&data[start..]));
+    }
+
+    units.retain(|unit| !unit.is_empty());
+    units
+}
+
+fn trim_trailing_zeros(data: &[u8]) -> &[u8] {
+    let end = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+// H.264 SPS 中与画面尺寸相关的字段
+struct AvcSpsGeometry {
+    chroma_format_idc: u32,
+    width_in_mbs_minus1: u32,
+    height_in_map_units_minus1: u32,
+    frame_mbs_only: u32,
+    // 左、右、上、下裁剪量
+    crop: [u32; 4],
+}
+
+// 解析 H.264 SPS，返回显示宽高；数据不完整或尺寸溢出时报错
+pub fn parse_avc_sps(sps: &[u8]) -> Result<(u32, u32), M3u8Error> {
+    let geometry = read_avc_sps_geometry(sps)
+        .ok_or_else(|| M3u8Error::MergeError("H.264 SPS 数据不完整".to_string()))?;
+    let invalid = || M3u8Error::MergeError("H.264 SPS 中的画面尺寸无效".to_string());
+
+    let frame_height_factor = 2 - geometry.frame_mbs_only;
+    let width = geometry
+        .width_in_mbs_minus1
+        .checked_add(1)
+        .and_then(|mbs| mbs.checked_mul(16))
+        .ok_or_else(invalid)?;
+    let height = geometry
+        .height_in_map_units_minus1
+        .checked_add(1)
+        .and_then(|units| units.checked_mul(frame_height_factor * 16))
+        .ok_or_else(invalid)?;
+
+    let (crop_x, crop_y) = match geometry.chroma_format_idc {
+        0 => (1, frame_height_factor),
+        1 => (2, 2 * frame_height_factor),
+        2 => (2, frame_height_factor),
+        _ => (1, frame_height_factor),
+    };
+    let [left, right, top, bottom] = geometry.crop;
+    let crop_width = left
+        .checked_add(right)
+        .and_then(|crop| crop.checked_mul(crop_x));
+    let crop_height = top
+        .checked_add(bottom)
+        .and_then(|crop| crop.checked_mul(crop_y));
+    let width = crop_width
+        .and_then(|crop| width.checked_sub(crop))
+        .ok_or_else(invalid)?;
+    let height = crop_height
+        .and_then(|crop| height.checked_sub(crop))
+        .ok_or_else(invalid)?;
+
+    Ok((width, height))
+}
+
+fn read_avc_sps_geometry(sps: &[u8]) -> Option<AvcSpsGeometry> {
+    let rbsp = remove_emulation_prevention(sps.get(1..)?);
+    let mut reader = BitReader::new(&rbsp);
+
+    let profile_idc = reader.read_bits(8)?;
+    reader.skip_bits(16)?;
+    reader.read_ue()?;
+
+    let mut chroma_format_idc = 1;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc == 3 {
+            reader.skip_bits(1)?;
+        }
+        reader.read_ue()?;
+        reader.read_ue()?;
+        reader.skip_bits(1)?;
+        if reader.read_bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if reader.read_bit()? == 1 {
+                    skip_scaling_list(&mut reader, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    reader.read_ue()?;
+    match reader.read_ue()? {
+        0 => {
+            reader.read_ue()?;
+        }
+        1 => {
+            reader.skip_bits(1)?;
+            reader.read_se()?;
+            reader.read_se()?;
+            for _ in 0..reader.read_ue()? {
+                reader.read_se()?;
+            }
+        }
+        _ => {}
+    }
+    reader.read_ue()?;
+    reader.skip_bits(1)?;
+
+    let width_in_mbs_minus1 = reader.read_ue()?;
+    let height_in_map_units_minus1 = reader.read_ue()?;
+    let frame_mbs_only = reader.read_bit()?;
+    if frame_mbs_only == 0 {
+        reader.skip_bits(1)?;
+    }
+    reader.skip_bits(1)?;
+
+    let mut crop = [0; 4];
+    if reader.read_bit()? == 1 {
+        for value in &mut crop {
+            *value = reader.read_ue()?;
+        }
+    }
+
+    Some(AvcSpsGeometry {
+        chroma_format_idc,
+        width_in_mbs_minus1,
+        height_in_map_units_minus1,
+        frame_mbs_only,
+        crop,
+    })
+}
+
+fn skip_scaling_list(reader: &mut BitReader, size: usize) -> Option<()> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta = reader.read_se()?;
+            next_scale = (last_scale + delta + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Some(())
+}
+
+// 解析 H.265 SPS，返回显示宽高及 hvcC 所需字段
+pub fn parse_hevc_sps(sps: &[u8]) -> Option<(u32, u32, HevcSpsInfo)> {
+    let rbsp = remove_emulation_prevention(sps.get(2..)?);
+    let mut reader = BitReader::new(&rbsp);
+
+    reader.skip_bits(4)?;
+    let max_sub_layers_minus1 = reader.read_bits(3)? as usize;
+    let temporal_id_nested = reader.read_bit()? as u8;
+
+    let profile_tier_level: [u8; 12] = rbsp.get(1..13)?.try_into().ok()?;
+    reader.skip_bits(96)?;
+
+    let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        let profile_present = reader.read_bit()?;
+        let level_present = reader.read_bit()?;
+        sub_layer_flags.push((profile_present, level_present));
+    }
+    if max_sub_layers_minus1 > 0 {
+        reader.skip_bits(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present == 1 {
+            reader.skip_bits(88)?;
+        }
+        if level_present == 1 {
+            reader.skip_bits(8)?;
+        }
+    }
+
+    reader.read_ue()?;
+    let chroma_format_idc = reader.read_ue()?;
+    if chroma_format_idc == 3 {
+        reader.skip_bits(1)?;
+    }
+    let mut width = reader.read_ue()?;
+    let mut height = reader.read_ue()?;
+    if reader.read_bit()? == 1 {
+        let (sub_width, sub_height) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let left = reader.read_ue()?;
+        let right = reader.read_ue()?;
+        let top = reader.read_ue()?;
+        let bottom = reader.read_ue()?;
+        width = width.checked_sub(sub_width * (left + right))?;
+        height = height.checked_sub(sub_height * (top + bottom))?;
+    }
+    let bit_depth_luma_minus8 = reader.read_ue()? as u8;
+    let bit_depth_chroma_minus8 = reader.read_ue()? as u8;
+
+    Some((
+        width,
+        height,
+        HevcSpsInfo {
+            profile_tier_level,
+            chroma_format_idc: chroma_format_idc as u8 & 3,
+            bit_depth_luma_minus8: bit_depth_luma_minus8 & 7,
+            bit_depth_chroma_minus8: bit_depth_chroma_minus8 & 7,
+            temporal_layers: max_sub_layers_minus1 as u8 + 1,
+            temporal_id_nested,
+        },
+    ))
+}
+
+// 解析 PES 中连续的 ADTS 帧，返回编码参数与去掉帧头的原始 AAC 数据
+pub fn parse_adts_frames(data: &[u8]) -> Vec<(CodecConfig, &[u8])> {
+    let mut frames = Vec::new();
+    let mut i = 0;
+
+    while i + 7 <= data.len() {
+        if data[i] != 0xFF || data[i + 1] & 0xF0 != 0xF0 {
+            i += 1;
+            continue;
+        }
+
+        let protection_absent = data[i + 1] & 1 == 1;
+        let profile = data[i + 2] >> 6;
+        let frequency_index = (data[i + 2] >> 2) & 0x0F;
+        let channels = ((data[i + 2] & 1) << 2) | (data[i + 3] >> 6);
+        let frame_length = ((data[i + 3] as usize & 3) << 11)
+            | ((data[i + 4] as usize) << 3)
+            | (data[i + 5] as usize >> 5);
+        let header_length = if protection_absent { 7 } else { 9 };
+
+        let Some(&sample_rate) = AAC_SAMPLE_RATES.get(frequency_index as usize) else {
+            i += 1;
+            continue;
+        };
+        if frame_length <= header_length || i + frame_length > data.len() {
+            break;
+        }
+
+        frames.push((
+            CodecConfig::Aac {
+                object_type: profile + 1,
+                frequency_index,
+                sample_rate,
+                channels,
+            },
+            &data[i + header_length..i + frame_length],
+        ));
+        i += frame_length;
+    }
+
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AVC_SPS: [u8; 9] = [0x67, 0x64, 0x00, 0x28, 0xac, 0xe5, 0x05, 0x07, 0xe8];
+    // 含防竞争字节（00 00 03）的 1280x720 Main 档次 SPS
+    const HEVC_SPS: [u8; 25] = [
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x5d, 0xa0, 0x02, 0x80, 0x80, 0x2d, 0x16, 0x58,
+    ];
+
+    fn adts_frame(frequency_index: u8, channels: u8, payload: &[u8]) -> Vec<u8> {
+        let length = payload.len() + 7;
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            (1 << 6) | (frequency_index << 2) | (channels >> 2),
+            ((channels & 3) << 6) | (length >> 11) as u8,
+            (length >> 3) as u8,
+            ((length & 7) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn splits_annex_b_nal_units() {
+        let data = [
+            0, 0, 0, 1, 0x09, 0xF0, 0, 0, 1, 0x65, 0x88, 0, 0, 0, 0, 1, 0x41, 0,
+        ];
+        assert_eq!(
+            split_nal_units(&data),
+            [&[0x09, 0xF0][..], &[0x65, 0x88][..], &[0x41][..]]
+        );
+        assert!(split_nal_units(&[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn removes_emulation_prevention_bytes() {
+        assert_eq!(
+            remove_emulation_prevention(&[0, 0, 3, 1, 0, 0, 3, 0, 3]),
+            [0, 0, 1, 0, 0, 0, 3]
+        );
+    }
+
+    #[test]
+    fn parses_avc_sps_dimensions() {
+        assert_eq!(parse_avc_sps(&AVC_SPS).unwrap(), (320, 240));
+        assert!(matches!(
+            parse_avc_sps(&AVC_SPS[..4]),
+            Err(M3u8Error::MergeError(_))
+        ));
+    }
+
+    // 按 Exp-Golomb 编码写入无符号数
+    fn push_ue(bits: &mut Vec<u8>, value: u32) {
+        let code = value as u64 + 1;
+        let length = 64 - code.leading_zeros();
+        bits.extend(std::iter::repeat_n(0, length as usize - 1));
+        bits.extend((0..length).rev().map(|i| (code >> i) as u8 & 1));
+    }
+
+    // 生成 Baseline 档次、帧编码的 SPS
+    fn baseline_sps(
+        width_in_mbs_minus1: u32,
+        height_in_mbs_minus1: u32,
+        crop: [u32; 4],
+    ) -> Vec<u8> {
+        let mut bits: Vec<u8> = Vec::new();
+        for byte in [66u8, 0, 30] {
+            bits.extend((0..8).rev().map(|i| (byte >> i) & 1));
+        }
+        // sps_id、log2_max_frame_num_minus4、pic_order_cnt_type=2、max_num_ref_frames
+        for value in [0, 0, 2, 0] {
+            push_ue(&mut bits, value);
+        }
+        bits.push(0);
+        push_ue(&mut bits, width_in_mbs_minus1);
+        push_ue(&mut bits, height_in_mbs_minus1);
+        // frame_mbs_only、direct_8x8_inference、frame_cropping
+        bits.extend([1, 0, 1]);
+        for value in crop {
+            push_ue(&mut bits, value);
+        }
+        bits.push(1);
+
+        // 与编码器一样插入防竞争字节
+        let mut sps = vec![0x67];
+        let mut zeros = 0;
+        for chunk in bits.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, bit)| byte | bit << (7 - i));
+            if zeros >= 2 && byte <= 3 {
+                sps.push(3);
+                zeros = 0;
+            }
+            sps.push(byte);
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+        }
+        sps
+    }
+
+    #[test]
+    fn applies_avc_frame_cropping() {
+        assert_eq!(
+            parse_avc_sps(&baseline_sps(119, 67, [0, 0, 0, 4])).unwrap(),
+            (1920, 1080)
+        );
+    }
+
+    #[test]
+    fn rejects_overflowing_avc_dimensions() {
+        for sps in [
+            baseline_sps(u32::MAX - 1, 0, [0; 4]),
+            baseline_sps(0, u32::MAX / 8, [0; 4]),
+            baseline_sps(19, 14, [200, 0, 0, 0]),
+            baseline_sps(19, 14, [0, 0, u32::MAX - 1, 1]),
+        ] {
+            let result = parse_avc_sps(&sps);
+            assert!(
+                matches!(result, Err(M3u8Error::MergeError(_))),
+                "{:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn parses_hevc_sps() {
+        let (width, height, info) = parse_hevc_sps(&HEVC_SPS).unwrap();
+        assert_eq!((width, height), (1280, 720));
+        assert_eq!(info.chroma_format_idc, 1);
+        assert_eq!(info.temporal_layers, 1);
+        assert_eq!(info.temporal_id_nested, 1);
+        assert_eq!(info.profile_tier_level[0], 0x01);
+        // general_level_idc 93 对应 Level 3.1
+        assert_eq!(info.profile_tier_level[11], 93);
+    }
+
+    #[test]
+    fn builds_avc_decoder_config() {
+        let config = CodecConfig::Avc {
+            sps: AVC_SPS.to_vec(),
+            pps: vec![0x68, 0xee, 0x3c, 0x80],
+            width: 320,
+            height: 240,
+        };
+        let avcc = config.decoder_config();
+        assert_eq!(avcc[..6], [1, 0x64, 0x00, 0x28, 0xFF, 0xE1]);
+        assert_eq!(avcc[6..8], [0, 9]);
+        assert_eq!(avcc[8..17], AVC_SPS);
+        assert_eq!(avcc[17..], [1, 0, 4, 0x68, 0xee, 0x3c, 0x80]);
+
+        let data = config.with_parameter_sets(&[0, 0, 0, 1, 0x65]);
+        assert_eq!(data[..4], [0, 0, 0, 9]);
+        assert_eq!(data[data.len() - 5..], [0, 0, 0, 1, 0x65]);
+    }
+
+    #[test]
+    fn parses_adts_frames() {
+        let mut data = adts_frame(3, 2, &[1; 10]);
+        data.extend(adts_frame(3, 2, &[2; 4]));
+        // 不完整的帧被忽略
+        data.extend(&adts_frame(3, 2, &[3; 8])[..9]);
+
+        let frames = parse_adts_frames(&data);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            frames[0].0,
+            CodecConfig::Aac {
+                object_type: 2,
+                frequency_index: 3,
+                sample_rate: 48000,
+                channels: 2,
+            }
+        );
+        assert_eq!(frames[0].1, [1; 10]);
+        assert_eq!(frames[1].1, [2; 4]);
+        // AudioSpecificConfig: AAC LC, 48kHz, 双声道
+        assert_eq!(frames[0].0.decoder_config(), [0x11, 0x90]);
+    }
+}
@@ -0,0 +1,270 @@
+// 分片 MP4 写出：先写出不含样本的 moov，之后每个视频关键帧开启一个 moof + mdat 分片
+use super::codec::{CodecConfig, AAC_FRAME_SAMPLES};
+use super::mp4::{
+    build_ftyp, build_mdia, build_mvhd, build_sample_entry, build_tkhd, compatible_brands,
+    full_box, mp4_box, track_headers, track_timescale, Fields, DEFAULT_FRAME_DURATION,
+    MAX_FRAME_DURATION,
+};
+use super::ts::{Sample, Track, TS_TIMESCALE};
+use crate::error::M3u8Error;
+use std::io::Write;
+
+// 没有视频轨道时的分片时长上限
+const MAX_FRAGMENT_DURATION: i64 = 2 * TS_TIMESCALE as i64;
+
+// 关键帧与非关键帧的 sample_flags
+const SYNC_SAMPLE_FLAGS: u32 = 0x02000000;
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x01010000;
+
+// trun 中的单个样本: (时长, 大小, 标志, 显示时间偏移)
+type RunEntry = (u32, u32, u32, i32);
+// 一条轨道在分片中的样本: (轨道号, 起始解码时间, 样本列表)
+type TrackRun = (u32, u64, Vec<RunEntry>);
+
+struct FragmentTrack {
+    track_id: u32,
+    config: CodecConfig,
+    // 下一个分片的起始解码时间（轨道时间刻度）
+    decode_time: Option<u64>,
+    last_duration: i64,
+}
+
+pub struct FragmentedMp4Writer<W: Write> {
+    output: W,
+    // 解复用轨道序号到输出轨道的映射，没有编码参数的轨道不写出
+    tracks: Vec<Option<FragmentTrack>>,
+    video_track: Option<usize>,
+    // 作为解码时间零点的时间戳（90kHz）
+    start: i64,
+    pending: Vec<Sample>,
+    sequence: u32,
+}
+
+impl<W: Write> FragmentedMp4Writer<W> {
+    pub fn new(mut output: W, tracks: &[Track], start: i64) -> Result<Self, M3u8Error> {
+        let written: Vec<&Track> = tracks
+            .iter()
+            .filter(|track| track.config.is_some())
+            .collect();
+        let headers = track_headers(&written);
+        let mut outputs = Vec::with_capacity(tracks.len());
+        let mut video_track = None;
+        let mut traks = Vec::new();
+        let mut trexs = Vec::new();
+
+        for (index, track) in tracks.iter().enumerate() {
+            let Some(config) = track.config.clone() else {
+                outputs.push(None);
+                continue;
+            };
+            let track_id = traks.len() as u32 + 1;
+            if config.is_video() && video_track.is_none() {
+                video_track = Some(index);
+            }
+
+            // 样本表为空，样本信息全部位于各分片的 trun 中
+            let stbl = [
+                full_box(
+                    b"stsd",
+                    0,
+                    0,
+                    &Fields::new()
+                        .u32(1)
+                        .bytes(&build_sample_entry(&config, track.parameter_sets_in_band))
+                        .0,
+                ),
+                full_box(b"stts", 0, 0, &0u32.to_be_bytes()),
+                full_box(b"stsc", 0, 0, &0u32.to_be_bytes()),
+                full_box(b"stsz", 0, 0, &[0; 8]),
+                full_box(b"stco", 0, 0, &0u32.to_be_bytes()),
+            ]
+            .concat();
+            traks.push(mp4_box(
+                b"trak",
+                &[
+                    build_tkhd(track_id, &config, 0, headers[traks.len()]),
+                    build_mdia(&config, 0, &stbl, track.label.as_ref()),
+                ]
+                .concat(),
+            ));
+            trexs.push(full_box(
+                b"trex",
+                0,
+                0,
+                &Fields::new().u32(track_id).u32(1).u32(0).u32(0).u32(0).0,
+            ));
+
+            outputs.push(Some(FragmentTrack {
+                track_id,
+                config,
+                decode_time: None,
+                last_duration: DEFAULT_FRAME_DURATION,
+            }));
+        }
+        if traks.is_empty() {
+            return Err(M3u8Error::MergeError(
+                "片段中没有可识别的音视频数据".to_string(),
+            ));
+        }
+
+        let mut moov = build_mvhd(0, traks.len() as u32 + 1);
+        moov.extend(traks.concat());
+        moov.extend(mp4_box(b"mvex", &trexs.concat()));
+
+        output.write_all(&build_ftyp(
+            b"iso6",
+            &compatible_brands(&[b"iso6", b"iso5"], &written),
+        ))?;
+        output.write_all(&mp4_box(b"moov", &moov))?;
+
+        Ok(Self {
+            output,
+            tracks: outputs,
+            video_track,
+            start,
+            pending: Vec::new(),
+            sequence: 0,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: &Sample) -> Result<(), M3u8Error> {
+        if !matches!(self.tracks.get(sample.track), Some(Some(_))) {
+            return Ok(());
+        }
+
+        let split = match (self.video_track, self.pending.first()) {
+            (_, None) => false,
+            (Some(video_track), Some(_)) => sample.track == video_track && sample.keyframe,
+            (None, Some(first)) => sample.dts - first.dts >= MAX_FRAGMENT_DURATION,
+        };
+        if split {
+            let next_video_dts = self.video_track.map(|_| sample.dts);
+            self.flush_fragment(next_video_dts)?;
+        }
+
+        self.pending.push(sample.clone());
+        Ok(())
+    }
+
+    // 写出剩余样本，返回写出的轨道数
+    pub fn finish(mut self) -> Result<usize, M3u8Error> {
+        self.flush_fragment(None)?;
+        self.output.flush()?;
+        Ok(self.tracks.iter().flatten().count())
+    }
+
+    fn flush_fragment(&mut self, next_video_dts: Option<i64>) -> Result<(), M3u8Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        self.sequence += 1;
+
+        // 按轨道分组，每条轨道生成一个 traf，样本数据在 mdat 中按轨道顺序排列
+        let mut groups: Vec<(usize, Vec<&Sample>)> = Vec::new();
+        for sample in &pending {
+            match groups.iter_mut().find(|(track, _)| *track == sample.track) {
+                Some((_, samples)) => samples.push(sample),
+                None => groups.push((sample.track, vec![sample])),
+            }
+        }
+        groups.sort_by_key(|(track, _)| *track);
+
+        let mut runs = Vec::new();
+        for (index, samples) in &groups {
+            let next_dts = next_video_dts.filter(|_| Some(*index) == self.video_track);
+            runs.push(self.build_run(*index, samples, next_dts));
+        }
+
+        // moof 长度与数据偏移的取值无关，先计算长度再填入偏移
+        let moof_length = self.build_moof(&runs, 0).len();
+        let moof = self.build_moof(&runs, moof_length as u32 + 8);
+
+        let data_length: usize = pending.iter().map(|sample| sample.data.len()).sum();
+        self.output.write_all(&moof)?;
+        self.output
+            .write_all(&(data_length as u32 + 8).to_be_bytes())?;
+        self.output.write_all(b"mdat")?;
+        for (_, samples) in &groups {
+            for sample in samples {
+                self.output.write_all(&sample.data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // 生成一条轨道在当前分片中的样本信息
+    fn build_run(&mut self, index: usize, samples: &[&Sample], next_dts: Option<i64>) -> TrackRun {
+        let start = self.start;
+        let track = self.tracks[index].as_mut().unwrap();
+        let timescale = track_timescale(&track.config);
+        let decode_time = *track.decode_time.get_or_insert_with(|| {
+            ((samples[0].dts - start).max(0) * timescale as i64 / TS_TIMESCALE as i64) as u64
+        });
+
+        let mut entries = Vec::with_capacity(samples.len());
+        for (i, sample) in samples.iter().enumerate() {
+            let duration = if track.config.is_video() {
+                let next = samples.get(i + 1).map(|next| next.dts).or(next_dts);
+                match next.map(|next| next - sample.dts) {
+                    Some(duration) if duration > 0 && duration <= MAX_FRAME_DURATION => {
+                        track.last_duration = duration;
+                        duration
+                    }
+                    _ => track.last_duration,
+                }
+            } else {
+                AAC_FRAME_SAMPLES as i64
+            };
+            let flags = if sample.keyframe {
+                SYNC_SAMPLE_FLAGS
+            } else {
+                NON_SYNC_SAMPLE_FLAGS
+            };
+            let composition_offset = if track.config.is_video() {
+                (sample.pts - sample.dts) as i32
+            } else {
+                0
+            };
+            entries.push((
+                duration as u32,
+                sample.data.len() as u32,
+                flags,
+                composition_offset,
+            ));
+        }
+
+        let total: u64 = entries.iter().map(|(duration, ..)| *duration as u64).sum();
+        track.decode_time = Some(decode_time + total);
+        (track.track_id, decode_time, entries)
+    }
+
+    fn build_moof(&self, runs: &[TrackRun], data_offset: u32) -> Vec<u8> {
+        let mut moof = full_box(b"mfhd", 0, 0, &self.sequence.to_be_bytes());
+        let mut offset = data_offset;
+
+        for (track_id, decode_time, entries) in runs {
+            // default-base-is-moof，数据偏移相对于 moof 起始位置
+            let tfhd = full_box(b"tfhd", 0, 0x020000, &track_id.to_be_bytes());
+            let tfdt = full_box(b"tfdt", 1, 0, &decode_time.to_be_bytes());
+            let trun = entries.iter().fold(
+                Fields::new().u32(entries.len() as u32).u32(offset),
+                |fields, (duration, size, flags, composition_offset)| {
+                    fields
+                        .u32(*duration)
+                        .u32(*size)
+                        .u32(*flags)
+                        .u32(*composition_offset as u32)
+                },
+            );
+            // 标志: 数据偏移、时长、大小、sample_flags、显示时间偏移
+            let trun = full_box(b"trun", 1, 0x000F01, &trun.0);
+
+            moof.extend(mp4_box(b"traf", &[tfhd, tfdt, trun].concat()));
+            offset += entries.iter().map(|(_, size, ..)| *size).sum::<u32>();
+        }
+
+        mp4_box(b"moof", &moof)
+    }
+}
@@ -0,0 +1,375 @@
+// Matroska 写出：头部在写入样本前生成，簇按视频关键帧切分，结束时回填时长、索引与 SeekHead
+use super::codec::CodecConfig;
+use super::ts::{Sample, Track, TrackLabel, TS_TIMESCALE};
+use crate::error::M3u8Error;
+use std::io::{Seek, SeekFrom, Write};
+
+const EBML: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549A966;
+const TRACKS: u32 = 0x1654AE6B;
+const CLUSTER: u32 = 0x1F43B675;
+const CUES: u32 = 0x1C53BB6B;
+const VOID: u32 = 0xEC;
+const DURATION: u32 = 0x4489;
+
+// 时间戳单位为毫秒
+const TIMESTAMP_SCALE: u64 = 1_000_000;
+// 预留给 SeekHead 的空间
+const SEEK_HEAD_RESERVED: usize = 100;
+// 没有视频关键帧时的簇时长上限，同时保证块的相对时间戳在 i16 范围内
+const MAX_CLUSTER_DURATION: i64 = 5000;
+
+// EBML 可变长度整数
+fn vint(value: u64) -> Vec<u8> {
+    let length = (1..=8)
+        .find(|&n| value < (1u64 << (7 * n)) - 1)
+        .unwrap_or(8);
+    let marked = value | (1u64 << (7 * length));
+    marked.to_be_bytes()[8 - length..].to_vec()
+}
+
+fn element_id(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().position(|&b| b != 0).unwrap_or(3);
+    bytes[skip..].to_vec()
+}
+
+fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+    [element_id(id), vint(payload.len() as u64), payload.to_vec()].concat()
+}
+
+fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().position(|&b| b != 0).unwrap_or(7);
+    element(id, &bytes[skip..])
+}
+
+fn float_element(id: u32, value: f64) -> Vec<u8> {
+    element(id, &value.to_be_bytes())
+}
+
+fn string_element(id: u32, value: &str) -> Vec<u8> {
+    element(id, value.as_bytes())
+}
+
+// 填充指定长度的 Void 元素
+fn void_element(length: usize) -> Vec<u8> {
+    let payload_length = length.saturating_sub(2);
+    let mut data = element_id(VOID);
+    data.push(0x80 | payload_length as u8);
+    data.resize(length, 0);
+    data
+}
+
+// 备选媒体的轨道另外写入名称、语言及默认标志
+fn track_entry(number: u64, config: &CodecConfig, label: Option<&TrackLabel>) -> Vec<u8> {
+    let (track_type, codec_id) = match config {
+        CodecConfig::Avc { .. } => (1, "V_MPEG4/ISO/AVC"),
+        CodecConfig::Hevc { .. } => (1, "V_MPEGH/ISO/HEVC"),
+        CodecConfig::Aac { .. } => (2, "A_AAC"),
+    };
+
+    let mut entry = [
+        uint_element(0xD7, number),
+        uint_element(0x73C5, number),
+        uint_element(0x83, track_type),
+        uint_element(0x9C, 0),
+        string_element(0x22B59C, "und"),
+        string_element(0x86, codec_id),
+        element(0x63A2, &config.decoder_config()),
+    ]
+    .concat();
+
+    if let Some(label) = label {
+        entry.extend(string_element(0x536E, &label.name));
+        entry.extend(uint_element(0x88, label.default as u64));
+        // Language 只支持 ISO 639-2，播放列表中的 BCP 47 语言标签写入 LanguageBCP47
+        if let Some(language) = &label.language {
+            entry.extend(string_element(0x22B59D, language));
+        }
+    }
+
+    match config {
+        CodecConfig::Aac {
+            sample_rate,
+            channels,
+            ..
+        } => entry.extend(element(
+            0xE1,
+            &[
+                float_element(0xB5, *sample_rate as f64),
+                uint_element(0x9F, *channels as u64),
+            ]
+            .concat(),
+        )),
+        _ => {
+            let (width, height) = config.dimensions().unwrap_or((0, 0));
+            entry.extend(element(
+                0xE0,
+                &[
+                    uint_element(0xB0, width as u64),
+                    uint_element(0xBA, height as u64),
+                ]
+                .concat(),
+            ));
+        }
+    }
+
+    element(0xAE, &entry)
+}
+
+// 正在写入的簇
+struct Cluster {
+    timestamp: i64,
+    blocks: Vec<u8>,
+    keyframe: bool,
+}
+
+pub struct MkvWriter<W: Write + Seek> {
+    output: W,
+    // Segment 数据起始位置，SeekHead 与 Cues 中的位置均相对于此
+    segment_start: u64,
+    position: u64,
+    seek_head_position: u64,
+    duration_position: u64,
+    info_position: u64,
+    tracks_position: u64,
+    // 解复用轨道序号到 Matroska 轨道号的映射，没有编码参数的轨道不写出
+    track_numbers: Vec<Option<u64>>,
+    // 第一条视频轨道，用于切分簇与生成索引
+    video_track: Option<(usize, u64)>,
+    // 作为时间零点的显示时间（90kHz）
+    start: i64,
+    cluster: Option<Cluster>,
+    // (时间, 轨道号, 簇位置)
+    cues: Vec<(u64, u64, u64)>,
+    duration: i64,
+}
+
+impl<W: Write + Seek> MkvWriter<W> {
+    pub fn new(mut output: W, tracks: &[Track], start: i64) -> Result<Self, M3u8Error> {
+        let ebml = element(
+            EBML,
+            &[
+                uint_element(0x4286, 1),
+                uint_element(0x42F7, 1),
+                uint_element(0x42F2, 4),
+                uint_element(0x42F3, 8),
+                string_element(0x4282, "matroska"),
+                uint_element(0x4287, 4),
+                uint_element(0x4285, 2),
+            ]
+            .concat(),
+        );
+
+        let mut track_numbers = Vec::with_capacity(tracks.len());
+        let mut video_track = None;
+        let mut entries = Vec::new();
+        for (index, track) in tracks.iter().enumerate() {
+            match &track.config {
+                Some(config) => {
+                    let number = entries.len() as u64 + 1;
+                    entries.push(track_entry(number, config, track.label.as_ref()));
+                    track_numbers.push(Some(number));
+                    if config.is_video() && video_track.is_none() {
+                        video_track = Some((index, number));
+                    }
+                }
+                None => track_numbers.push(None),
+            }
+        }
+        if entries.is_empty() {
+            return Err(M3u8Error::MergeError(
+                "片段中没有可识别的音视频数据".to_string(),
+            ));
+        }
+
+        // Segment 长度未知，结束时回填
+        let mut header = ebml;
+        header.extend(element_id(SEGMENT));
+        header.extend([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let segment_start = header.len() as u64;
+
+        let seek_head_position = header.len() as u64;
+        header.extend(void_element(SEEK_HEAD_RESERVED));
+
+        let info_position = header.len() as u64;
+        let version = concat!("rust-m3u8 ", env!("CARGO_PKG_VERSION"));
+        let info = element(
+            INFO,
+            &[
+                uint_element(0x2AD7B1, TIMESTAMP_SCALE),
+                string_element(0x4D80, version),
+                string_element(0x5741, version),
+                float_element(DURATION, 0.0),
+            ]
+            .concat(),
+        );
+        // Duration 为 Info 的最后一个元素，浮点值位于末尾 8 字节
+        let duration_position = info_position + info.len() as u64 - 8;
+        header.extend(info);
+
+        let tracks_position = header.len() as u64;
+        header.extend(element(TRACKS, &entries.concat()));
+
+        output.write_all(&header)?;
+
+        Ok(Self {
+            output,
+            segment_start,
+            position: header.len() as u64,
+            seek_head_position,
+            duration_position,
+            info_position,
+            tracks_position,
+            track_numbers,
+            video_track,
+            start,
+            cluster: None,
+            cues: Vec::new(),
+            duration: 0,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: &Sample) -> Result<(), M3u8Error> {
+        let Some(Some(number)) = self.track_numbers.get(sample.track).copied() else {
+            return Ok(());
+        };
+        let timestamp = ((sample.pts - self.start) * 1000 / TS_TIMESCALE as i64).max(0);
+        self.duration = self.duration.max(timestamp);
+
+        // 视频关键帧开启新簇，便于定位
+        let is_video_keyframe =
+            sample.keyframe && self.video_track.map(|(index, _)| index) == Some(sample.track);
+        let start_cluster = match &self.cluster {
+            None => true,
+            Some(cluster) => {
+                (is_video_keyframe && !cluster.blocks.is_empty())
+                    || timestamp - cluster.timestamp > MAX_CLUSTER_DURATION
+                    || timestamp < cluster.timestamp - i16::MAX as i64
+            }
+        };
+        if start_cluster {
+            self.flush_cluster()?;
+            self.cluster = Some(Cluster {
+                timestamp,
+                blocks: Vec::new(),
+                keyframe: is_video_keyframe,
+            });
+        }
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative = (timestamp - cluster.timestamp) as i16;
+        let mut block = vint(number);
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if sample.keyframe { 0x80 } else { 0 });
+        block.extend_from_slice(&sample.data);
+        cluster.blocks.extend(element(0xA3, &block));
+
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> Result<(), M3u8Error> {
+        let Some(cluster) = self.cluster.take() else {
+            return Ok(());
+        };
+
+        let cluster_position = self.position - self.segment_start;
+        if let Some((_, number)) = self.video_track.filter(|_| cluster.keyframe) {
+            self.cues
+                .push((cluster.timestamp as u64, number, cluster_position));
+        }
+
+        let data = element(
+            CLUSTER,
+            &[uint_element(0xE7, cluster.timestamp as u64), cluster.blocks].concat(),
+        );
+        self.output.write_all(&data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    // 写出索引，回填时长、SeekHead 与 Segment 长度
+    pub fn finish(mut self) -> Result<usize, M3u8Error> {
+        self.flush_cluster()?;
+
+        let mut seeks = vec![
+            (INFO, self.info_position - self.segment_start),
+            (TRACKS, self.tracks_position - self.segment_start),
+        ];
+        if !self.cues.is_empty() {
+            let points: Vec<u8> = self
+                .cues
+                .iter()
+                .flat_map(|(time, track, position)| {
+                    element(
+                        0xBB,
+                        &[
+                            uint_element(0xB3, *time),
+                            element(
+                                0xB7,
+                                &[uint_element(0xF7, *track), uint_element(0xF1, *position)]
+                                    .concat(),
+                            ),
+                        ]
+                        .concat(),
+                    )
+                })
+                .collect();
+            let cues = element(CUES, &points);
+            seeks.push((CUES, self.position - self.segment_start));
+            self.output.write_all(&cues)?;
+            self.position += cues.len() as u64;
+        }
+
+        let seek_head = element(
+            SEEK_HEAD,
+            &seeks
+                .iter()
+                .flat_map(|(id, position)| {
+                    element(
+                        SEEK,
+                        &[
+                            element(SEEK_ID, &id.to_be_bytes()),
+                            element(SEEK_POSITION, &position.to_be_bytes()),
+                        ]
+                        .concat(),
+                    )
+                })
+                .collect::<Vec<u8>>(),
+        );
+        // Void 元素至少 2 字节，剩余 1 字节时无法填充
+        let padding = SEEK_HEAD_RESERVED
+            .checked_sub(seek_head.len())
+            .filter(|length| *length != 1)
+            .map(void_element)
+            .ok_or_else(|| {
+                M3u8Error::MergeError(format!(
+                    "SeekHead 长度 {} 超出预留空间 {}",
+                    seek_head.len(),
+                    SEEK_HEAD_RESERVED
+                ))
+            })?;
+        self.output.seek(SeekFrom::Start(self.seek_head_position))?;
+        self.output.write_all(&[seek_head, padding].concat())?;
+
+        self.output.seek(SeekFrom::Start(self.duration_position))?;
+        self.output
+            .write_all(&(self.duration as f64).to_be_bytes())?;
+
+        let segment_size = self.position - self.segment_start;
+        self.output.seek(SeekFrom::Start(self.segment_start - 8))?;
+        self.output
+            .write_all(&(segment_size | (1u64 << 56)).to_be_bytes())?;
+
+        self.output.seek(SeekFrom::Start(self.position))?;
+        self.output.flush()?;
+
+        Ok(self.track_numbers.iter().flatten().count())
+    }
+}
@@ -0,0 +1,894 @@
+pub mod codec;
+pub mod fmp4;
+pub mod fmp4_demux;
+pub mod mkv;
+pub mod mp4;
+pub mod timeline;
+pub mod ts;
+
+use crate::error::M3u8Error;
+use crate::time_range::ClipWindow;
+use fmp4::FragmentedMp4Writer;
+use fmp4_demux::Fmp4Demuxer;
+use mkv::MkvWriter;
+use mp4::Mp4Writer;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use timeline::Timeline;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use ts::{Sample, Track, TrackLabel, TsDemuxer};
+
+// 合并工具：优先调用 ffmpeg，或始终使用内置封装
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MergeMode {
+    // 调用 ffmpeg 合并，不可用或失败时回退到内置封装
+    #[default]
+    Ffmpeg,
+    // 内置解复用并封装，无需 ffmpeg
+    Native,
+}
+
+impl FromStr for MergeMode {
+    type Err = M3u8Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ffmpeg" => Ok(MergeMode::Ffmpeg),
+            "native" => Ok(MergeMode::Native),
+            other => Err(M3u8Error::ParseError(format!(
+                "未知的合并方式: {}，可选 ffmpeg/native",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MergeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MergeMode::Ffmpeg => "ffmpeg",
+            MergeMode::Native => "native",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// 输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    // 直接拼接的 MPEG-TS
+    Ts,
+    #[default]
+    Mp4,
+    Mkv,
+    // 分片 MP4（moof + mdat）
+    Fmp4,
+    // 片段与本地播放列表组成的目录
+    HlsDir,
+}
+
+impl OutputFormat {
+    // 默认扩展名，hls-dir 输出为目录，没有扩展名
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Ts => Some("ts"),
+            OutputFormat::Mp4 | OutputFormat::Fmp4 => Some("mp4"),
+            OutputFormat::Mkv => Some("mkv"),
+            OutputFormat::HlsDir => None,
+        }
+    }
+
+    // 根据扩展名推断输出格式
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ts" | "m2ts" => Some(OutputFormat::Ts),
+            "mp4" | "m4v" => Some(OutputFormat::Mp4),
+            "mkv" => Some(OutputFormat::Mkv),
+            _ => None,
+        }
+    }
+
+    // 确定输出格式与路径：
+    // 指定格式时，输出名带扩展名则原样保留，否则补上该格式的扩展名；
+    // 未指定格式时，按可识别的扩展名推断格式，否则使用默认格式并补上扩展名
+    pub fn resolve_output(output: &str, format: Option<OutputFormat>) -> (OutputFormat, PathBuf) {
+        let path = PathBuf::from(output);
+        let extension = path.extension().and_then(|e| e.to_str());
+
+        let format = match (format, extension) {
+            (Some(format), Some(_)) => return (format, path),
+            (Some(format), None) => format,
+            (None, extension) => match extension.and_then(Self::from_extension) {
+                Some(format) => return (format, path),
+                None => OutputFormat::default(),
+            },
+        };
+
+        match format.extension() {
+            Some(extension) => (format, PathBuf::from(format!("{}.{}", output, extension))),
+            None => (format, path),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = M3u8Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ts" => Ok(OutputFormat::Ts),
+            "mp4" => Ok(OutputFormat::Mp4),
+            "mkv" => Ok(OutputFormat::Mkv),
+            "fmp4" => Ok(OutputFormat::Fmp4),
+            "hls-dir" => Ok(OutputFormat::HlsDir),
+            other => Err(M3u8Error::ParseError(format!(
+                "未知的输出格式: {}，可选 ts/mp4/mkv/fmp4/hls-dir",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Ts => "ts",
+            OutputFormat::Mp4 => "mp4",
+            OutputFormat::Mkv => "mkv",
+            OutputFormat::Fmp4 => "fmp4",
+            OutputFormat::HlsDir => "hls-dir",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// 一路输入：主播放列表或备选媒体播放列表（EXT-X-MEDIA）下载的片段
+#[derive(Debug, Clone, Default)]
+pub struct MergeInput {
+    // 临时目录中的文件名前缀，片段为 {前缀}seg{序号}.ts，初始化片段为 {前缀}init{序号}.mp4
+    pub prefix: String,
+    // 各片段的序号（被过滤的片段不在其中）
+    pub sequences: Vec<usize>,
+    // 各片段时长，用于生成 hls-dir 的播放列表及交错读取各路输入
+    pub segment_durations: Vec<f64>,
+    // 各片段前是否有不连续标记，合并时按不连续点分组处理时间戳
+    pub discontinuities: Vec<bool>,
+    // 各片段使用的初始化片段序号（EXT-X-MAP），没有时为 None
+    pub init_segments: Vec<Option<usize>>,
+    // 备选媒体的轨道名称与语言，主播放列表为 None
+    pub label: Option<TrackLabel>,
+}
+
+impl MergeInput {
+    fn segment_path(&self, temp_dir: &Path, sequence: usize) -> PathBuf {
+        temp_dir.join(format!("{}seg{:06}.ts", self.prefix, sequence))
+    }
+
+    fn init_path(&self, temp_dir: &Path, index: usize) -> PathBuf {
+        temp_dir.join(format!("{}init{:04}.mp4", self.prefix, index))
+    }
+
+    fn is_discontinuity(&self, index: usize) -> bool {
+        self.discontinuities.get(index).copied().unwrap_or(false)
+    }
+
+    fn init_segment(&self, index: usize) -> Option<usize> {
+        self.init_segments.get(index).copied().flatten()
+    }
+
+    fn has_init_segments(&self) -> bool {
+        self.init_segments.iter().any(Option::is_some)
+    }
+
+    // 按不连续点把片段分组，组内时间戳连续；start 为片段在播放列表中的开始时间
+    fn segment_groups(&self, temp_dir: &Path) -> Vec<Vec<SegmentFile>> {
+        let mut groups: Vec<Vec<SegmentFile>> = Vec::new();
+        let mut start = 0.0;
+        for (index, sequence) in self.sequences.iter().enumerate() {
+            let file = SegmentFile {
+                path: self.segment_path(temp_dir, *sequence),
+                init: self
+                    .init_segment(index)
+                    .map(|init| self.init_path(temp_dir, init)),
+                start,
+            };
+            start += self.segment_durations.get(index).copied().unwrap_or(0.0);
+            match groups.last_mut() {
+                Some(group) if !self.is_discontinuity(index) => group.push(file),
+                _ => groups.push(vec![file]),
+            }
+        }
+        groups
+    }
+
+    // 依次写出所有片段，初始化片段变化时先写出新的初始化片段
+    async fn write_segments(
+        output: &mut fs::File,
+        segments: &[SegmentFile],
+    ) -> Result<(), M3u8Error> {
+        let mut current_init = None;
+        for segment in segments {
+            if let Some(init) = segment
+                .init
+                .as_ref()
+                .filter(|init| current_init != Some(*init))
+            {
+                output.write_all(&fs::read(init).await?).await?;
+                current_init = Some(init);
+            }
+            output.write_all(&fs::read(&segment.path).await?).await?;
+        }
+        Ok(())
+    }
+}
+
+// 临时目录中的一个片段及其初始化片段
+#[derive(Debug, Clone)]
+struct SegmentFile {
+    path: PathBuf,
+    init: Option<PathBuf>,
+    // 在播放列表中的开始时间（秒）
+    start: f64,
+}
+
+// 一组片段：各路输入在同一段连续时间内的片段，下标为输入序号
+type SegmentGroup = Vec<Vec<SegmentFile>>;
+
+// 内置封装的输入
+struct RemuxInput {
+    groups: Vec<SegmentGroup>,
+    // 各路输入的轨道标签
+    labels: Vec<Option<TrackLabel>>,
+    clip: Option<ClipWindow>,
+}
+
+// 把各组片段封装到输出文件，返回写出的轨道数
+type RemuxFn = fn(&RemuxInput, &Path) -> Result<usize, M3u8Error>;
+
+// 一路输入的解复用器，按片段内容选择 MPEG-TS 或 fMP4
+enum Demuxer {
+    Ts(TsDemuxer),
+    Fmp4(Fmp4Demuxer),
+}
+
+struct SegmentDemuxer {
+    demuxer: Demuxer,
+    // 最近写入的初始化片段
+    init: Option<PathBuf>,
+}
+
+impl SegmentDemuxer {
+    // 以同步字节开头且每 188 字节重复的数据为 MPEG-TS，否则按 fMP4 处理
+    fn detect(data: &[u8]) -> Self {
+        let is_ts = data.first() == Some(&0x47) && data.get(188).is_none_or(|byte| *byte == 0x47);
+        let demuxer = if is_ts {
+            Demuxer::Ts(TsDemuxer::new())
+        } else {
+            Demuxer::Fmp4(Fmp4Demuxer::new())
+        };
+        Self {
+            demuxer,
+            init: None,
+        }
+    }
+
+    fn push(&mut self, segment: &SegmentFile, data: &[u8]) -> Result<(), M3u8Error> {
+        if let Some(init) = segment
+            .init
+            .as_ref()
+            .filter(|init| self.init.as_ref() != Some(init))
+        {
+            let init_data = std::fs::read(init)?;
+            self.push_data(&init_data)
+                .map_err(|e| M3u8Error::MergeError(format!("{:?}: {}", init, e)))?;
+            self.init = Some(init.clone());
+        }
+        self.push_data(data)
+            .map_err(|e| M3u8Error::MergeError(format!("{:?}: {}", segment.path, e)))
+    }
+
+    fn push_data(&mut self, data: &[u8]) -> Result<(), M3u8Error> {
+        match &mut self.demuxer {
+            Demuxer::Ts(demuxer) => demuxer.push(data),
+            Demuxer::Fmp4(demuxer) => demuxer.push(data),
+        }
+    }
+
+    fn flush(&mut self) {
+        match &mut self.demuxer {
+            Demuxer::Ts(demuxer) => demuxer.flush(),
+            Demuxer::Fmp4(demuxer) => demuxer.flush(),
+        }
+    }
+
+    fn tracks(&self) -> &[Track] {
+        match &self.demuxer {
+            Demuxer::Ts(demuxer) => demuxer.tracks(),
+            Demuxer::Fmp4(demuxer) => demuxer.tracks(),
+        }
+    }
+
+    fn take_samples(&mut self) -> Vec<Sample> {
+        match &mut self.demuxer {
+            Demuxer::Ts(demuxer) => std::iter::from_fn(|| demuxer.pop_sample()).collect(),
+            Demuxer::Fmp4(demuxer) => std::iter::from_fn(|| demuxer.pop_sample()).collect(),
+        }
+    }
+}
+
+pub struct VideoMerger {
+    temp_dir: PathBuf,
+    output_path: PathBuf,
+    segments: usize,
+    // 主播放列表的片段
+    main: MergeInput,
+    // 备选媒体播放列表的片段，封装时作为额外的轨道
+    renditions: Vec<MergeInput>,
+    // 精确裁剪窗口，相对第一个片段开头
+    clip: Option<ClipWindow>,
+}
+
+impl VideoMerger {
+    pub async fn new(
+        temp_dir: &Path,
+        output_path: &Path,
+        sequences: &[usize],
+    ) -> Result<Self, M3u8Error> {
+        // 创建file_list.txt
+        let file_list_path = temp_dir.join("file_list.txt");
+        let mut file_list_content = String::new();
+
+        for sequence in sequences {
+            let segment_path = temp_dir.join(format!("seg{:06}.ts", sequence));
+            if !segment_path.exists() {
+                return Err(M3u8Error::FileNotFoundError(segment_path));
+            }
+
+            // 获取绝对路径并转换为 FFmpeg 兼容格式
+            let absolute_path = segment_path.canonicalize().map_err(M3u8Error::IoError)?;
+
+            // 在 Windows 上将反斜杠转换为正斜杠，FFmpeg 更好地支持正斜杠
+            let path_str = absolute_path.to_string_lossy().replace('\\', "/");
+            file_list_content.push_str(&format!("file '{}'\n", path_str));
+        }
+        // 写入文件列表
+        fs::write(&file_list_path, file_list_content).await?;
+
+        // 确保output_path的文件夹存在，不存在则创建
+        if let Some(parent) = output_path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent).await?;
+            }
+        }
+
+        Ok(Self {
+            temp_dir: temp_dir.to_path_buf(),
+            output_path: output_path.to_path_buf(),
+            segments: sequences.len(),
+            main: MergeInput {
+                sequences: sequences.to_vec(),
+                ..MergeInput::default()
+            },
+            renditions: Vec::new(),
+            clip: None,
+        })
+    }
+
+    pub fn with_segment_durations(mut self, segment_durations: Vec<f64>) -> Self {
+        self.main.segment_durations = segment_durations;
+        self
+    }
+
+    pub fn with_discontinuities(mut self, discontinuities: Vec<bool>) -> Self {
+        self.main.discontinuities = discontinuities;
+        self
+    }
+
+    // 各片段使用的初始化片段序号，对应临时目录中的 init{序号}.mp4
+    pub fn with_init_segments(mut self, init_segments: Vec<Option<usize>>) -> Self {
+        self.main.init_segments = init_segments;
+        self
+    }
+
+    // 备选媒体播放列表的片段，只在重新封装（mp4/mkv/fmp4）时使用
+    pub fn with_renditions(mut self, renditions: Vec<MergeInput>) -> Self {
+        self.renditions = renditions;
+        self
+    }
+
+    pub fn with_clip(mut self, clip: Option<ClipWindow>) -> Self {
+        self.clip = clip.filter(|clip| !clip.is_empty());
+        self
+    }
+
+    fn inputs(&self) -> impl Iterator<Item = &MergeInput> {
+        std::iter::once(&self.main).chain(&self.renditions)
+    }
+
+    // 按不连续点把各路输入的片段分组，各路输入的第 n 组对应同一段连续时间
+    fn segment_groups(&self) -> Vec<SegmentGroup> {
+        let mut groups: Vec<SegmentGroup> = Vec::new();
+        let inputs = self.inputs().count();
+        let main_groups = self.main.segment_groups(&self.temp_dir).len();
+
+        for (input, merge_input) in self.inputs().enumerate() {
+            let input_groups = merge_input.segment_groups(&self.temp_dir);
+            if input_groups.len() > main_groups {
+                println!(
+                    "⚠️  备选媒体 {} 的不连续点多于主播放列表，忽略多出的 {} 组片段",
+                    merge_input
+                        .label
+                        .as_ref()
+                        .map_or("", |label| label.name.as_str()),
+                    input_groups.len() - main_groups
+                );
+            }
+            for (index, segments) in input_groups.into_iter().take(main_groups).enumerate() {
+                if groups.len() <= index {
+                    groups.push(vec![Vec::new(); inputs]);
+                }
+                groups[index][input] = segments;
+            }
+        }
+        groups
+    }
+
+    pub async fn merge(&self, format: OutputFormat, mode: MergeMode) -> Result<(), M3u8Error> {
+        if let (Some(clip), OutputFormat::Ts | OutputFormat::HlsDir) = (self.clip, format) {
+            println!(
+                "⚠️  {} 输出不重新封装，只按片段边界裁剪，忽略精确裁剪 {}",
+                format, clip
+            );
+        }
+        match (format, mode) {
+            (OutputFormat::Ts, _) => self.merge_with_rust().await,
+            (OutputFormat::HlsDir, _) => self.merge_to_hls_dir().await,
+            (format, MergeMode::Ffmpeg) => match self.run_ffmpeg(format).await {
+                Ok(()) => {
+                    println!(
+                        "✅ 成功合并 {} 个片段到 {:?}",
+                        self.segments, self.output_path
+                    );
+                    Ok(())
+                }
+                Err(e) => {
+                    println!("⚠️  FFmpeg 合并失败，使用内置封装。 错误信息: {}", e);
+                    match self.merge_native(format).await {
+                        Ok(()) => Ok(()),
+                        Err(e) => self.merge_fallback(e).await,
+                    }
+                }
+            },
+            (format, MergeMode::Native) => self.merge_native(format).await,
+        }
+    }
+
+    // 未明确要求内置封装时，FFmpeg 与内置封装都失败后直接拼接片段，
+    // 扩展名与内容一致（TS 片段为 .ts，fMP4 片段为 .mp4），备选媒体无法保留
+    async fn merge_fallback(&self, error: M3u8Error) -> Result<(), M3u8Error> {
+        let extension = if self.main.has_init_segments() {
+            "mp4"
+        } else {
+            "ts"
+        };
+        let fallback_path = self.output_path.with_extension(extension);
+        println!("⚠️  {}，改为直接合并片段到 {:?}", error, fallback_path);
+        if !self.renditions.is_empty() {
+            println!(
+                "⚠️  直接合并只保留主播放列表，忽略 {} 个备选媒体",
+                self.renditions.len()
+            );
+        }
+        self.concat_to(&fallback_path).await
+    }
+
+    async fn merge_native(&self, format: OutputFormat) -> Result<(), M3u8Error> {
+        match format {
+            OutputFormat::Ts => self.merge_with_rust().await,
+            OutputFormat::Mp4 => self.merge_with_remux().await,
+            OutputFormat::Mkv => self.merge_with_mkv().await,
+            OutputFormat::Fmp4 => self.merge_with_fmp4().await,
+            OutputFormat::HlsDir => self.merge_to_hls_dir().await,
+        }
+    }
+
+    pub async fn merge_with_rust(&self) -> Result<(), M3u8Error> {
+        self.concat_to(&self.output_path).await
+    }
+
+    // 直接拼接片段字节，fMP4 片段在初始化片段变化处写入新的初始化片段
+    async fn concat_to(&self, output_path: &Path) -> Result<(), M3u8Error> {
+        if self.main.has_init_segments() {
+            println!("⚠️  片段为 fMP4，直接拼接的结果为分片 MP4 而非 TS");
+        }
+        let mut output_file = fs::File::create(output_path).await?;
+
+        let segments: Vec<SegmentFile> = self
+            .main
+            .segment_groups(&self.temp_dir)
+            .into_iter()
+            .flatten()
+            .collect();
+        MergeInput::write_segments(&mut output_file, &segments).await?;
+
+        output_file.flush().await?;
+        println!("✅ 成功合并 {} 个片段到 {:?}", self.segments, output_path);
+
+        Ok(())
+    }
+
+    // 输出片段及本地播放列表 index.m3u8 到目录，fMP4 片段同时输出初始化片段并写入 EXT-X-MAP
+    pub async fn merge_to_hls_dir(&self) -> Result<(), M3u8Error> {
+        fs::create_dir_all(&self.output_path).await?;
+
+        let target_duration = self
+            .main
+            .segment_durations
+            .iter()
+            .fold(1.0, |max: f64, duration| max.max(*duration))
+            .ceil();
+        // 媒体播放列表使用 EXT-X-MAP 需要版本 6
+        let (version, extension) = if self.main.has_init_segments() {
+            (6, "m4s")
+        } else {
+            (3, "ts")
+        };
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:{}\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+            version, target_duration
+        );
+
+        let mut current_init = None;
+        for (index, sequence) in self.main.sequences.iter().enumerate() {
+            let name = format!("seg{:06}.{}", index, extension);
+            fs::copy(
+                self.main.segment_path(&self.temp_dir, *sequence),
+                self.output_path.join(&name),
+            )
+            .await?;
+            let duration = self
+                .main
+                .segment_durations
+                .get(index)
+                .copied()
+                .unwrap_or(0.0);
+            if index > 0 && self.main.is_discontinuity(index) {
+                playlist.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+            if let Some(init) = self
+                .main
+                .init_segment(index)
+                .filter(|init| current_init != Some(*init))
+            {
+                let init_name = format!("init{:04}.mp4", init);
+                fs::copy(
+                    self.main.init_path(&self.temp_dir, init),
+                    self.output_path.join(&init_name),
+                )
+                .await?;
+                playlist.push_str(&format!("#EXT-X-MAP:URI=\"{}\"\n", init_name));
+                current_init = Some(init);
+            }
+            playlist.push_str(&format!("#EXTINF:{:.3},\n{}\n", duration, name));
+        }
+        playlist.push_str("#EXT-X-ENDLIST\n");
+
+        let playlist_path = self.output_path.join("index.m3u8");
+        fs::write(&playlist_path, playlist).await?;
+        println!(
+            "✅ 成功输出 {} 个片段及播放列表到 {:?}",
+            self.segments, playlist_path
+        );
+
+        Ok(())
+    }
+
+    // 解复用片段并重新封装为 MP4
+    pub async fn merge_with_remux(&self) -> Result<(), M3u8Error> {
+        self.remux_with("MP4", Self::remux_to_mp4).await
+    }
+
+    // 解复用片段并重新封装为 MKV
+    pub async fn merge_with_mkv(&self) -> Result<(), M3u8Error> {
+        self.remux_with("MKV", Self::remux_to_mkv).await
+    }
+
+    // 解复用片段并重新封装为分片 MP4
+    pub async fn merge_with_fmp4(&self) -> Result<(), M3u8Error> {
+        self.remux_with("fMP4", Self::remux_to_fmp4).await
+    }
+
+    // 封装失败时删除写了一半的输出文件并返回错误
+    async fn remux_with(&self, format: &str, remux: RemuxFn) -> Result<(), M3u8Error> {
+        let input = RemuxInput {
+            groups: self.segment_groups(),
+            labels: self.inputs().map(|input| input.label.clone()).collect(),
+            clip: self.clip,
+        };
+        let output_path = self.output_path.clone();
+        let result = tokio::task::spawn_blocking(move || remux(&input, &output_path))
+            .await
+            .map_err(|e| M3u8Error::MergeError(e.to_string()))
+            .and_then(|result| result);
+
+        match result {
+            Ok(tracks) => {
+                println!(
+                    "✅ 成功封装 {} 个片段（{} 条轨道）到 {:?}",
+                    self.segments, tracks, self.output_path
+                );
+                Ok(())
+            }
+            Err(e) => {
+                if self.output_path.exists() {
+                    fs::remove_file(&self.output_path).await?;
+                }
+                println!("❌ {} 封装失败，已删除不完整的输出文件", format);
+                Err(e)
+            }
+        }
+    }
+
+    // 依次解复用所有片段，将样本交给 on_sample 处理，返回处理完的时间线
+    // 每组片段的每路输入使用独立的解复用器，时间戳由 Timeline 接续到上一组之后；
+    // 组内按片段在播放列表中的开始时间交错读取各路输入，各输入都已读到的时间之前的样本
+    // 按解码时间合并输出，使不同输入的样本在文件中交错排列
+    fn demux_segments(
+        segment_groups: &[SegmentGroup],
+        mut timeline: Timeline,
+        mut on_sample: impl FnMut(&Sample) -> Result<(), M3u8Error>,
+    ) -> Result<Timeline, M3u8Error> {
+        for group in segment_groups {
+            let mut demuxers: Vec<Option<SegmentDemuxer>> = group.iter().map(|_| None).collect();
+            let mut pending: Vec<Vec<Sample>> = vec![Vec::new(); group.len()];
+            // 各输入已读到的最大解码时间
+            let mut reached: Vec<Option<i64>> = vec![None; group.len()];
+            let mut positions = vec![0; group.len()];
+            timeline.start_group();
+
+            loop {
+                let next = (0..group.len())
+                    .filter(|&input| positions[input] < group[input].len())
+                    .min_by(|&a, &b| {
+                        group[a][positions[a]]
+                            .start
+                            .total_cmp(&group[b][positions[b]].start)
+                    });
+                let Some(input) = next else {
+                    break;
+                };
+                let segment = &group[input][positions[input]];
+                positions[input] += 1;
+
+                let data = std::fs::read(&segment.path)?;
+                let demuxer = demuxers[input].get_or_insert_with(|| SegmentDemuxer::detect(&data));
+                demuxer.push(segment, &data)?;
+                let samples = demuxer.take_samples();
+                if let Some(dts) = samples.iter().map(|sample| sample.dts).max() {
+                    reached[input] = Some(reached[input].map_or(dts, |reached| reached.max(dts)));
+                }
+                pending[input].extend(samples);
+
+                // 还有片段未读的输入限制可以输出的时间，尚未读到样本时暂不输出
+                let frontier = (0..group.len())
+                    .filter(|&input| positions[input] < group[input].len())
+                    .map(|input| reached[input])
+                    .try_fold(i64::MAX, |frontier, reached| {
+                        reached.map(|reached| frontier.min(reached))
+                    });
+                if let Some(frontier) = frontier {
+                    let ready = pending
+                        .iter_mut()
+                        .map(|samples| {
+                            let (ready, rest) =
+                                samples.drain(..).partition(|sample| sample.dts <= frontier);
+                            *samples = rest;
+                            ready
+                        })
+                        .collect();
+                    Self::process_batches(&mut timeline, &demuxers, ready, &mut on_sample)?;
+                }
+            }
+
+            for (input, demuxer) in demuxers.iter_mut().enumerate() {
+                if let Some(demuxer) = demuxer {
+                    demuxer.flush();
+                    pending[input].extend(demuxer.take_samples());
+                }
+            }
+            Self::process_batches(&mut timeline, &demuxers, pending, &mut on_sample)?;
+        }
+
+        Ok(timeline)
+    }
+
+    // 把各输入的样本连同其解复用器的轨道信息一起交给时间线
+    fn process_batches(
+        timeline: &mut Timeline,
+        demuxers: &[Option<SegmentDemuxer>],
+        samples: Vec<Vec<Sample>>,
+        on_sample: &mut impl FnMut(&Sample) -> Result<(), M3u8Error>,
+    ) -> Result<(), M3u8Error> {
+        let batches = samples
+            .into_iter()
+            .enumerate()
+            .filter(|(_, samples)| !samples.is_empty())
+            .filter_map(|(input, samples)| {
+                let tracks = demuxers[input].as_ref()?.tracks();
+                Some((input, tracks, samples))
+            })
+            .collect();
+        timeline.process(batches, on_sample)
+    }
+
+    // 预先解复用全部片段，收集所有输入中出现过的轨道（包括中途才出现的轨道）及其编码参数，
+    // 返回记录了输出轨道的时间线与最早的解码时间
+    fn probe_tracks(input: &RemuxInput) -> Result<(Timeline, i64), M3u8Error> {
+        let mut start: Option<i64> = None;
+        let timeline = Self::demux_segments(
+            &input.groups,
+            Timeline::new().with_labels(input.labels.clone()),
+            |sample| {
+                start = Some(start.map_or(sample.dts, |start| start.min(sample.dts)));
+                Ok(())
+            },
+        )?;
+        Ok((timeline, start.unwrap_or(0)))
+    }
+
+    // MP4 通过编辑列表隐藏裁剪起点前的帧，可以精确到起点
+    fn remux_to_mp4(input: &RemuxInput, output_path: &Path) -> Result<usize, M3u8Error> {
+        let output_file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+        let mut writer = Mp4Writer::new(output_file)?;
+        let timeline = Self::demux_segments(
+            &input.groups,
+            Timeline::new()
+                .with_labels(input.labels.clone())
+                .with_clip(input.clip, true),
+            |sample| writer.write_sample(sample),
+        )?;
+        if let Some((start, end)) = timeline.clip_range() {
+            writer.set_presentation_range(start, end);
+        }
+        writer.finish(timeline.tracks())
+    }
+
+    // Matroska 需要先写出轨道信息，因此先探测编码参数再正式解复用
+    // 裁剪时从起点后的第一个关键帧开始
+    fn remux_to_mkv(input: &RemuxInput, output_path: &Path) -> Result<usize, M3u8Error> {
+        let (timeline, start) = Self::probe_tracks(input)?;
+        let output_file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+        let mut writer = MkvWriter::new(output_file, timeline.tracks(), start)?;
+        Self::demux_segments(
+            &input.groups,
+            timeline.restart().with_clip(input.clip, false),
+            |sample| writer.write_sample(sample),
+        )?;
+        writer.finish()
+    }
+
+    fn remux_to_fmp4(input: &RemuxInput, output_path: &Path) -> Result<usize, M3u8Error> {
+        let (timeline, start) = Self::probe_tracks(input)?;
+        let output_file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+        let mut writer = FragmentedMp4Writer::new(output_file, timeline.tracks(), start)?;
+        Self::demux_segments(
+            &input.groups,
+            timeline.restart().with_clip(input.clip, false),
+            |sample| writer.write_sample(sample),
+        )?;
+        writer.finish()
+    }
+
+    pub async fn merge_with_ffmpeg(&self) -> Result<(), M3u8Error> {
+        self.merge(OutputFormat::Mp4, MergeMode::Ffmpeg).await
+    }
+
+    // ffmpeg concat 按输入文件接续时间戳，有不连续点或 fMP4 片段时先把每组片段拼接为一个文件，
+    // 使时间戳重置只出现在文件之间；否则直接使用片段列表。每路输入生成一个文件列表
+    async fn ffmpeg_file_lists(&self) -> Result<Vec<PathBuf>, M3u8Error> {
+        let mut file_lists = Vec::new();
+        for input in self.inputs() {
+            let segment_groups = input.segment_groups(&self.temp_dir);
+            let join_groups = segment_groups.len() > 1 || input.has_init_segments();
+            if !join_groups && input.prefix.is_empty() {
+                file_lists.push(self.temp_dir.join("file_list.txt"));
+                continue;
+            }
+
+            let mut files = Vec::new();
+            if join_groups {
+                let extension = if input.has_init_segments() {
+                    "mp4"
+                } else {
+                    "ts"
+                };
+                for (index, segments) in segment_groups.iter().enumerate() {
+                    let group_path = self
+                        .temp_dir
+                        .join(format!("{}group{:04}.{}", input.prefix, index, extension));
+                    let mut group_file = fs::File::create(&group_path).await?;
+                    MergeInput::write_segments(&mut group_file, segments).await?;
+                    group_file.flush().await?;
+                    files.push(group_path);
+                }
+            } else {
+                files.extend(
+                    segment_groups
+                        .into_iter()
+                        .flatten()
+                        .map(|segment| segment.path),
+                );
+            }
+
+            let mut file_list_content = String::new();
+            for file in files {
+                let absolute_path = file.canonicalize().map_err(M3u8Error::IoError)?;
+                let path_str = absolute_path.to_string_lossy().replace('\\', "/");
+                file_list_content.push_str(&format!("file '{}'\n", path_str));
+            }
+            let file_list_path = self
+                .temp_dir
+                .join(format!("{}group_list.txt", input.prefix));
+            fs::write(&file_list_path, file_list_content).await?;
+            file_lists.push(file_list_path);
+        }
+
+        let groups = self.main.segment_groups(&self.temp_dir).len();
+        if groups > 1 {
+            println!("🔀 检测到 {} 处不连续点，按 {} 组合并", groups - 1, groups);
+        }
+        Ok(file_lists)
+    }
+
+    // 使用 ffmpeg 合并并封装为指定格式
+    // 裁剪时 -ss 作为输入选项，流复制从起点前最近的关键帧开始，由编辑列表对齐到起点
+    async fn run_ffmpeg(&self, format: OutputFormat) -> Result<(), String> {
+        let file_lists = self.ffmpeg_file_lists().await.map_err(|e| e.to_string())?;
+        let mut seek_args = Vec::new();
+        let mut duration_args = Vec::new();
+        if let Some(clip) = self.clip {
+            seek_args = vec!["-ss".to_string(), format!("{:.3}", clip.start)];
+            if let Some(duration) = clip.duration() {
+                duration_args = vec!["-t".to_string(), format!("{:.3}", duration)];
+            }
+        }
+        let format_args: &[&str] = match format {
+            OutputFormat::Mkv => &["-f", "matroska"],
+            OutputFormat::Fmp4 => &[
+                "-f",
+                "mp4",
+                "-movflags",
+                "frag_keyframe+empty_moov+default_base_moof",
+            ],
+            _ => &["-f", "mp4"],
+        };
+
+        // 每路输入各自作为一个 concat 输入，备选媒体的轨道通过 -map 一并输出
+        let mut command = std::process::Command::new("ffmpeg");
+        for file_list_path in &file_lists {
+            command
+                .args(["-f", "concat", "-safe", "0"])
+                .args(&seek_args)
+                .arg("-i")
+                .arg(file_list_path);
+        }
+        for index in 0..file_lists.len() {
+            command.args(["-map", &index.to_string()]);
+        }
+        let output = command
+            .args(["-c", "copy"])
+            .args(&duration_args)
+            .args(format_args)
+            .arg("-y")
+            .arg(&self.output_path)
+            .output()
+            .map_err(|e| format!("FFmpeg 不可用: {}", e))?;
+
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,844 @@
+// MP4 写出：样本按解复用顺序写入 mdat，结束时在文件末尾写出 moov
+use super::codec::{CodecConfig, AAC_FRAME_SAMPLES};
+use super::ts::{Sample, Track, TrackLabel, TS_TIMESCALE};
+use crate::error::M3u8Error;
+use std::io::{Seek, SeekFrom, Write};
+
+const MOVIE_TIMESCALE: u32 = 1000;
+// 文件开头为 ftyp 预留的长度，兼容品牌在写完样本后按轨道编码格式回填，剩余部分用 free 填充
+const FTYP_RESERVED: usize = 40;
+// 视频帧间隔异常（缺失或超过 10 秒）时使用的默认帧间隔，约 30fps
+pub(super) const DEFAULT_FRAME_DURATION: i64 = 3000;
+pub(super) const MAX_FRAME_DURATION: i64 = 10 * TS_TIMESCALE as i64;
+
+// 轨道的样本表
+#[derive(Default)]
+struct TrackSamples {
+    sizes: Vec<u32>,
+    dts: Vec<i64>,
+    pts: Vec<i64>,
+    // 关键帧序号，从 1 开始
+    sync_samples: Vec<u32>,
+    // (文件偏移, 样本数)
+    chunks: Vec<(u64, u32)>,
+}
+
+pub struct Mp4Writer<W: Write + Seek> {
+    output: W,
+    mdat_start: u64,
+    position: u64,
+    samples: Vec<TrackSamples>,
+    last_track: Option<usize>,
+    // 显示的起止时间，裁剪时范围外的帧只用于解码
+    presentation_start: Option<i64>,
+    presentation_end: Option<i64>,
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+    pub fn new(mut output: W) -> Result<Self, M3u8Error> {
+        output.write_all(&build_padded_ftyp(&[]))?;
+
+        // mdat 使用 64 位长度，写完样本后回填
+        let mdat_start = FTYP_RESERVED as u64;
+        output.write_all(&1u32.to_be_bytes())?;
+        output.write_all(b"mdat")?;
+        output.write_all(&0u64.to_be_bytes())?;
+
+        Ok(Self {
+            output,
+            mdat_start,
+            position: mdat_start + 16,
+            samples: Vec::new(),
+            last_track: None,
+            presentation_start: None,
+            presentation_end: None,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: &Sample) -> Result<(), M3u8Error> {
+        self.output.write_all(&sample.data)?;
+
+        if self.samples.len() <= sample.track {
+            self.samples
+                .resize_with(sample.track + 1, TrackSamples::default);
+        }
+        let track = &mut self.samples[sample.track];
+        track.sizes.push(sample.data.len() as u32);
+        track.dts.push(sample.dts);
+        track.pts.push(sample.pts);
+        if sample.keyframe {
+            track.sync_samples.push(track.sizes.len() as u32);
+        }
+
+        // 同一轨道的连续样本归入同一个块
+        match track.chunks.last_mut() {
+            Some((_, count)) if self.last_track == Some(sample.track) => *count += 1,
+            _ => track.chunks.push((self.position, 1)),
+        }
+        self.last_track = Some(sample.track);
+        self.position += sample.data.len() as u64;
+
+        Ok(())
+    }
+
+    pub fn set_presentation_range(&mut self, start: i64, end: Option<i64>) {
+        self.presentation_start = Some(start);
+        self.presentation_end = end;
+    }
+
+    // 回填 mdat 长度并写出 moov，返回写出的轨道数
+    pub fn finish(mut self, tracks: &[Track]) -> Result<usize, M3u8Error> {
+        let mdat_size = self.position - self.mdat_start;
+        self.output.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.output.write_all(&mdat_size.to_be_bytes())?;
+        self.output.seek(SeekFrom::Start(self.position))?;
+
+        let outputs: Vec<(&Track, &CodecConfig, &TrackSamples)> = tracks
+            .iter()
+            .zip(&self.samples)
+            .filter_map(|(track, samples)| {
+                let config = track.config.as_ref()?;
+                (!samples.sizes.is_empty()).then_some((track, config, samples))
+            })
+            .collect();
+        if outputs.is_empty() {
+            return Err(M3u8Error::MergeError(
+                "片段中没有可识别的音视频数据".to_string(),
+            ));
+        }
+
+        // 以最早的显示时间为起点，晚开始的轨道用空编辑补齐，保持音画同步
+        let start = self.presentation_start.unwrap_or_else(|| {
+            outputs
+                .iter()
+                .map(|(_, _, samples)| samples.pts.iter().min().copied().unwrap_or(0))
+                .min()
+                .unwrap_or(0)
+        });
+
+        let headers = track_headers(&outputs.iter().map(|(track, ..)| *track).collect::<Vec<_>>());
+        let mut traks = Vec::new();
+        let mut movie_duration = 0;
+        for (index, (track, config, samples)) in outputs.iter().enumerate() {
+            let (trak, duration) = build_trak(
+                index as u32 + 1,
+                track,
+                config,
+                headers[index],
+                samples,
+                start,
+                self.presentation_end,
+            );
+            movie_duration = movie_duration.max(duration);
+            traks.push(trak);
+        }
+
+        let mut moov = build_mvhd(movie_duration, outputs.len() as u32 + 1);
+        for trak in traks {
+            moov.extend_from_slice(&trak);
+        }
+        self.output.write_all(&mp4_box(b"moov", &moov))?;
+
+        let written: Vec<&Track> = outputs.iter().map(|(track, _, _)| *track).collect();
+        self.output.seek(SeekFrom::Start(0))?;
+        self.output.write_all(&build_padded_ftyp(&written))?;
+        self.output.flush()?;
+
+        Ok(outputs.len())
+    }
+}
+
+// MP4 的 ftyp，只有 H.264 轨道且参数集都在样本描述中时才声明 avc1 品牌
+fn build_padded_ftyp(tracks: &[&Track]) -> Vec<u8> {
+    let mut ftyp = build_ftyp(b"isom", &compatible_brands(&[b"isom", b"iso2"], tracks));
+    let padding = FTYP_RESERVED - ftyp.len();
+    ftyp.extend_from_slice(&mp4_box(b"free", &vec![0; padding - 8]));
+    ftyp
+}
+
+// 兼容品牌：avc1 品牌要求 H.264 样本描述为 avc1，H.265 及带内参数集的轨道不声明
+pub(super) fn compatible_brands<'a>(base: &[&'a [u8; 4]], tracks: &[&Track]) -> Vec<&'a [u8; 4]> {
+    let mut brands = base.to_vec();
+    let avc = tracks.iter().any(|track| {
+        matches!(track.config, Some(CodecConfig::Avc { .. })) && !track.parameter_sets_in_band
+    });
+    if avc {
+        brands.push(b"avc1");
+    }
+    brands.push(b"mp41");
+    brands
+}
+
+pub(super) fn build_ftyp(major_brand: &[u8; 4], compatible_brands: &[&[u8; 4]]) -> Vec<u8> {
+    let mut payload = major_brand.to_vec();
+    payload.extend_from_slice(&0x200u32.to_be_bytes());
+    for brand in compatible_brands {
+        payload.extend_from_slice(*brand);
+    }
+    mp4_box(b"ftyp", &payload)
+}
+
+pub(super) fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(payload.len() + 8);
+    data.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    data.extend_from_slice(kind);
+    data.extend_from_slice(payload);
+    data
+}
+
+pub(super) fn full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = vec![version];
+    data.extend_from_slice(&flags.to_be_bytes()[1..]);
+    data.extend_from_slice(payload);
+    mp4_box(kind, &data)
+}
+
+// 按字段顺序拼接大端字节
+pub(super) struct Fields(pub(super) Vec<u8>);
+
+impl Fields {
+    pub(super) fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub(super) fn u8(mut self, value: u8) -> Self {
+        self.0.push(value);
+        self
+    }
+
+    pub(super) fn u16(mut self, value: u16) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(super) fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(super) fn u64(mut self, value: u64) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub(super) fn bytes(mut self, value: &[u8]) -> Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub(super) fn zeros(mut self, count: usize) -> Self {
+        self.0.resize(self.0.len() + count, 0);
+        self
+    }
+
+    pub(super) fn matrix(self) -> Self {
+        [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000]
+            .into_iter()
+            .fold(self, |fields, value| fields.u32(value))
+    }
+}
+
+fn rescale(value: i64, from: u32, to: u32) -> i64 {
+    value * to as i64 / from as i64
+}
+
+pub(super) fn build_mvhd(duration: u64, next_track_id: u32) -> Vec<u8> {
+    let payload = Fields::new()
+        .u64(0)
+        .u32(MOVIE_TIMESCALE)
+        .u64(duration)
+        .u32(0x00010000)
+        .u16(0x0100)
+        .zeros(10)
+        .matrix()
+        .zeros(24)
+        .u32(next_track_id);
+    full_box(b"mvhd", 1, 0, &payload.0)
+}
+
+// 计算每个样本的时长（轨道时间刻度）
+fn sample_durations(config: &CodecConfig, samples: &TrackSamples) -> Vec<u32> {
+    if !config.is_video() {
+        return vec![AAC_FRAME_SAMPLES; samples.sizes.len()];
+    }
+
+    let mut durations = Vec::with_capacity(samples.dts.len());
+    let mut last_valid = DEFAULT_FRAME_DURATION;
+    for pair in samples.dts.windows(2) {
+        let duration = pair[1] - pair[0];
+        if duration > 0 && duration <= MAX_FRAME_DURATION {
+            last_valid = duration;
+            durations.push(duration as u32);
+        } else {
+            durations.push(last_valid as u32);
+        }
+    }
+    durations.push(last_valid as u32);
+    durations
+}
+
+fn build_trak(
+    track_id: u32,
+    track: &Track,
+    config: &CodecConfig,
+    header: TrackHeader,
+    samples: &TrackSamples,
+    start: i64,
+    end: Option<i64>,
+) -> (Vec<u8>, u64) {
+    let timescale = track_timescale(config);
+    let durations = sample_durations(config, samples);
+    let media_duration: u64 = durations.iter().map(|&d| d as u64).sum();
+
+    // 首个样本的显示时间相对起点的延迟，以及编辑列表中媒体的起始时间，
+    // 早于起点的帧（裁剪时保留的前导帧）不显示
+    let first_pts = samples.pts.iter().min().copied().unwrap_or(0);
+    let visible_pts = first_pts.max(start);
+    let delay = rescale(first_pts - start, TS_TIMESCALE, MOVIE_TIMESCALE).max(0) as u64;
+    let media_time = rescale(visible_pts - samples.dts[0], TS_TIMESCALE, timescale).max(0);
+    let skipped = rescale(visible_pts - first_pts, TS_TIMESCALE, timescale);
+    let mut presentation = rescale(media_duration as i64 - skipped, timescale, MOVIE_TIMESCALE);
+    if let Some(end) = end {
+        presentation = presentation.min(rescale(end - visible_pts, TS_TIMESCALE, MOVIE_TIMESCALE));
+    }
+    let presentation = presentation.max(0) as u64;
+
+    let mut elst = Fields::new().u32(if delay > 0 { 2 } else { 1 });
+    if delay > 0 {
+        elst = elst.u64(delay).u64(u64::MAX).u16(1).u16(0);
+    }
+    elst = elst.u64(presentation).u64(media_time as u64).u16(1).u16(0);
+    let edts = mp4_box(b"edts", &full_box(b"elst", 1, 0, &elst.0));
+
+    let trak = [
+        build_tkhd(track_id, config, delay + presentation, header),
+        edts,
+        build_mdia(
+            config,
+            media_duration,
+            &build_stbl(config, track.parameter_sets_in_band, samples, &durations),
+            track.label.as_ref(),
+        ),
+    ]
+    .concat();
+
+    (mp4_box(b"trak", &trak), delay + presentation)
+}
+
+// 轨道时间刻度：视频沿用 90kHz，音频使用采样率
+pub(super) fn track_timescale(config: &CodecConfig) -> u32 {
+    match config {
+        CodecConfig::Aac { sample_rate, .. } => *sample_rate,
+        _ => TS_TIMESCALE,
+    }
+}
+
+// tkhd 中的备用组与启用标志
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct TrackHeader {
+    pub alternate_group: u16,
+    pub enabled: bool,
+}
+
+// 同类轨道（如主播放列表与备选媒体的音频）多于一条时放入同一备用组，播放器只播放其中一条；
+// 组内启用主播放列表的轨道与默认的备选媒体轨道，都不满足时启用第一条
+pub(super) fn track_headers(tracks: &[&Track]) -> Vec<TrackHeader> {
+    let is_video = |track: &Track| track.config.as_ref().is_some_and(CodecConfig::is_video);
+    let mut headers: Vec<TrackHeader> = tracks
+        .iter()
+        .map(|track| TrackHeader {
+            alternate_group: 0,
+            enabled: track.label.as_ref().is_none_or(|label| label.default),
+        })
+        .collect();
+
+    for (group, video) in [(1, true), (2, false)] {
+        let members: Vec<usize> = (0..tracks.len())
+            .filter(|&index| is_video(tracks[index]) == video)
+            .collect();
+        if members.len() < 2 {
+            continue;
+        }
+        for &index in &members {
+            headers[index].alternate_group = group;
+        }
+        if !members.iter().any(|&index| headers[index].enabled) {
+            headers[members[0]].enabled = true;
+        }
+    }
+    headers
+}
+
+pub(super) fn build_tkhd(
+    track_id: u32,
+    config: &CodecConfig,
+    duration: u64,
+    header: TrackHeader,
+) -> Vec<u8> {
+    let (width, height) = config.dimensions().unwrap_or((0, 0));
+    let tkhd = Fields::new()
+        .u64(0)
+        .u64(0)
+        .u32(track_id)
+        .u32(0)
+        .u64(duration)
+        .zeros(8)
+        .u16(0)
+        .u16(header.alternate_group)
+        .u16(if config.is_video() { 0 } else { 0x0100 })
+        .u16(0)
+        .matrix()
+        .u32(width << 16)
+        .u32(height << 16);
+    // 标志: track_in_movie，启用时另加 track_enabled
+    let flags = if header.enabled { 3 } else { 2 };
+    full_box(b"tkhd", 1, flags, &tkhd.0)
+}
+
+// 备选媒体的轨道以名称作为 hdlr 的名称，并在 elng 中写入 BCP 47 语言标签
+pub(super) fn build_mdia(
+    config: &CodecConfig,
+    duration: u64,
+    stbl: &[u8],
+    label: Option<&TrackLabel>,
+) -> Vec<u8> {
+    let mdhd = Fields::new()
+        .u64(0)
+        .u64(0)
+        .u32(track_timescale(config))
+        .u64(duration)
+        .u16(0x55C4)
+        .u16(0);
+    let (handler, default_name) = if config.is_video() {
+        (b"vide", "VideoHandler")
+    } else {
+        (b"soun", "SoundHandler")
+    };
+    let name = label.map_or(default_name, |label| label.name.as_str());
+    let hdlr = Fields::new()
+        .u32(0)
+        .bytes(handler)
+        .zeros(12)
+        .bytes(name.as_bytes())
+        .zeros(1);
+
+    let media_header = if config.is_video() {
+        full_box(b"vmhd", 0, 1, &[0; 8])
+    } else {
+        full_box(b"smhd", 0, 0, &[0; 4])
+    };
+    let dref = full_box(
+        b"dref",
+        0,
+        0,
+        &Fields::new().u32(1).bytes(&full_box(b"url ", 0, 1, &[])).0,
+    );
+
+    let minf = [
+        media_header,
+        mp4_box(b"dinf", &dref),
+        mp4_box(b"stbl", stbl),
+    ]
+    .concat();
+    let mut mdia = full_box(b"mdhd", 1, 0, &mdhd.0);
+    if let Some(language) = label.and_then(|label| label.language.as_ref()) {
+        mdia.extend(full_box(
+            b"elng",
+            0,
+            0,
+            &[language.as_bytes(), &[0]].concat(),
+        ));
+    }
+    mdia.extend(full_box(b"hdlr", 0, 0, &hdlr.0));
+    mdia.extend(mp4_box(b"minf", &minf));
+    mp4_box(b"mdia", &mdia)
+}
+
+// 参数集出现在样本中时视频样本描述使用 avc3/hev1，否则使用 avc1/hvc1
+pub(super) fn build_sample_entry(config: &CodecConfig, parameter_sets_in_band: bool) -> Vec<u8> {
+    match config {
+        CodecConfig::Avc { width, height, .. } | CodecConfig::Hevc { width, height, .. } => {
+            let (kind, config_kind) = match (config, parameter_sets_in_band) {
+                (CodecConfig::Avc { .. }, false) => (b"avc1", b"avcC"),
+                (CodecConfig::Avc { .. }, true) => (b"avc3", b"avcC"),
+                (_, false) => (b"hvc1", b"hvcC"),
+                (_, true) => (b"hev1", b"hvcC"),
+            };
+            let entry = Fields::new()
+                .zeros(6)
+                .u16(1)
+                .zeros(16)
+                .u16(*width as u16)
+                .u16(*height as u16)
+                .u32(0x00480000)
+                .u32(0x00480000)
+                .u32(0)
+                .u16(1)
+                .zeros(32)
+                .u16(0x0018)
+                .u16(0xFFFF)
+                .bytes(&mp4_box(config_kind, &config.decoder_config()));
+            mp4_box(kind, &entry.0)
+        }
+        CodecConfig::Aac {
+            sample_rate,
+            channels,
+            ..
+        } => {
+            let specific_config = config.decoder_config();
+            let decoder_specific =
+                [&[0x05, specific_config.len() as u8], &specific_config[..]].concat();
+            let decoder_config = Fields::new()
+                .u8(0x40)
+                .u8(0x15)
+                .zeros(3)
+                .u32(0)
+                .u32(0)
+                .bytes(&decoder_specific);
+            let es = Fields::new()
+                .u16(0)
+                .u8(0)
+                .u8(0x04)
+                .u8(decoder_config.0.len() as u8)
+                .bytes(&decoder_config.0)
+                .bytes(&[0x06, 0x01, 0x02]);
+            let esds = Fields::new().u8(0x03).u8(es.0.len() as u8).bytes(&es.0);
+
+            let entry = Fields::new()
+                .zeros(6)
+                .u16(1)
+                .zeros(8)
+                .u16(*channels as u16)
+                .u16(16)
+                .u16(0)
+                .u16(0)
+                .u32(sample_rate << 16)
+                .bytes(&full_box(b"esds", 0, 0, &esds.0));
+            mp4_box(b"mp4a", &entry.0)
+        }
+    }
+}
+
+// 将连续相同的值压缩为 (次数, 值)
+fn run_length<T: PartialEq + Copy>(values: impl IntoIterator<Item = T>) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+fn build_stbl(
+    config: &CodecConfig,
+    parameter_sets_in_band: bool,
+    samples: &TrackSamples,
+    durations: &[u32],
+) -> Vec<u8> {
+    let mut stbl = full_box(
+        b"stsd",
+        0,
+        0,
+        &Fields::new()
+            .u32(1)
+            .bytes(&build_sample_entry(config, parameter_sets_in_band))
+            .0,
+    );
+
+    let stts = run_length(durations.iter().copied());
+    let stts = stts.iter().fold(
+        Fields::new().u32(stts.len() as u32),
+        |fields, (count, delta)| fields.u32(*count).u32(*delta),
+    );
+    stbl.extend_from_slice(&full_box(b"stts", 0, 0, &stts.0));
+
+    // 显示时间与解码时间不同（存在 B 帧）时写出 ctts
+    let offsets = run_length(
+        samples
+            .pts
+            .iter()
+            .zip(&samples.dts)
+            .map(|(pts, dts)| (pts - dts).clamp(0, u32::MAX as i64) as u32),
+    );
+    if offsets.iter().any(|(_, offset)| *offset != 0) {
+        let ctts = offsets.iter().fold(
+            Fields::new().u32(offsets.len() as u32),
+            |fields, (count, offset)| fields.u32(*count).u32(*offset),
+        );
+        stbl.extend_from_slice(&full_box(b"ctts", 0, 0, &ctts.0));
+    }
+
+    if config.is_video() {
+        let stss = samples.sync_samples.iter().fold(
+            Fields::new().u32(samples.sync_samples.len() as u32),
+            |fields, sample| fields.u32(*sample),
+        );
+        stbl.extend_from_slice(&full_box(b"stss", 0, 0, &stss.0));
+    }
+
+    let mut stsc = Vec::new();
+    let mut first_chunk = 1;
+    for (count, samples_per_chunk) in run_length(samples.chunks.iter().map(|(_, count)| *count)) {
+        stsc.push((first_chunk, samples_per_chunk));
+        first_chunk += count;
+    }
+    let stsc = stsc.iter().fold(
+        Fields::new().u32(stsc.len() as u32),
+        |fields, (first_chunk, samples_per_chunk)| {
+            fields.u32(*first_chunk).u32(*samples_per_chunk).u32(1)
+        },
+    );
+    stbl.extend_from_slice(&full_box(b"stsc", 0, 0, &stsc.0));
+
+    let stsz = samples.sizes.iter().fold(
+        Fields::new().u32(0).u32(samples.sizes.len() as u32),
+        |fields, size| fields.u32(*size),
+    );
+    stbl.extend_from_slice(&full_box(b"stsz", 0, 0, &stsz.0));
+
+    // 偏移超过 4GB 时使用 co64
+    let chunk_count = Fields::new().u32(samples.chunks.len() as u32);
+    if samples
+        .chunks
+        .iter()
+        .any(|(offset, _)| *offset > u32::MAX as u64)
+    {
+        let co64 = samples
+            .chunks
+            .iter()
+            .fold(chunk_count, |fields, (offset, _)| fields.u64(*offset));
+        stbl.extend_from_slice(&full_box(b"co64", 0, 0, &co64.0));
+    } else {
+        let stco = samples
+            .chunks
+            .iter()
+            .fold(chunk_count, |fields, (offset, _)| {
+                fields.u32(*offset as u32)
+            });
+        stbl.extend_from_slice(&full_box(b"stco", 0, 0, &stco.0));
+    }
+
+    stbl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::ts::TsDemuxer;
+    use std::io::Cursor;
+
+    fn children(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            let (header, size) = if size == 1 {
+                let size = u64::from_be_bytes(data[offset + 8..offset + 16].try_into().unwrap());
+                (16, size as usize)
+            } else {
+                (8, size)
+            };
+            boxes.push((
+                &data[offset + 4..offset + 8],
+                &data[offset + header..offset + size],
+            ));
+            offset += size;
+        }
+        boxes
+    }
+
+    // 按路径查找盒子内容，full box 返回版本与标志之后的内容
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        path.iter().fold(data, |data, kind| {
+            let (_, payload) = children(data)
+                .into_iter()
+                .find(|(found, _)| found == kind)
+                .unwrap_or_else(|| panic!("缺少 {}", String::from_utf8_lossy(*kind)));
+            payload
+        })
+    }
+
+    fn u32s(data: &[u8]) -> Vec<u32> {
+        data.chunks_exact(4)
+            .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    fn u64s(data: &[u8]) -> Vec<u64> {
+        data.chunks_exact(8)
+            .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    fn remux(data: &[u8]) -> (Vec<u8>, Vec<(usize, i64)>) {
+        let mut demuxer = TsDemuxer::new();
+        demuxer.push(data).unwrap();
+        demuxer.flush();
+
+        let mut output = Cursor::new(Vec::new());
+        let mut writer = Mp4Writer::new(&mut output).unwrap();
+        let mut order = Vec::new();
+        while let Some(sample) = demuxer.pop_sample() {
+            order.push((sample.track, sample.dts));
+            writer.write_sample(&sample).unwrap();
+        }
+        writer.finish(demuxer.tracks()).unwrap();
+        (output.into_inner(), order)
+    }
+
+    fn traks(data: &[u8]) -> Vec<&[u8]> {
+        children(find(data, &[b"moov"]))
+            .into_iter()
+            .filter(|(kind, _)| kind == b"trak")
+            .map(|(_, payload)| payload)
+            .collect()
+    }
+
+    // full box 表格：跳过版本、标志与条目数
+    fn table<'a>(trak: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+        children(find(trak, &[b"mdia", b"minf", b"stbl"]))
+            .into_iter()
+            .find(|(found, _)| found == kind)
+            .map(|(_, payload)| &payload[8..])
+    }
+
+    fn sample_entry(trak: &[u8]) -> &[u8] {
+        let stsd = table(trak, b"stsd").unwrap();
+        &stsd[4..8]
+    }
+
+    // (显示时长, 媒体起始时间)，空编辑的媒体起始时间为 -1
+    fn edit_list(trak: &[u8]) -> Vec<(u64, i64)> {
+        find(trak, &[b"edts", b"elst"])[8..]
+            .chunks_exact(20)
+            .map(|entry| {
+                (
+                    u64::from_be_bytes(entry[..8].try_into().unwrap()),
+                    i64::from_be_bytes(entry[8..16].try_into().unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_sample_tables() {
+        let (data, order) = remux(include_bytes!("testdata/avc_aac.ts"));
+        assert_eq!(
+            order,
+            [
+                (0, 900000),
+                (0, 903000),
+                (1, 900000),
+                (1, 902089),
+                (0, 906000),
+                (0, 909000),
+                (1, 906000),
+                (1, 908089)
+            ]
+        );
+        let traks = traks(&data);
+        assert_eq!(traks.len(), 2);
+        let (video, audio) = (traks[0], traks[1]);
+
+        assert_eq!(u32s(table(video, b"stts").unwrap()), [4, 3000]);
+        assert_eq!(
+            u32s(table(video, b"ctts").unwrap()),
+            [1, 3000, 1, 6000, 1, 0, 1, 3000]
+        );
+        assert_eq!(u32s(table(video, b"stss").unwrap()), [1, 4]);
+        // 每个块 2 个样本，音视频交替
+        assert_eq!(u32s(table(video, b"stsc").unwrap()), [1, 2, 1]);
+        assert_eq!(
+            u32s(&find(video, &[b"mdia", b"minf", b"stbl", b"stsz"])[8..]),
+            [4, 21, 17, 17, 43]
+        );
+        // mdat 从预留的 ftyp 之后开始，样本数据位于 16 字节的 mdat 头之后
+        assert_eq!(u32s(table(video, b"stco").unwrap()), [56, 116]);
+
+        assert_eq!(u32s(table(audio, b"stts").unwrap()), [4, 1024]);
+        assert!(table(audio, b"ctts").is_none());
+        assert!(table(audio, b"stss").is_none());
+        assert_eq!(u32s(table(audio, b"stco").unwrap()), [94, 176]);
+        assert_eq!(sample_entry(audio), b"mp4a");
+    }
+
+    #[test]
+    fn writes_edit_lists_relative_to_earliest_track() {
+        let (data, _) = remux(include_bytes!("testdata/avc_aac.ts"));
+        let traks = traks(&data);
+        // 视频首帧显示时间比音频晚 3000（33ms），用空编辑补齐，并跳过首帧的显示延迟
+        assert_eq!(edit_list(traks[0]), [(33, -1), (133, 3000)]);
+        assert_eq!(edit_list(traks[1]), [(92, 0)]);
+    }
+
+    #[test]
+    fn declares_brands_and_sample_entries_by_codec() {
+        // 中途变化的参数集写在样本中，使用 avc3 且不声明 avc1 品牌
+        let (data, _) = remux(include_bytes!("testdata/avc_aac.ts"));
+        assert_eq!(sample_entry(traks(&data)[0]), b"avc3");
+        let ftyp = find(&data, &[b"ftyp"]);
+        assert_eq!(ftyp, b"isom\0\0\x02\0isomiso2mp41");
+        assert_eq!(children(&data)[1].0, b"free");
+
+        let (data, _) = remux(include_bytes!("testdata/hevc.ts"));
+        assert_eq!(sample_entry(traks(&data)[0]), b"hvc1");
+        assert_eq!(&find(&data, &[b"ftyp"])[8..], b"isomiso2mp41");
+    }
+
+    #[test]
+    fn groups_alternate_audio_tracks() {
+        let (data, _) = remux(include_bytes!("testdata/avc_aac.ts"));
+        let mut demuxer = TsDemuxer::new();
+        demuxer.push(include_bytes!("testdata/avc_aac.ts")).unwrap();
+        let mut tracks = demuxer.tracks().to_vec();
+        assert_eq!(
+            track_headers(&tracks.iter().collect::<Vec<_>>()),
+            [TrackHeader {
+                alternate_group: 0,
+                enabled: true,
+            }; 2]
+        );
+        // 版本 1，标志 track_enabled | track_in_movie
+        assert_eq!(u32s(&find(traks(&data)[1], &[b"tkhd"])[..4]), [1 << 24 | 3]);
+
+        // 备选媒体的音轨与主音轨同组，非默认的备选媒体不启用
+        let mut rendition = tracks[1].clone();
+        rendition.label = Some(TrackLabel {
+            name: "Commentary".to_string(),
+            language: Some("en".to_string()),
+            default: false,
+        });
+        tracks.push(rendition);
+        let headers = track_headers(&tracks.iter().collect::<Vec<_>>());
+        let groups: Vec<(u16, bool)> = headers
+            .iter()
+            .map(|header| (header.alternate_group, header.enabled))
+            .collect();
+        assert_eq!(groups, [(0, true), (2, true), (2, false)]);
+    }
+
+    #[test]
+    fn uses_co64_for_offsets_beyond_4gb() {
+        let config = CodecConfig::Aac {
+            object_type: 2,
+            frequency_index: 4,
+            sample_rate: 44100,
+            channels: 2,
+        };
+        let samples = TrackSamples {
+            sizes: vec![10, 10],
+            dts: vec![0, 2089],
+            pts: vec![0, 2089],
+            sync_samples: Vec::new(),
+            chunks: vec![(1000, 1), (u32::MAX as u64 + 100, 1)],
+        };
+        let stbl = build_stbl(&config, false, &samples, &[1024, 1024]);
+        let boxes = children(&stbl);
+        assert!(boxes.iter().all(|(kind, _)| kind != b"stco"));
+        let (_, co64) = boxes.iter().find(|(kind, _)| kind == b"co64").unwrap();
+        assert_eq!(u32s(&co64[4..8]), [2]);
+        assert_eq!(u64s(&co64[8..]), [1000, u32::MAX as u64 + 100]);
+    }
+}
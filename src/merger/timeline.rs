@@ -0,0 +1,428 @@
+// 跨不连续点拼接时间线：每组片段使用独立的解复用器，时间戳平移到上一组结束之后，
+// 轨道按输入、类型及顺序对应到输出轨道，视频编码参数变化时把新的参数集插入关键帧。
+// 输入为主播放列表与各备选媒体播放列表，同一组内各输入共用时间偏移
+use super::ts::{Sample, StreamType, Track, TrackLabel, TS_TIMESCALE};
+use crate::error::M3u8Error;
+use crate::time_range::ClipWindow;
+
+// 精确裁剪的状态，时间均为 90kHz
+#[derive(Default)]
+struct Clip {
+    // 相对第一个样本的起止时间
+    start: i64,
+    end: Option<i64>,
+    // 保留起点前最近的关键帧及之后的帧，由封装格式的编辑列表隐藏；
+    // 否则从起点后的第一个视频关键帧开始输出
+    keep_preroll: bool,
+    // 第一个样本的 (显示时间, 解码时间)
+    origin: Option<(i64, i64)>,
+    // 实际开始输出的显示时间
+    begin: Option<i64>,
+    // 不保留前导帧时，把输出的时间戳平移回第一个样本处
+    shift: i64,
+    preroll: Vec<Sample>,
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    // 输出轨道，按首次出现的顺序排列
+    tracks: Vec<Track>,
+    // 每条输出轨道所属的输入
+    owners: Vec<usize>,
+    // 每个输入的轨道标签，备选媒体的轨道据此命名
+    labels: Vec<Option<TrackLabel>>,
+    group: usize,
+    // 每个输入在当前组中的轨道序号到输出轨道序号的映射
+    mappings: Vec<Vec<Option<usize>>>,
+    // 当前组的时间偏移，读到该组第一批样本时确定
+    offset: Option<i64>,
+    // 每条输出轨道最后一个样本的 (解码时间, 与前一样本的间隔)
+    last: Vec<Option<(i64, i64)>>,
+    clip: Option<Clip>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 只输出裁剪窗口内的样本
+    pub fn with_clip(mut self, window: Option<ClipWindow>, keep_preroll: bool) -> Self {
+        let to_ticks = |seconds: f64| (seconds * TS_TIMESCALE as f64).round() as i64;
+        self.clip = window
+            .filter(|window| !window.is_empty())
+            .map(|window| Clip {
+                start: to_ticks(window.start),
+                end: window.end.map(to_ticks),
+                keep_preroll,
+                ..Clip::default()
+            });
+        self
+    }
+
+    // 设置各输入的轨道标签，下标为输入序号
+    pub fn with_labels(mut self, labels: Vec<Option<TrackLabel>>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    // 重新开始解复用，保留已探测到的输出轨道，使正式解复用的轨道序号与探测结果一致
+    pub fn restart(self) -> Self {
+        Self {
+            last: vec![None; self.tracks.len()],
+            tracks: self.tracks,
+            owners: self.owners,
+            labels: self.labels,
+            ..Self::default()
+        }
+    }
+
+    // 裁剪起止的显示时间，封装格式据此设置编辑列表
+    pub fn clip_range(&self) -> Option<(i64, Option<i64>)> {
+        let clip = self.clip.as_ref()?;
+        let (origin, _) = clip.origin?;
+        Some((origin + clip.start, clip.end.map(|end| origin + end)))
+    }
+
+    // 开始新的一组片段（第一组之后的每组均从不连续点开始）
+    pub fn start_group(&mut self) {
+        if self.offset.is_some() || self.mappings.iter().any(|m| !m.is_empty()) {
+            self.group += 1;
+        }
+        self.mappings.clear();
+        self.offset = None;
+    }
+
+    // 输出轨道信息，编码参数以轨道第一次出现时为准
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    // 处理当前组各输入的解复用器输出的一批样本 (输入序号, 轨道, 样本)，
+    // 平移时间戳后按解码时间顺序交给 on_sample
+    pub fn process(
+        &mut self,
+        batches: Vec<(usize, &[Track], Vec<Sample>)>,
+        on_sample: &mut impl FnMut(&Sample) -> Result<(), M3u8Error>,
+    ) -> Result<(), M3u8Error> {
+        if self.offset.is_none() {
+            let group_start = batches
+                .iter()
+                .flat_map(|(_, _, samples)| samples.iter().map(|sample| sample.dts))
+                .min();
+            if let Some(group_start) = group_start {
+                self.offset = Some(self.group_offset(group_start));
+            }
+        }
+        let offset = self.offset.unwrap_or(0);
+
+        let mut output = Vec::new();
+        for (input, tracks, samples) in batches {
+            self.map_tracks(input, tracks);
+            for sample in samples {
+                if let Some(sample) = self.map_sample(input, tracks, sample, offset) {
+                    output.push(sample);
+                }
+            }
+        }
+
+        output.sort_by_key(|sample| sample.dts);
+        for sample in output {
+            self.emit(sample, on_sample)?;
+        }
+        Ok(())
+    }
+
+    // 把样本对应到输出轨道并平移时间戳，无法对应的轨道返回 None
+    fn map_sample(
+        &mut self,
+        input: usize,
+        tracks: &[Track],
+        mut sample: Sample,
+        offset: i64,
+    ) -> Option<Sample> {
+        let index = self.mappings[input].get(sample.track).copied().flatten()?;
+        if let (Some(config), Some(base)) =
+            (&tracks[sample.track].config, &self.tracks[index].config)
+        {
+            // 参数集变化过之后每个关键帧都带上本组参数集，切换回第一组的参数集时同样需要
+            let in_band = self.tracks[index].parameter_sets_in_band;
+            if sample.keyframe && config.is_video() && (config != base || in_band) {
+                sample.data = config.with_parameter_sets(&sample.data);
+                self.tracks[index].parameter_sets_in_band = true;
+            }
+        }
+        if tracks[sample.track].parameter_sets_in_band {
+            self.tracks[index].parameter_sets_in_band = true;
+        }
+
+        sample.track = index;
+        sample.dts += offset;
+        sample.pts += offset;
+
+        let delta = match self.last[index] {
+            Some((last_dts, _)) if sample.dts > last_dts => sample.dts - last_dts,
+            Some((_, last_delta)) => last_delta,
+            None => 0,
+        };
+        self.last[index] = Some((sample.dts, delta));
+        Some(sample)
+    }
+
+    // 按裁剪窗口过滤样本后交给 on_sample
+    fn emit(
+        &mut self,
+        mut sample: Sample,
+        on_sample: &mut impl FnMut(&Sample) -> Result<(), M3u8Error>,
+    ) -> Result<(), M3u8Error> {
+        let is_video = |track: &Track| track.stream_type != StreamType::Aac;
+        let video = is_video(&self.tracks[sample.track]);
+        let has_video = self.tracks.iter().any(is_video);
+        let Some(clip) = self.clip.as_mut() else {
+            return on_sample(&sample);
+        };
+
+        let (origin_pts, origin_dts) = *clip.origin.get_or_insert((sample.pts, sample.dts));
+        let start = origin_pts + clip.start;
+        // 按解码时间截断结尾，保留结尾前的帧所依赖的参考帧
+        if clip.end.is_some_and(|end| sample.dts >= origin_pts + end) {
+            return Ok(());
+        }
+
+        if let Some(begin) = clip.begin {
+            if !clip.keep_preroll && !video && sample.pts < begin {
+                return Ok(());
+            }
+            sample.dts -= clip.shift;
+            sample.pts -= clip.shift;
+            return on_sample(&sample);
+        }
+
+        let reached = if video {
+            sample.dts >= start
+        } else {
+            sample.pts >= start
+        };
+        if clip.keep_preroll {
+            if video && sample.keyframe {
+                clip.preroll.clear();
+            }
+            if !reached {
+                if video && (sample.keyframe || !clip.preroll.is_empty()) {
+                    clip.preroll.push(sample);
+                }
+                return Ok(());
+            }
+            clip.begin = Some(start);
+            for preroll in std::mem::take(&mut clip.preroll) {
+                on_sample(&preroll)?;
+            }
+            return on_sample(&sample);
+        }
+
+        // 有视频轨道时从视频关键帧开始，保证第一帧可以解码
+        if !reached || (has_video && !(video && sample.keyframe)) {
+            return Ok(());
+        }
+        clip.begin = Some(sample.pts);
+        clip.shift = sample.dts - origin_dts;
+        sample.dts -= clip.shift;
+        sample.pts -= clip.shift;
+        on_sample(&sample)
+    }
+
+    // 第一组保持原时间戳，之后每组紧接在上一组所有轨道的结束时间之后
+    fn group_offset(&self, group_start: i64) -> i64 {
+        if self.group == 0 {
+            return 0;
+        }
+        let end = self
+            .last
+            .iter()
+            .flatten()
+            .map(|(dts, delta)| dts + delta)
+            .max();
+        end.map_or(0, |end| end - group_start)
+    }
+
+    // 按轨道类型及同类轨道中的顺序对应到同一输入的输出轨道，超出已有轨道数时新增输出轨道；
+    // 视频编码格式不同时无法写入同一轨道，直接丢弃
+    fn map_tracks(&mut self, input: usize, tracks: &[Track]) {
+        if self.mappings.len() <= input {
+            self.mappings.resize(input + 1, Vec::new());
+        }
+        let is_video = |track: &Track| track.stream_type != StreamType::Aac;
+
+        for index in self.mappings[input].len()..tracks.len() {
+            let track = &tracks[index];
+            let ordinal = tracks[..index]
+                .iter()
+                .filter(|other| is_video(other) == is_video(track))
+                .count();
+            let existing = (0..self.tracks.len())
+                .filter(|&base| {
+                    self.owners[base] == input && is_video(&self.tracks[base]) == is_video(track)
+                })
+                .nth(ordinal);
+
+            let mapped = match existing {
+                Some(base) => (self.tracks[base].stream_type == track.stream_type).then_some(base),
+                None => {
+                    let mut output = track.clone();
+                    output.label = self.labels.get(input).cloned().flatten();
+                    self.tracks.push(output);
+                    self.owners.push(input);
+                    self.last.push(None);
+                    Some(self.tracks.len() - 1)
+                }
+            };
+            self.mappings[input].push(mapped);
+        }
+
+        // 编码参数在轨道出现之后才读到时补上
+        for (index, track) in tracks.iter().enumerate() {
+            if let Some(output) = self.mappings[input][index] {
+                if self.tracks[output].config.is_none() {
+                    self.tracks[output].config = track.config.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::codec::CodecConfig;
+
+    fn track(stream_type: StreamType) -> Track {
+        let config = match stream_type {
+            StreamType::Aac => CodecConfig::Aac {
+                object_type: 2,
+                frequency_index: 4,
+                sample_rate: 44100,
+                channels: 2,
+            },
+            _ => CodecConfig::Avc {
+                sps: vec![0x67, 0x64, 0x00, 0x1F],
+                pps: vec![0x68],
+                width: 1280,
+                height: 720,
+            },
+        };
+        Track {
+            id: 0,
+            stream_type,
+            config: Some(config),
+            parameter_sets_in_band: false,
+            label: None,
+        }
+    }
+
+    fn sample(track: usize, dts: i64) -> Sample {
+        Sample {
+            track,
+            dts,
+            pts: dts,
+            keyframe: true,
+            data: vec![0, 0, 0, 1, 0x65],
+        }
+    }
+
+    fn label(name: &str) -> TrackLabel {
+        TrackLabel {
+            name: name.to_string(),
+            language: Some("en".to_string()),
+            default: false,
+        }
+    }
+
+    // 处理一批样本，返回输出的 (轨道, 解码时间)
+    fn process(
+        timeline: &mut Timeline,
+        batches: Vec<(usize, &[Track], Vec<Sample>)>,
+    ) -> Vec<(usize, i64)> {
+        let mut output = Vec::new();
+        timeline
+            .process(batches, &mut |sample| {
+                output.push((sample.track, sample.dts));
+                Ok(())
+            })
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn adds_tracks_appearing_mid_stream() {
+        let video = [track(StreamType::H264)];
+        let video_audio = [track(StreamType::H264), track(StreamType::Aac)];
+        let mut timeline = Timeline::new();
+        timeline.start_group();
+        process(&mut timeline, vec![(0, &video, vec![sample(0, 0)])]);
+        let output = process(
+            &mut timeline,
+            vec![(0, &video_audio, vec![sample(0, 3000), sample(1, 3000)])],
+        );
+
+        assert_eq!(output, [(0, 3000), (1, 3000)]);
+        let types: Vec<StreamType> = timeline.tracks().iter().map(|t| t.stream_type).collect();
+        assert_eq!(types, [StreamType::H264, StreamType::Aac]);
+
+        // 不连续点之后才出现的轨道同样追加，时间戳接续在上一组之后
+        timeline.start_group();
+        let two_audio = [track(StreamType::Aac), track(StreamType::Aac)];
+        let output = process(
+            &mut timeline,
+            vec![(0, &two_audio, vec![sample(0, 90000), sample(1, 90000)])],
+        );
+        assert_eq!(timeline.tracks().len(), 3);
+        assert_eq!(output, [(1, 6000), (2, 6000)]);
+    }
+
+    #[test]
+    fn keeps_renditions_on_separate_labeled_tracks() {
+        let main = [track(StreamType::H264), track(StreamType::Aac)];
+        let rendition = [track(StreamType::Aac)];
+        let mut timeline = Timeline::new().with_labels(vec![None, Some(label("Commentary"))]);
+        timeline.start_group();
+        let output = process(
+            &mut timeline,
+            vec![
+                (
+                    0,
+                    &main,
+                    vec![sample(0, 0), sample(1, 2000), sample(0, 3000)],
+                ),
+                (1, &rendition, vec![sample(0, 1500), sample(0, 3500)]),
+            ],
+        );
+
+        // 同一组内各输入按解码时间交错输出
+        assert_eq!(output, [(0, 0), (2, 1500), (1, 2000), (0, 3000), (2, 3500)]);
+        let labels: Vec<Option<&str>> = timeline
+            .tracks()
+            .iter()
+            .map(|track| track.label.as_ref().map(|label| label.name.as_str()))
+            .collect();
+        assert_eq!(labels, [None, None, Some("Commentary")]);
+    }
+
+    #[test]
+    fn restart_keeps_probed_track_order() {
+        let main = [track(StreamType::H264), track(StreamType::Aac)];
+        let rendition = [track(StreamType::Aac)];
+        let mut probe = Timeline::new().with_labels(vec![None, Some(label("Commentary"))]);
+        probe.start_group();
+        process(&mut probe, vec![(0, &main, vec![sample(0, 0)])]);
+        process(&mut probe, vec![(1, &rendition, vec![sample(0, 0)])]);
+
+        // 正式解复用时备选媒体先读到样本，仍对应到探测时的轨道
+        let mut timeline = probe.restart();
+        timeline.start_group();
+        let output = process(&mut timeline, vec![(1, &rendition, vec![sample(0, 0)])]);
+        assert_eq!(output, [(2, 0)]);
+        let output = process(&mut timeline, vec![(0, &main, vec![sample(1, 0)])]);
+        assert_eq!(output, [(1, 0)]);
+        assert_eq!(timeline.tracks().len(), 3);
+    }
+}
@@ -0,0 +1,527 @@
+// MPEG-TS 解复用：解析 PAT/PMT，重组 PES，输出 H.264/H.265/AAC 样本
+use super::codec::{
+    parse_adts_frames, parse_avc_sps, parse_hevc_sps, split_nal_units, CodecConfig,
+    AAC_FRAME_SAMPLES,
+};
+use crate::error::M3u8Error;
+use std::collections::{HashMap, VecDeque};
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+// PTS/DTS 为 33 位，超出后回绕
+const TIMESTAMP_WRAP: i64 = 1 << 33;
+
+// MPEG-TS 时间戳的时间刻度（90kHz）
+pub const TS_TIMESCALE: u32 = 90000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamType {
+    H264,
+    H265,
+    Aac,
+}
+
+impl StreamType {
+    fn from_pmt(stream_type: u8) -> Option<Self> {
+        match stream_type {
+            0x1B => Some(StreamType::H264),
+            0x24 => Some(StreamType::H265),
+            0x0F => Some(StreamType::Aac),
+            _ => None,
+        }
+    }
+}
+
+// 备选媒体（EXT-X-MEDIA）轨道的名称与语言
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackLabel {
+    pub name: String,
+    pub language: Option<String>,
+    // 是否为默认轨道（DEFAULT=YES）
+    pub default: bool,
+}
+
+// 解复用得到的轨道，编码参数在读到 SPS/PPS 或 ADTS 帧头后才可用
+#[derive(Debug, Clone)]
+pub struct Track {
+    // MPEG-TS 的 PID 或 fMP4 的 track_ID
+    pub id: u32,
+    pub stream_type: StreamType,
+    pub config: Option<CodecConfig>,
+    // 编码参数中途变化过，新的参数集已插入关键帧样本（MP4 需使用 avc3/hev1 样本描述）
+    pub parameter_sets_in_band: bool,
+    // 来自备选媒体播放列表的轨道
+    pub label: Option<TrackLabel>,
+}
+
+// 单个样本（视频访问单元或 AAC 帧），时间戳为 90kHz 且已处理回绕
+// 视频数据为 4 字节长度前缀的 NAL 单元序列，参数集已移入解码器配置，
+// 参数集与轨道配置不同时保留在关键帧开头
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub track: usize,
+    pub dts: i64,
+    pub pts: i64,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+// 轨道解复用状态
+#[derive(Default)]
+struct TrackState {
+    pes_buffer: Vec<u8>,
+    last_dts: Option<i64>,
+    // 是否已输出过关键帧，关键帧之前的视频样本无法解码，直接丢弃
+    started: bool,
+    vps: Option<Vec<u8>>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct TsDemuxer {
+    pmt_pid: Option<u16>,
+    pids: HashMap<u16, usize>,
+    tracks: Vec<Track>,
+    states: Vec<TrackState>,
+    samples: VecDeque<Sample>,
+}
+
+impl TsDemuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    // 写入一段 TS 数据（通常为一个完整片段），自动跳过损坏的字节重新同步
+    pub fn push(&mut self, data: &[u8]) -> Result<(), M3u8Error> {
+        let mut packets = 0;
+        let mut i = 0;
+
+        while i + TS_PACKET_SIZE <= data.len() {
+            if data[i] != TS_SYNC_BYTE {
+                i += 1;
+                continue;
+            }
+            self.push_packet(&data[i..i + TS_PACKET_SIZE]);
+            packets += 1;
+            i += TS_PACKET_SIZE;
+        }
+
+        if packets == 0 && !data.is_empty() {
+            return Err(M3u8Error::MergeError("不是有效的 MPEG-TS 数据".to_string()));
+        }
+        Ok(())
+    }
+
+    // 处理所有未结束的 PES，输入结束时调用
+    pub fn flush(&mut self) {
+        for index in 0..self.states.len() {
+            let buffer = std::mem::take(&mut self.states[index].pes_buffer);
+            self.process_pes(index, &buffer);
+        }
+    }
+
+    pub fn pop_sample(&mut self) -> Option<Sample> {
+        self.samples.pop_front()
+    }
+
+    fn push_packet(&mut self, packet: &[u8]) {
+        let payload_unit_start = packet[1] & 0x40 != 0;
+        let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+
+        if adaptation_field_control & 0x01 == 0 {
+            return;
+        }
+        let mut offset = 4;
+        if adaptation_field_control & 0x02 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if offset >= TS_PACKET_SIZE {
+            return;
+        }
+        let payload = &packet[offset..];
+
+        if pid == 0 {
+            if payload_unit_start {
+                self.parse_pat(payload);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if payload_unit_start {
+                self.parse_pmt(payload);
+            }
+        } else if let Some(&index) = self.pids.get(&pid) {
+            if payload_unit_start {
+                let buffer = std::mem::take(&mut self.states[index].pes_buffer);
+                self.process_pes(index, &buffer);
+                self.states[index].pes_buffer.extend_from_slice(payload);
+            } else if !self.states[index].pes_buffer.is_empty() {
+                self.states[index].pes_buffer.extend_from_slice(payload);
+            }
+        }
+    }
+
+    // 取出 PSI 表数据（跳过 pointer_field），并按 section_length 截断，去掉 CRC
+    fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+        let pointer = *payload.first()? as usize;
+        let section = payload.get(1 + pointer..)?;
+        let section_length =
+            (((*section.get(1)? & 0x0F) as usize) << 8) | *section.get(2)? as usize;
+        section.get(..(3 + section_length).checked_sub(4)?)
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        let Some(section) = Self::psi_section(payload) else {
+            return;
+        };
+        for program in section.get(8..).unwrap_or_default().chunks_exact(4) {
+            let program_number = u16::from_be_bytes([program[0], program[1]]);
+            if program_number != 0 {
+                self.pmt_pid = Some((((program[2] & 0x1F) as u16) << 8) | program[3] as u16);
+                break;
+            }
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = Self::psi_section(payload) else {
+            return;
+        };
+        if section.len() < 12 {
+            return;
+        }
+        let program_info_length = (((section[10] & 0x0F) as usize) << 8) | section[11] as usize;
+
+        let mut i = 12 + program_info_length;
+        while i + 5 <= section.len() {
+            let stream_type = section[i];
+            let pid = (((section[i + 1] & 0x1F) as u16) << 8) | section[i + 2] as u16;
+            let es_info_length =
+                (((section[i + 3] & 0x0F) as usize) << 8) | section[i + 4] as usize;
+            i += 5 + es_info_length;
+
+            if self.pids.contains_key(&pid) {
+                continue;
+            }
+            if let Some(stream_type) = StreamType::from_pmt(stream_type) {
+                self.pids.insert(pid, self.tracks.len());
+                self.tracks.push(Track {
+                    id: pid as u32,
+                    stream_type,
+                    config: None,
+                    parameter_sets_in_band: false,
+                    label: None,
+                });
+                self.states.push(TrackState::default());
+            }
+        }
+    }
+
+    fn parse_timestamp(data: &[u8]) -> i64 {
+        (((data[0] as i64 >> 1) & 0x07) << 30)
+            | ((data[1] as i64) << 22)
+            | ((data[2] as i64 >> 1) << 15)
+            | ((data[3] as i64) << 7)
+            | (data[4] as i64 >> 1)
+    }
+
+    // 以上一个 DTS 为参考展开 33 位时间戳，处理回绕
+    fn unwrap_timestamp(reference: Option<i64>, raw: i64) -> i64 {
+        let Some(reference) = reference else {
+            return raw;
+        };
+        let mut diff = raw - reference.rem_euclid(TIMESTAMP_WRAP);
+        if diff > TIMESTAMP_WRAP / 2 {
+            diff -= TIMESTAMP_WRAP;
+        } else if diff < -TIMESTAMP_WRAP / 2 {
+            diff += TIMESTAMP_WRAP;
+        }
+        reference + diff
+    }
+
+    fn process_pes(&mut self, index: usize, pes: &[u8]) {
+        if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+            return;
+        }
+        let timestamp_flags = pes[7] >> 6;
+        let payload_start = 9 + pes[8] as usize;
+        if payload_start > pes.len() {
+            return;
+        }
+
+        let raw_pts = (timestamp_flags & 0x02 != 0 && pes.len() >= 14)
+            .then(|| Self::parse_timestamp(&pes[9..14]));
+        let raw_dts = (timestamp_flags == 0x03 && pes.len() >= 19)
+            .then(|| Self::parse_timestamp(&pes[14..19]))
+            .or(raw_pts);
+
+        let state = &mut self.states[index];
+        let (dts, pts) = match (raw_dts, raw_pts) {
+            (Some(raw_dts), Some(raw_pts)) => {
+                let dts = Self::unwrap_timestamp(state.last_dts, raw_dts);
+                (dts, Self::unwrap_timestamp(Some(dts), raw_pts))
+            }
+            // 缺少时间戳时沿用上一个时间戳
+            _ => {
+                let last = state.last_dts.unwrap_or(0);
+                (last, last)
+            }
+        };
+        state.last_dts = Some(dts);
+
+        let payload = &pes[payload_start..];
+        match self.tracks[index].stream_type {
+            StreamType::Aac => self.process_aac(index, dts, payload),
+            StreamType::H264 | StreamType::H265 => self.process_video(index, dts, pts, payload),
+        }
+    }
+
+    fn process_aac(&mut self, index: usize, pts: i64, payload: &[u8]) {
+        for (frame_index, (config, frame)) in parse_adts_frames(payload).into_iter().enumerate() {
+            let CodecConfig::Aac { sample_rate, .. } = config else {
+                continue;
+            };
+            if self.tracks[index].config.is_none() {
+                self.tracks[index].config = Some(config);
+            }
+
+            let offset = frame_index as i64 * AAC_FRAME_SAMPLES as i64 * TS_TIMESCALE as i64
+                / sample_rate as i64;
+            self.samples.push_back(Sample {
+                track: index,
+                dts: pts + offset,
+                pts: pts + offset,
+                keyframe: true,
+                data: frame.to_vec(),
+            });
+        }
+    }
+
+    fn process_video(&mut self, index: usize, dts: i64, pts: i64, payload: &[u8]) {
+        let is_hevc = self.tracks[index].stream_type == StreamType::H265;
+        let state = &mut self.states[index];
+        let mut data = Vec::with_capacity(payload.len() + 16);
+        let mut keyframe = false;
+
+        for nal in split_nal_units(payload) {
+            let nal_type = if is_hevc {
+                (nal[0] >> 1) & 0x3F
+            } else {
+                nal[0] & 0x1F
+            };
+
+            // 参数集放入解码器配置，访问单元分隔符直接丢弃
+            match (is_hevc, nal_type) {
+                (false, 7) | (true, 33) => state.sps = Some(nal.to_vec()),
+                (false, 8) | (true, 34) => state.pps = Some(nal.to_vec()),
+                (true, 32) => state.vps = Some(nal.to_vec()),
+                (false, 9) | (true, 35) => {}
+                _ => {
+                    keyframe |= if is_hevc {
+                        (16..=21).contains(&nal_type)
+                    } else {
+                        nal_type == 5
+                    };
+                    data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    data.extend_from_slice(nal);
+                }
+            }
+        }
+
+        // 轨道配置取第一组参数集，之后参数集变化（如切换分辨率）时把新的参数集插入关键帧，
+        // 变化过之后每个关键帧都带上当前参数集，使切换回原参数集时也能正确解码
+        let current = Self::video_config(is_hevc, state);
+        let track = &mut self.tracks[index];
+        match (&track.config, current) {
+            (None, current) => track.config = current,
+            (Some(config), Some(current))
+                if keyframe && (*config != current || track.parameter_sets_in_band) =>
+            {
+                data = current.with_parameter_sets(&data);
+                track.parameter_sets_in_band = true;
+            }
+            _ => {}
+        }
+        if data.is_empty() || track.config.is_none() {
+            return;
+        }
+        if !state.started {
+            if !keyframe {
+                return;
+            }
+            state.started = true;
+        }
+
+        self.samples.push_back(Sample {
+            track: index,
+            dts,
+            pts,
+            keyframe,
+            data,
+        });
+    }
+
+    fn video_config(is_hevc: bool, state: &TrackState) -> Option<CodecConfig> {
+        let sps = state.sps.clone()?;
+        let pps = state.pps.clone()?;
+        if is_hevc {
+            let (width, height, info) = parse_hevc_sps(&sps)?;
+            Some(CodecConfig::Hevc {
+                vps: state.vps.clone()?,
+                sps,
+                pps,
+                width,
+                height,
+                info,
+            })
+        } else {
+            let (width, height) = parse_avc_sps(&sps).ok()?;
+            Some(CodecConfig::Avc {
+                sps,
+                pps,
+                width,
+                height,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 帧 H.264（第 4 帧为 640x480 的关键帧）与 4 帧 AAC，起始 DTS 为 900000
+    const AVC_AAC: &[u8] = include_bytes!("testdata/avc_aac.ts");
+    // 2 帧 1280x720 的 H.265，关键帧带 VPS/SPS/PPS
+    const HEVC: &[u8] = include_bytes!("testdata/hevc.ts");
+
+    fn demux(data: &[u8]) -> (Vec<Track>, Vec<Sample>) {
+        let mut demuxer = TsDemuxer::new();
+        demuxer.push(data).unwrap();
+        demuxer.flush();
+        let samples = std::iter::from_fn(|| demuxer.pop_sample()).collect();
+        (demuxer.tracks().to_vec(), samples)
+    }
+
+    fn track_samples(samples: &[Sample], track: usize) -> Vec<&Sample> {
+        samples
+            .iter()
+            .filter(|sample| sample.track == track)
+            .collect()
+    }
+
+    #[test]
+    fn reads_tracks_from_pat_and_pmt() {
+        let (tracks, _) = demux(AVC_AAC);
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(
+            (tracks[0].id, tracks[0].stream_type),
+            (0x100, StreamType::H264)
+        );
+        assert_eq!(
+            (tracks[1].id, tracks[1].stream_type),
+            (0x101, StreamType::Aac)
+        );
+        assert_eq!(
+            tracks[0].config.as_ref().unwrap().dimensions(),
+            Some((320, 240))
+        );
+        assert!(matches!(
+            tracks[1].config,
+            Some(CodecConfig::Aac {
+                sample_rate: 44100,
+                channels: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn reassembles_pes_into_samples() {
+        let (_, samples) = demux(AVC_AAC);
+
+        let video = track_samples(&samples, 0);
+        let timestamps: Vec<(i64, i64)> = video.iter().map(|s| (s.dts, s.pts)).collect();
+        assert_eq!(
+            timestamps,
+            [
+                (900000, 903000),
+                (903000, 909000),
+                (906000, 906000),
+                (909000, 912000)
+            ]
+        );
+        let keyframes: Vec<bool> = video.iter().map(|s| s.keyframe).collect();
+        assert_eq!(keyframes, [true, false, false, true]);
+        // 参数集与访问单元分隔符不进入样本，NAL 单元改为长度前缀
+        assert_eq!(video[0].data[..5], [0, 0, 0, 17, 0x65]);
+        assert_eq!(video[0].data.len(), 4 + 17);
+
+        // 同一 PES 中的多个 ADTS 帧按采样数推算时间戳
+        let audio = track_samples(&samples, 1);
+        let timestamps: Vec<i64> = audio.iter().map(|s| s.pts).collect();
+        assert_eq!(timestamps, [900000, 902089, 906000, 908089]);
+        assert_eq!(audio[0].data, [1; 10]);
+        assert_eq!(audio[1].data, [2; 12]);
+    }
+
+    #[test]
+    fn inserts_parameter_sets_when_sps_changes() {
+        let (tracks, samples) = demux(AVC_AAC);
+        // 轨道配置保持第一组参数集，新的 SPS/PPS 插入变化后的关键帧
+        assert_eq!(
+            tracks[0].config.as_ref().unwrap().dimensions(),
+            Some((320, 240))
+        );
+        assert!(tracks[0].parameter_sets_in_band);
+
+        let video = track_samples(&samples, 0);
+        let sps = [0x67, 0x64, 0x00, 0x28, 0xac, 0xe5, 0x02, 0x80, 0xf6, 0x80];
+        assert_eq!(video[3].data[..4], [0, 0, 0, sps.len() as u8]);
+        assert_eq!(video[3].data[4..4 + sps.len()], sps);
+        assert_eq!(video[0].data[4], 0x65);
+    }
+
+    #[test]
+    fn demuxes_hevc_with_vps() {
+        let (tracks, samples) = demux(HEVC);
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].stream_type, StreamType::H265);
+        let config = tracks[0].config.as_ref().unwrap();
+        assert_eq!(config.dimensions(), Some((1280, 720)));
+        assert_eq!(config.parameter_sets().len(), 3);
+        assert!(!tracks[0].parameter_sets_in_band);
+
+        assert_eq!(samples.len(), 2);
+        assert!(samples[0].keyframe && !samples[1].keyframe);
+        assert_eq!(samples[0].data[4..6], [0x26, 0x01]);
+    }
+
+    #[test]
+    fn skips_garbage_and_rejects_non_ts_data() {
+        let mut data = vec![0xAB; 7];
+        data.extend_from_slice(HEVC);
+        let (tracks, samples) = demux(&data);
+        assert_eq!((tracks.len(), samples.len()), (1, 2));
+
+        assert!(TsDemuxer::new().push(&[0u8; 400]).is_err());
+    }
+
+    #[test]
+    fn unwraps_33_bit_timestamps() {
+        let last = TIMESTAMP_WRAP - 1000;
+        assert_eq!(
+            TsDemuxer::unwrap_timestamp(Some(last), 2000),
+            TIMESTAMP_WRAP + 2000
+        );
+        assert_eq!(
+            TsDemuxer::unwrap_timestamp(Some(TIMESTAMP_WRAP + 2000), TIMESTAMP_WRAP - 500),
+            TIMESTAMP_WRAP - 500
+        );
+        assert_eq!(TsDemuxer::unwrap_timestamp(None, 42), 42);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::types::{AdBreak, CueMarker, M3u8Segment};
+use url::Url;
+
+// 默认判定为广告的最低得分
+pub const DEFAULT_AD_SCORE_THRESHOLD: u32 = 3;
+
+// 片段时长与正片相差超过该比例时视为时长模式不同
+const DURATION_DEVIATION: f64 = 0.25;
+// 常见广告时长为 5 秒的整数倍，最长 120 秒
+const AD_LENGTH_UNIT: f64 = 5.0;
+const AD_LENGTH_TOLERANCE: f64 = 0.5;
+const MAX_AD_LENGTH: f64 = 120.0;
+
+// 一段以不连续点或广告标记为边界的连续片段
+struct Run {
+    start: usize,
+    end: usize,
+    duration: f64,
+    // 是否位于 CUE-OUT/CUE-IN（或 SCTE-35 DATERANGE）标记的广告区间内
+    in_cue: bool,
+}
+
+// 基于不连续点的广告检测：以不连续点与广告标记切分片段，对每段按
+// 广告标记、域名、片段时长模式及总时长打分，得分达到阈值的段判定为广告。
+// 前后不连续点与总时长只是结构特征，正片的章节同样具备，只在已有广告标记、
+// 域名或时长模式的信号时才计分
+#[derive(Debug, Clone)]
+pub struct AdDetector {
+    threshold: u32,
+}
+
+impl Default for AdDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_AD_SCORE_THRESHOLD)
+    }
+}
+
+impl AdDetector {
+    pub fn new(threshold: u32) -> Self {
+        Self { threshold }
+    }
+
+    // 返回判定为广告的片段段落，按位置排列
+    pub fn detect(&self, segments: &[M3u8Segment]) -> Vec<AdBreak> {
+        let runs = Self::split_runs(segments);
+        if runs.len() < 2 {
+            return Vec::new();
+        }
+
+        // 按域名汇总时长，总时长最长的域名作为正片参照；同一域名下的不同目录可能是正片的不同章节，
+        // 不作区分
+        let mut sources: Vec<(Option<String>, f64)> = Vec::new();
+        for segment in segments {
+            let host = Self::host(&segment.url);
+            match sources.iter_mut().find(|(key, _)| *key == host) {
+                Some((_, duration)) => *duration += segment.duration,
+                None => sources.push((host, segment.duration)),
+            }
+        }
+        let (main_host, _) = sources
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        let main_median = Self::median(
+            segments
+                .iter()
+                .filter(|s| Self::host(&s.url) == main_host)
+                .map(|s| s.duration)
+                .collect(),
+        );
+
+        let mut ad_breaks = Vec::new();
+        for run in &runs {
+            let run_segments = &segments[run.start..run.end];
+            let mut score = 0;
+            let mut reasons = Vec::new();
+
+            if run.in_cue {
+                score += 3;
+                reasons.push("位于 CUE-OUT/CUE-IN 广告区间".to_string());
+            }
+
+            let host = Self::host(&run_segments[0].url);
+            if host != main_host {
+                score += 2;
+                reasons.push(format!("域名不同: {}", host.unwrap_or_default()));
+            }
+
+            let median = Self::median(run_segments.iter().map(|s| s.duration).collect());
+            if main_median > 0.0 && (median - main_median).abs() / main_median > DURATION_DEVIATION
+            {
+                score += 1;
+                reasons.push(format!(
+                    "片段时长 {:.2}s 与正片 {:.2}s 不同",
+                    median, main_median
+                ));
+            }
+
+            // 没有任何广告信号的段落不计结构特征
+            if score == 0 {
+                continue;
+            }
+
+            // 仅考虑中间的段落，避免把开头或结尾的正片误判
+            let bounded = run.start > 0
+                && run.end < segments.len()
+                && segments[run.start].discontinuity
+                && segments[run.end].discontinuity;
+            if bounded {
+                score += 1;
+                reasons.push("前后均为不连续点".to_string());
+            }
+
+            let units = (run.duration / AD_LENGTH_UNIT).round();
+            if run.duration <= MAX_AD_LENGTH
+                && units >= 1.0
+                && (run.duration - units * AD_LENGTH_UNIT).abs() <= AD_LENGTH_TOLERANCE
+            {
+                score += 1;
+                reasons.push(format!("总时长 {:.1}s 符合常见广告长度", run.duration));
+            }
+
+            if score >= self.threshold {
+                ad_breaks.push(AdBreak {
+                    start_sequence: run_segments[0].sequence,
+                    segment_count: run_segments.len(),
+                    duration: run.duration,
+                    score,
+                    reasons,
+                });
+            }
+        }
+
+        ad_breaks
+    }
+
+    // 在不连续点及广告开始/结束标记处切分
+    fn split_runs(segments: &[M3u8Segment]) -> Vec<Run> {
+        let mut runs: Vec<Run> = Vec::new();
+        let mut in_cue = false;
+
+        for (index, segment) in segments.iter().enumerate() {
+            let boundary = match segment.cue {
+                Some(CueMarker::Out) => {
+                    let boundary = !in_cue;
+                    in_cue = true;
+                    boundary
+                }
+                Some(CueMarker::In) => {
+                    let boundary = in_cue;
+                    in_cue = false;
+                    boundary
+                }
+                Some(CueMarker::Cont) => {
+                    let boundary = !in_cue;
+                    in_cue = true;
+                    boundary
+                }
+                None => false,
+            };
+
+            match runs.last_mut() {
+                Some(run) if !boundary && !segment.discontinuity => {
+                    run.end = index + 1;
+                    run.duration += segment.duration;
+                }
+                _ => runs.push(Run {
+                    start: index,
+                    end: index + 1,
+                    duration: segment.duration,
+                    in_cue,
+                }),
+            }
+        }
+
+        runs
+    }
+
+    fn host(url: &str) -> Option<String> {
+        Url::parse(url)
+            .ok()?
+            .host_str()
+            .map(|host| host.to_string())
+    }
+
+    fn median(mut durations: Vec<f64>) -> f64 {
+        durations.sort_by(|a, b| a.total_cmp(b));
+        durations.get(durations.len() / 2).copied().unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::MediaParser;
+
+    // 按 (地址, 时长, 前面是否有不连续点) 生成媒体播放列表，返回广告检测后保留的片段地址
+    fn kept_segments(segments: &[(&str, f64, bool)]) -> Vec<String> {
+        let mut content = String::from("#EXTM3U\n#EXT-X-TARGETDURATION:10\n");
+        for (url, duration, discontinuity) in segments {
+            if *discontinuity {
+                content.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+            content.push_str(&format!("#EXTINF:{},\n{}\n", duration, url));
+        }
+        content.push_str("#EXT-X-ENDLIST\n");
+
+        let playlist = MediaParser::new(Vec::new())
+            .unwrap()
+            .with_ad_detector(Some(AdDetector::default()))
+            .parse(&content, None)
+            .unwrap();
+        playlist.segments.into_iter().map(|s| s.url).collect()
+    }
+
+    fn main_segments(directory: &str, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|index| format!("https://cdn.example.com/show/{}/{}.ts", directory, index))
+            .collect()
+    }
+
+    #[test]
+    fn removes_mid_roll_but_keeps_chapters() {
+        // 第二章位于另一目录，前后都是不连续点且总时长 30 秒，仍属于正片
+        let part1 = main_segments("part1", 10);
+        let part2 = main_segments("part2", 5);
+        let part3 = main_segments("part3", 10);
+        let ads: Vec<String> = (0..3)
+            .map(|index| format!("https://ads.example.net/spot/{}.ts", index))
+            .collect();
+
+        let mut segments: Vec<(&str, f64, bool)> = Vec::new();
+        for url in &part1 {
+            segments.push((url, 6.0, false));
+        }
+        for (index, url) in part2.iter().enumerate() {
+            segments.push((url, 6.0, index == 0));
+        }
+        for (index, url) in ads.iter().enumerate() {
+            segments.push((url, 5.0, index == 0));
+        }
+        for (index, url) in part3.iter().enumerate() {
+            segments.push((url, 6.0, index == 0));
+        }
+
+        let kept = kept_segments(&segments);
+        let main: Vec<String> = [part1, part2, part3].concat();
+        assert_eq!(kept, main);
+    }
+
+    #[test]
+    fn removes_pre_roll_and_keeps_main_content() {
+        let ads: Vec<String> = (0..2)
+            .map(|index| format!("https://ads.example.net/pre/{}.ts", index))
+            .collect();
+        // 正片开头的片头在单独的目录中，时长为 5 秒的整数倍
+        let intro = main_segments("intro", 2);
+        let episode = main_segments("episode", 20);
+
+        let mut segments: Vec<(&str, f64, bool)> = Vec::new();
+        for url in &ads {
+            segments.push((url, 5.0, false));
+        }
+        for (index, url) in intro.iter().enumerate() {
+            segments.push((url, 5.0, index == 0));
+        }
+        for (index, url) in episode.iter().enumerate() {
+            segments.push((url, 6.0, index == 0));
+        }
+
+        let kept = kept_segments(&segments);
+        assert_eq!(kept, [intro, episode].concat());
+    }
+}
@@ -1,11 +1,75 @@
 use crate::error::M3u8Error;
 use crate::types::PlaylistType;
+use chrono::{DateTime, FixedOffset};
 use regex::Regex;
 use url::Url;
 
+// EXT-X-STREAM-INF 属性：带宽、分辨率、编码
+pub type StreamInfAttributes = (Option<u32>, Option<(u32, u32)>, Option<String>);
+
+// 解析带协议的绝对地址，单字母协议视为 Windows 盘符路径（如 C:\video\seg.ts）
+fn parse_absolute_url(url: &str) -> Option<Url> {
+    Url::parse(url).ok().filter(|u| u.scheme().len() > 1)
+}
+
+// 解析基础地址：可以是 URL，也可以是本地目录或文件路径
+pub fn resolve_base_url(base: &str) -> Option<Url> {
+    if let Some(url) = parse_absolute_url(base) {
+        return Some(url);
+    }
+
+    let path = std::path::Path::new(base);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if path.is_dir() {
+        Url::from_directory_path(&path).ok()
+    } else {
+        Url::from_file_path(&path).ok()
+    }
+}
+
+// 查询参数传递方式：将上级播放列表地址中的查询参数附加到子地址上
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum QueryPropagation {
+    // 不传递
+    #[default]
+    None,
+    // 传递全部查询参数
+    All,
+    // 仅传递指定名称的查询参数
+    Selected(Vec<String>),
+}
+
+impl QueryPropagation {
+    // 解析命令行参数："all" 表示全部，否则为逗号分隔的参数名
+    pub fn from_arg(arg: &str) -> Self {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            QueryPropagation::None
+        } else if arg == "all" || arg == "*" {
+            QueryPropagation::All
+        } else {
+            QueryPropagation::Selected(
+                arg.split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect(),
+            )
+        }
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        match self {
+            QueryPropagation::None => false,
+            QueryPropagation::All => true,
+            QueryPropagation::Selected(names) => names.iter().any(|n| n == name),
+        }
+    }
+}
+
 // M3U8 内容解析器 - 负责解析播放列表内容
 pub struct ContentParser {
     ad_filters: Vec<Regex>,
+    query_propagation: QueryPropagation,
 }
 
 impl ContentParser {
@@ -15,21 +79,22 @@ impl ContentParser {
             .map(|pattern| Regex::new(pattern))
             .collect();
 
-        let compiled_filters = compiled_filters.map_err(|e| M3u8Error::RegexError(e))?;
+        let compiled_filters = compiled_filters.map_err(M3u8Error::RegexError)?;
 
         Ok(Self {
             ad_filters: compiled_filters,
+            query_propagation: QueryPropagation::None,
         })
     }
 
+    pub fn set_query_propagation(&mut self, query_propagation: QueryPropagation) {
+        self.query_propagation = query_propagation;
+    }
+
     // 解析播放列表内容并确定类型
     pub fn parse_content_type(&self, content: &str) -> PlaylistType {
-        let lines: Vec<&str> = content.lines().collect();
-
-        // 检查是否为有效的 M3U8 文件
-        if !lines.first().unwrap_or(&"").starts_with("#EXTM3U") {
-            return PlaylistType::Unknown;
-        }
+        // #EXTM3U 文件头由各解析器按解析模式检查，这里只根据标签判断类型
+        let lines: Vec<&str> = content.lines().map(str::trim).collect();
 
         // 检查是否包含变体流信息（主播放列表特征）
         for line in &lines {
@@ -54,11 +119,19 @@ impl ContentParser {
     }
 
     // 构建完整的 URL
+    // 带协议的绝对地址（http/https/file/data 等）直接使用，
+    // 其余按 RFC 3986 相对 base_url 解析（包括 //host/path 与 /abs/path）
     pub fn build_full_url(&self, url: &str, base_url: Option<&Url>) -> Result<String, M3u8Error> {
-        if url.starts_with("http") {
-            Ok(url.to_string())
+        if let Some(absolute) = parse_absolute_url(url) {
+            match base_url {
+                Some(base) if self.query_propagation != QueryPropagation::None => {
+                    Ok(self.propagate_query(absolute, base).to_string())
+                }
+                _ => Ok(url.to_string()),
+            }
         } else if let Some(base) = base_url {
-            Ok(base.join(url)?.to_string())
+            let full_url = base.join(url)?;
+            Ok(self.propagate_query(full_url, base).to_string())
         } else {
             Err(M3u8Error::ParseError(
                 "视频片段为相对URL，请手动配置 base_url".to_string(),
@@ -66,44 +139,100 @@ impl ContentParser {
         }
     }
 
-    // 解析 EXTINF 行
-    pub fn parse_extinf_line(&self, line: &str) -> (f64, Option<String>) {
-        let parts: Vec<&str> = line.split(':').collect();
-        if parts.len() < 2 {
-            return (0.0, None);
+    // 将上级地址中的查询参数附加到子地址，子地址已有的同名参数保持不变
+    // 仅作用于与上级地址同主机的子地址，避免把凭据泄露给其他主机
+    fn propagate_query(&self, mut url: Url, base: &Url) -> Url {
+        if self.query_propagation == QueryPropagation::None
+            || base.query().is_none()
+            || !matches!(url.scheme(), "http" | "https")
+            || url.host_str() != base.host_str()
+        {
+            return url;
         }
 
-        let content = parts[1];
-        let duration_part = content.split(',').next().unwrap_or("0.0");
-        let title = content.split(',').nth(1).map(|s| s.trim().to_string());
+        let existing: Vec<String> = url
+            .query_pairs()
+            .map(|(name, _)| name.into_owned())
+            .collect();
+        let inherited: Vec<(String, String)> = base
+            .query_pairs()
+            .filter(|(name, _)| self.query_propagation.allows(name))
+            .filter(|(name, _)| !existing.iter().any(|e| e == name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+
+        if !inherited.is_empty() {
+            url.query_pairs_mut().extend_pairs(inherited);
+        }
+        url
+    }
+
+    // 解析属性列表，如 METHOD=AES-128,URI="key.bin",IV=0x...，引号内的逗号不作为分隔符
+    pub fn parse_attribute_list(&self, content: &str) -> Vec<(String, String)> {
+        let mut attributes = Vec::new();
+        let mut rest = content.trim();
+
+        while !rest.is_empty() {
+            let Some((name, after)) = rest.split_once('=') else {
+                break;
+            };
+            let (value, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+                match quoted.find('"') {
+                    Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                    None => (quoted, ""),
+                }
+            } else {
+                match after.find(',') {
+                    Some(end) => (&after[..end], &after[end..]),
+                    None => (after, ""),
+                }
+            };
+
+            attributes.push((name.trim().to_string(), value.to_string()));
+            rest = remaining.trim_start_matches(',').trim_start();
+        }
+
+        attributes
+    }
+
+    // 解析 ISO 8601 日期时间，如 2024-01-01T00:00:00.000Z 或 2024-01-01T08:00:00+0800
+    pub fn parse_date_time(&self, value: &str) -> Option<DateTime<FixedOffset>> {
+        let value = value.trim();
+        DateTime::parse_from_rfc3339(value)
+            .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f%z"))
+            .ok()
+    }
 
-        let duration = duration_part.parse().unwrap_or(0.0);
-        (duration, title)
+    // 解析 EXTINF 行，返回 (时长, 标题)，时长无法解析时为 None
+    pub fn parse_extinf_line(&self, line: &str) -> (Option<f64>, Option<String>) {
+        let content = line.split_once(':').map_or("", |(_, content)| content);
+        let (duration, title) = match content.split_once(',') {
+            Some((duration, title)) => (duration, Some(title.trim().to_string())),
+            None => (content, None),
+        };
+        (duration.trim().parse().ok(), title)
     }
 
     // 解析 EXT-X-STREAM-INF 行
-    pub fn parse_stream_inf_line(
-        &self,
-        line: &str,
-    ) -> (Option<u32>, Option<(u32, u32)>, Option<String>) {
+    pub fn parse_stream_inf_line(&self, line: &str) -> StreamInfAttributes {
         let mut bandwidth = None;
         let mut resolution = None;
         let mut codecs = None;
 
-        // 解析属性
-        let attributes: Vec<&str> = line.split(',').collect();
-        for attr in attributes {
-            if let Some(bw) = attr.strip_prefix("BANDWIDTH=") {
-                bandwidth = bw.parse().ok();
-            } else if let Some(res) = attr.strip_prefix("RESOLUTION=") {
-                let parts: Vec<&str> = res.split('x').collect();
-                if parts.len() == 2 {
-                    if let (Ok(width), Ok(height)) = (parts[0].parse(), parts[1].parse()) {
-                        resolution = Some((width, height));
+        // 解析属性，CODECS 等带引号的值中可能包含逗号
+        let content = line.split_once(':').map_or("", |(_, content)| content);
+        for (name, value) in self.parse_attribute_list(content) {
+            match name.as_str() {
+                "BANDWIDTH" => bandwidth = value.parse().ok(),
+                "RESOLUTION" => {
+                    if let Some((width, height)) = value.split_once('x') {
+                        if let (Ok(width), Ok(height)) = (width.parse(), height.parse()) {
+                            resolution = Some((width, height));
+                        }
                     }
                 }
-            } else if let Some(c) = attr.strip_prefix("CODECS=") {
-                codecs = Some(c.trim_matches('"').to_string());
+                "CODECS" => codecs = Some(value),
+                _ => {}
             }
         }
 
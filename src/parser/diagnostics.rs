@@ -0,0 +1,180 @@
+use crate::error::M3u8Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// 解析模式：严格模式遇到不符合 RFC 8216 的内容即报错，
+// 宽松模式尽量容忍常见的服务端问题，并把问题记录为警告
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ParseMode {
+    Strict,
+    #[default]
+    Lenient,
+}
+
+impl FromStr for ParseMode {
+    type Err = M3u8Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "strict" => Ok(ParseMode::Strict),
+            "lenient" => Ok(ParseMode::Lenient),
+            other => Err(M3u8Error::ParseError(format!(
+                "未知的解析模式: {}，可选 strict/lenient",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ParseMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ParseMode::Strict => "strict",
+            ParseMode::Lenient => "lenient",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// 宽松模式下记录的问题，行号从 1 开始，0 表示与具体行无关
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParseWarning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "第 {} 行: {}", self.line, self.message)
+        } else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+// 按解析模式处理发现的问题
+#[derive(Default)]
+pub(crate) struct Diagnostics {
+    mode: ParseMode,
+    warnings: Vec<ParseWarning>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(mode: ParseMode) -> Self {
+        Self {
+            mode,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.mode == ParseMode::Strict
+    }
+
+    // 严格模式下返回带行号的错误，宽松模式下记录警告后继续
+    pub(crate) fn report(
+        &mut self,
+        line: usize,
+        message: impl Into<String>,
+    ) -> Result<(), M3u8Error> {
+        let warning = ParseWarning {
+            line,
+            message: message.into(),
+        };
+        match self.mode {
+            ParseMode::Strict => Err(M3u8Error::InvalidPlaylist(warning)),
+            ParseMode::Lenient => {
+                self.warnings.push(warning);
+                Ok(())
+            }
+        }
+    }
+
+    // 解析标签的数值，无法解析时按模式报错或返回 None
+    pub(crate) fn parse_value<T: FromStr>(
+        &mut self,
+        line: usize,
+        tag: &str,
+        value: &str,
+    ) -> Result<Option<T>, M3u8Error> {
+        match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => {
+                let tag = tag.trim_start_matches('#');
+                self.report(line, format!("{} 的值无效: {:?}", tag, value))?;
+                Ok(None)
+            }
+        }
+    }
+
+    // 按行号排列的警告
+    pub(crate) fn into_warnings(mut self) -> Vec<ParseWarning> {
+        self.warnings.sort_by_key(|warning| warning.line);
+        self.warnings
+    }
+}
+
+// 拆分播放列表的行，返回 (行号, 内容)；处理 BOM、CRLF 与单独的 CR 换行、
+// 行首尾空白以及 #EXTM3U 之前的空行，严格模式下这些问题均视为错误（CRLF 除外）
+pub(crate) fn split_lines<'a>(
+    content: &'a str,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<(usize, &'a str)>, M3u8Error> {
+    let content = match content.strip_prefix('\u{feff}') {
+        Some(content) => {
+            diagnostics.report(1, "文件以 UTF-8 BOM 开头")?;
+            content
+        }
+        None => content,
+    };
+
+    let mut lines = Vec::new();
+    let mut line_number = 0;
+    let mut reported_cr = false;
+    for line in content.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        // 个别服务端只使用 CR 换行，以 CR 分隔的每一段各算一行
+        for part in line.split('\r') {
+            line_number += 1;
+            if part.len() != line.len() && !reported_cr {
+                diagnostics.report(line_number, "使用单独的 CR 作为换行符")?;
+                reported_cr = true;
+            }
+            let trimmed = part.trim();
+            if trimmed.len() != part.len() && !trimmed.is_empty() {
+                diagnostics.report(line_number, "行首或行尾有多余的空白字符")?;
+            }
+            lines.push((line_number, trimmed));
+        }
+    }
+
+    // 去掉 #EXTM3U 之前的空行
+    let first = lines.iter().position(|(_, line)| !line.is_empty());
+    if let Some(first) = first.filter(|first| *first > 0) {
+        diagnostics.report(lines[first].0, "#EXTM3U 之前有空行")?;
+        lines.drain(..first);
+    }
+    Ok(lines)
+}
+
+// 检查第一行是否为 #EXTM3U，宽松模式下缺失时只记录警告
+pub(crate) fn check_header(
+    lines: &[(usize, &str)],
+    diagnostics: &mut Diagnostics,
+) -> Result<(), M3u8Error> {
+    match lines.first() {
+        Some((_, line)) if *line == "#EXTM3U" => Ok(()),
+        Some((line_number, line)) if line.starts_with("#EXTM3U") => {
+            diagnostics.report(*line_number, "#EXTM3U 之后有多余内容")
+        }
+        // 宽松模式下只要包含 M3U8 标签即继续解析
+        Some((line_number, _))
+            if !diagnostics.is_strict() && lines.iter().any(|(_, l)| l.starts_with("#EXT")) =>
+        {
+            diagnostics.report(*line_number, "缺少 #EXTM3U 文件头")
+        }
+        _ => Err(M3u8Error::ParseError("不是有效的 M3U8 文件".to_string())),
+    }
+}
@@ -1,77 +1,202 @@
 use crate::error::M3u8Error;
-use crate::parser::ContentParser;
-use crate::types::{M3u8Playlist, M3u8Variant, PlaylistType};
+use crate::parser::content_parser::{QueryPropagation, StreamInfAttributes};
+use crate::parser::diagnostics::{check_header, split_lines, Diagnostics};
+use crate::parser::{ContentParser, ParseMode};
+use crate::types::{M3u8Playlist, M3u8Rendition, M3u8Variant, PlaylistType};
 use url::Url;
 
 // 主播放列表解析器 - 负责解析包含多个变体流的主播放列表
 pub struct MasterParser {
     content_parser: ContentParser,
+    mode: ParseMode,
 }
 
 impl MasterParser {
     pub fn new(ad_filters: Vec<String>) -> Result<Self, M3u8Error> {
         Ok(Self {
             content_parser: ContentParser::new(ad_filters)?,
+            mode: ParseMode::default(),
         })
     }
 
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_query_propagation(mut self, query_propagation: QueryPropagation) -> Self {
+        self.content_parser.set_query_propagation(query_propagation);
+        self
+    }
+
     // 解析主播放列表内容
     pub fn parse(&self, content: &str, base_url: Option<&Url>) -> Result<M3u8Playlist, M3u8Error> {
         let mut playlist = M3u8Playlist::new(PlaylistType::Master);
-        let lines: Vec<&str> = content.lines().collect();
+        let mut diagnostics = Diagnostics::new(self.mode);
 
         // 检查是否为有效的 M3U8 文件
-        if !lines.first().unwrap_or(&"").starts_with("#EXTM3U") {
-            return Err(M3u8Error::ParseError("不是有效的 M3U8 文件".to_string()));
-        }
+        let lines = split_lines(content, &mut diagnostics)?;
+        check_header(&lines, &mut diagnostics)?;
 
-        let mut current_variant_attrs: Option<(Option<u32>, Option<(u32, u32)>, Option<String>)> =
-            None;
-
-        for i in 0..lines.len() {
-            let line = lines[i].trim();
+        let mut current_variant_attrs: Option<StreamInfAttributes> = None;
+        // 当前变体流引用的 AUDIO/VIDEO/SUBTITLES 媒体组
+        let mut current_groups = (None, None, None);
+        // EXT-X-STREAM-INF 所在的行号
+        let mut stream_inf_line = 0;
 
+        for (line_number, line) in lines {
             if line.is_empty() || line.starts_with("#EXTM3U") {
                 continue;
             }
 
-            if line.starts_with("#EXT-X-VERSION:") {
-                playlist.version = line
-                    .split(':')
-                    .nth(1)
-                    .and_then(|v| v.parse().ok())
+            if let Some(value) = line.strip_prefix("#EXT-X-VERSION:") {
+                playlist.version = diagnostics
+                    .parse_value(line_number, "#EXT-X-VERSION", value)?
                     .unwrap_or(1);
             } else if line.starts_with("#EXT-X-STREAM-INF:") {
+                if current_variant_attrs.is_some() {
+                    diagnostics.report(stream_inf_line, "EXT-X-STREAM-INF 之后缺少变体流 URI")?;
+                }
                 // 解析变体流属性
-                current_variant_attrs = Some(self.content_parser.parse_stream_inf_line(line));
-            } else if !line.starts_with('#') && current_variant_attrs.is_some() {
+                let attrs = self.content_parser.parse_stream_inf_line(line);
+                if attrs.0.is_none() {
+                    diagnostics.report(line_number, "EXT-X-STREAM-INF 缺少 BANDWIDTH 属性")?;
+                }
+                current_groups = self.parse_media_groups(line);
+                current_variant_attrs = Some(attrs);
+                stream_inf_line = line_number;
+            } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA:") {
+                if let Some(rendition) =
+                    self.parse_rendition(value, line_number, base_url, &mut diagnostics)?
+                {
+                    playlist.renditions.push(rendition);
+                }
+            } else if !line.starts_with('#') && current_variant_attrs.is_none() {
+                diagnostics.report(
+                    line_number,
+                    format!("URI 之前缺少 EXT-X-STREAM-INF: {}", line),
+                )?;
+            } else if !line.starts_with('#') {
                 // 这是变体流的 URL
                 if let Some((bandwidth, resolution, codecs)) = current_variant_attrs.take() {
                     let full_url = self.content_parser.build_full_url(line, base_url)?;
 
-                    if !self.content_parser.is_ad_url(&full_url) {
-                        let variant = M3u8Variant {
-                            url: full_url,
-                            bandwidth,
-                            resolution,
-                            codecs,
-                            audio: None,
-                            video: None,
-                            subtitles: None,
-                        };
-                        playlist.variants.push(variant);
+                    if self.content_parser.is_ad_url(&full_url) {
+                        continue;
                     }
+
+                    // 带宽、分辨率、编码及引用的媒体组都相同的变体流视为冗余备份流（RFC 8216 §6.3.3），
+                    // 媒体组不同的变体流内容不同（如不同语言的音轨），不能互为备份；
+                    // 未标注带宽的变体流无法判断是否冗余，各自保留
+                    let (audio, video, subtitles) = std::mem::take(&mut current_groups);
+                    let redundant = playlist.variants.iter_mut().find(|v| {
+                        bandwidth.is_some()
+                            && v.bandwidth == bandwidth
+                            && v.resolution == resolution
+                            && v.codecs == codecs
+                            && v.audio == audio
+                            && v.video == video
+                            && v.subtitles == subtitles
+                    });
+                    if let Some(primary) = redundant {
+                        if primary.url != full_url && !primary.backup_urls.contains(&full_url) {
+                            primary.backup_urls.push(full_url);
+                        }
+                        continue;
+                    }
+
+                    let variant = M3u8Variant {
+                        url: full_url,
+                        backup_urls: Vec::new(),
+                        bandwidth,
+                        resolution,
+                        codecs,
+                        audio,
+                        video,
+                        subtitles,
+                        line: stream_inf_line,
+                    };
+                    playlist.variants.push(variant);
                 }
             }
         }
 
+        if current_variant_attrs.is_some() {
+            diagnostics.report(stream_inf_line, "EXT-X-STREAM-INF 之后缺少变体流 URI")?;
+        }
         if playlist.variants.is_empty() {
             return Err(M3u8Error::EmptyError("未找到有效的变体流".to_string()));
         }
+        playlist.warnings = diagnostics.into_warnings();
 
         Ok(playlist)
     }
 
+    // 解析 EXT-X-STREAM-INF 引用的媒体组：(AUDIO, VIDEO, SUBTITLES)
+    fn parse_media_groups(&self, line: &str) -> (Option<String>, Option<String>, Option<String>) {
+        let content = line.split_once(':').map_or("", |(_, content)| content);
+        let mut groups = (None, None, None);
+        for (name, value) in self.content_parser.parse_attribute_list(content) {
+            match name.as_str() {
+                "AUDIO" => groups.0 = Some(value),
+                "VIDEO" => groups.1 = Some(value),
+                "SUBTITLES" => groups.2 = Some(value),
+                _ => {}
+            }
+        }
+        groups
+    }
+
+    // 解析 EXT-X-MEDIA，缺少 TYPE、GROUP-ID 或 NAME 时按模式报错或忽略
+    fn parse_rendition(
+        &self,
+        value: &str,
+        line_number: usize,
+        base_url: Option<&Url>,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Option<M3u8Rendition>, M3u8Error> {
+        let mut media_type = None;
+        let mut group_id = None;
+        let mut name = None;
+        let mut rendition = M3u8Rendition {
+            media_type: String::new(),
+            group_id: String::new(),
+            name: String::new(),
+            language: None,
+            uri: None,
+            default: false,
+            autoselect: false,
+        };
+
+        for (attr, attr_value) in self.content_parser.parse_attribute_list(value) {
+            match attr.as_str() {
+                "TYPE" => media_type = Some(attr_value),
+                "GROUP-ID" => group_id = Some(attr_value),
+                "NAME" => name = Some(attr_value),
+                "LANGUAGE" => rendition.language = Some(attr_value),
+                "URI" => {
+                    rendition.uri = Some(self.content_parser.build_full_url(&attr_value, base_url)?)
+                }
+                "DEFAULT" => rendition.default = attr_value == "YES",
+                "AUTOSELECT" => rendition.autoselect = attr_value == "YES",
+                _ => {}
+            }
+        }
+
+        match (media_type, group_id, name) {
+            (Some(media_type), Some(group_id), Some(name)) => {
+                rendition.media_type = media_type;
+                rendition.group_id = group_id;
+                rendition.name = name;
+                Ok(Some(rendition))
+            }
+            _ => {
+                diagnostics.report(line_number, "EXT-X-MEDIA 缺少 TYPE、GROUP-ID 或 NAME 属性")?;
+                Ok(None)
+            }
+        }
+    }
+
     // 获取最佳质量的变体流索引
     pub fn get_best_quality_index(&self, playlist: &M3u8Playlist) -> usize {
         let mut best_index = 0;
@@ -106,3 +231,70 @@ impl MasterParser {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> M3u8Playlist {
+        let base_url = Url::parse("https://example.com/master.m3u8").unwrap();
+        MasterParser::new(Vec::new())
+            .unwrap()
+            .parse(content, Some(&base_url))
+            .unwrap()
+    }
+
+    #[test]
+    fn groups_redundant_variants_as_backups() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1000000,RESOLUTION=1280x720,AUDIO=\"aac\"\n\
+             https://cdn1.example.com/720.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1920x1080,AUDIO=\"aac\"\n\
+             https://cdn1.example.com/1080.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1000000,RESOLUTION=1280x720,AUDIO=\"aac\"\n\
+             https://cdn2.example.com/720.m3u8\n",
+        );
+        assert_eq!(playlist.variants.len(), 2);
+        assert_eq!(
+            playlist.variants[0].backup_urls,
+            vec!["https://cdn2.example.com/720.m3u8".to_string()]
+        );
+        assert!(playlist.variants[1].backup_urls.is_empty());
+    }
+
+    #[test]
+    fn variants_with_different_media_groups_are_not_backups() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1000000,AUDIO=\"en\"\n\
+             en.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1000000,AUDIO=\"fr\"\n\
+             fr.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1000000,AUDIO=\"en\",SUBTITLES=\"subs\"\n\
+             en-subs.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=1000000,AUDIO=\"en\",VIDEO=\"angle2\"\n\
+             en-angle2.m3u8\n",
+        );
+        assert_eq!(playlist.variants.len(), 4);
+        assert!(playlist.variants.iter().all(|v| v.backup_urls.is_empty()));
+        assert_eq!(playlist.variants[1].audio.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn variants_without_bandwidth_are_not_backups() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-STREAM-INF:PROGRAM-ID=1\n\
+             a.m3u8\n\
+             #EXT-X-STREAM-INF:PROGRAM-ID=1\n\
+             b.m3u8\n",
+        );
+        let urls: Vec<_> = playlist.variants.iter().map(|v| v.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec!["https://example.com/a.m3u8", "https://example.com/b.m3u8"]
+        );
+        assert!(playlist.variants.iter().all(|v| v.backup_urls.is_empty()));
+    }
+}
@@ -1,136 +1,787 @@
 use crate::error::M3u8Error;
-use crate::parser::ContentParser;
-use crate::types::{M3u8Playlist, M3u8Segment, PlaylistType};
+use crate::parser::content_parser::QueryPropagation;
+use crate::parser::diagnostics::{check_header, split_lines, Diagnostics};
+use crate::parser::{AdDetector, ContentParser, ParseMode};
+use crate::scte35::SpliceInfo;
+use crate::types::{
+    AdBreak, CueEvent, CueMarker, DateRange, M3u8Key, M3u8Map, M3u8Playlist, M3u8Segment,
+    M3u8Start, MediaPlaylistType, PlaylistType,
+};
+use chrono::{DateTime, Duration, FixedOffset};
 use url::Url;
 
+// 下一个片段的信息，由 URI 行之前的标签累积
+#[derive(Default)]
+struct PendingSegment {
+    duration: Option<f64>,
+    title: Option<String>,
+    // (偏移量, 长度)，未指定偏移量时接续上一个字节范围
+    byte_range: Option<(Option<usize>, usize)>,
+    discontinuity: bool,
+    cue: Option<CueMarker>,
+    date_time: Option<DateTime<FixedOffset>>,
+    gap: bool,
+    unknown_tags: Vec<String>,
+}
+
+// 解析过程中跨片段的状态
+#[derive(Default)]
+struct ParseState {
+    segment: PendingSegment,
+    // 片段在播放列表中的位置，被过滤的片段也计入
+    sequence: usize,
+    ads_count: usize,
+    key: Option<M3u8Key>,
+    map: Option<M3u8Map>,
+    bitrate: Option<u32>,
+    // EXT-OATCLS-SCTE35 携带的数据，关联到随后的广告标记
+    splice: Option<SpliceInfo>,
+    // 由上一个片段推算的下一个片段时间
+    next_date_time: Option<DateTime<FixedOffset>>,
+    // 上一个字节范围所在的资源及结束位置
+    last_byte_range: Option<(String, usize)>,
+    // 当前行号
+    line: usize,
+    // EXTINF 所在的行号，用于报告缺少 URI 的片段；为 0 时尚未出现 EXTINF
+    extinf_line: usize,
+    diagnostics: Diagnostics,
+}
+
 // 媒体播放列表解析器 - 负责解析包含具体片段的媒体播放列表
 pub struct MediaParser {
     content_parser: ContentParser,
+    // 基于不连续点的广告检测，未设置时仅使用正则过滤
+    ad_detector: Option<AdDetector>,
+    mode: ParseMode,
 }
 
 impl MediaParser {
     pub fn new(ad_filters: Vec<String>) -> Result<Self, M3u8Error> {
         Ok(Self {
             content_parser: ContentParser::new(ad_filters)?,
+            ad_detector: None,
+            mode: ParseMode::default(),
         })
     }
 
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_ad_detector(mut self, ad_detector: Option<AdDetector>) -> Self {
+        self.ad_detector = ad_detector;
+        self
+    }
+
+    pub fn with_query_propagation(mut self, query_propagation: QueryPropagation) -> Self {
+        self.content_parser.set_query_propagation(query_propagation);
+        self
+    }
+
     // 解析媒体播放列表内容
+    // 逐行按标签名分派处理，片段相关的标签累积到下一个 URI 行时生成片段
     pub fn parse(&self, content: &str, base_url: Option<&Url>) -> Result<M3u8Playlist, M3u8Error> {
         let mut playlist = M3u8Playlist::new(PlaylistType::Media);
-        let lines: Vec<&str> = content.lines().collect();
+        let mut state = ParseState {
+            diagnostics: Diagnostics::new(self.mode),
+            ..ParseState::default()
+        };
 
         // 检查是否为有效的 M3U8 文件
-        if !lines.first().unwrap_or(&"").starts_with("#EXTM3U") {
-            return Err(M3u8Error::ParseError("不是有效的 M3U8 文件".to_string()));
-        }
-
-        let mut sequence = 0;
-        let mut ads_count = 0;
-        let mut i = 0;
-
-        while i < lines.len() {
-            let line = lines[i].trim();
+        let lines = split_lines(content, &mut state.diagnostics)?;
+        check_header(&lines, &mut state.diagnostics)?;
 
+        let mut has_target_duration = false;
+        for (line_number, line) in lines {
+            state.line = line_number;
             if line.is_empty() {
-                i += 1;
                 continue;
             }
+            if !line.starts_with('#') {
+                self.push_segment(line, &mut state, &mut playlist, base_url)?;
+            } else if line.to_ascii_uppercase().starts_with("#EXT") {
+                let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                // 个别服务端输出小写的标签名
+                let name = if name.starts_with("#EXT") {
+                    name.to_string()
+                } else {
+                    state
+                        .diagnostics
+                        .report(line_number, format!("标签名应为大写: {}", name))?;
+                    name.to_ascii_uppercase()
+                };
+                has_target_duration |= name == "#EXT-X-TARGETDURATION";
+                self.parse_tag(line, &name, value, &mut state, &mut playlist, base_url)?;
+            }
+            // 其余以 # 开头的行是注释
+        }
 
-            if line.starts_with("#EXT-X-VERSION:") {
-                playlist.version = line
-                    .split(':')
-                    .nth(1)
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(1);
-            } else if line.starts_with("#EXT-X-TARGETDURATION:") {
-                playlist.target_duration = line
-                    .split(':')
-                    .nth(1)
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(0.0);
-            } else if line.starts_with("#EXT-X-PLAYLIST-TYPE:") {
-                let playlist_type = line.split(':').nth(1).unwrap_or("");
-                playlist.is_live = playlist_type != "VOD";
-            } else if line.starts_with("#EXT-X-MEDIA-SEQUENCE:") {
-                playlist.media_sequence = line
-                    .split(':')
-                    .nth(1)
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(0);
-            } else if line.starts_with("#EXT-X-DISCONTINUITY-SEQUENCE:") {
-                playlist.discontinuity_sequence = line
-                    .split(':')
-                    .nth(1)
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(0);
-            } else if line.starts_with("#EXT-X-ENDLIST") {
+        if state.segment.duration.is_some() {
+            let line = state.extinf_line;
+            state.diagnostics.report(line, "EXTINF 之后缺少片段 URI")?;
+        }
+        if !has_target_duration {
+            state
+                .diagnostics
+                .report(0, "缺少必需的 EXT-X-TARGETDURATION 标签")?;
+        }
+
+        playlist.ads_count = state.ads_count;
+        playlist.unknown_tags.extend(state.segment.unknown_tags);
+        playlist.warnings = state.diagnostics.into_warnings();
+        if let Some(ad_detector) = &self.ad_detector {
+            let ad_breaks = ad_detector.detect(&playlist.segments);
+            Self::remove_ad_breaks(&mut playlist, ad_breaks);
+        }
+
+        if playlist.segments.is_empty() {
+            return Err(M3u8Error::EmptyError("未找到有效的视频片段".to_string()));
+        }
+
+        Ok(playlist)
+    }
+
+    // 处理一个标签，name 为冒号前的标签名，value 为冒号后的内容
+    fn parse_tag(
+        &self,
+        line: &str,
+        name: &str,
+        value: &str,
+        state: &mut ParseState,
+        playlist: &mut M3u8Playlist,
+        base_url: Option<&Url>,
+    ) -> Result<(), M3u8Error> {
+        let line_number = state.line;
+        let diagnostics = &mut state.diagnostics;
+        let pending = &mut state.segment;
+        match name {
+            // 播放列表级标签
+            "#EXTM3U" => {}
+            "#EXT-X-VERSION" => {
+                playlist.version = diagnostics
+                    .parse_value(line_number, name, value)?
+                    .unwrap_or(1)
+            }
+            "#EXT-X-TARGETDURATION" => {
+                // 规范要求为整数，部分服务端会输出小数
+                if value.parse::<u64>().is_err() && value.parse::<f64>().is_ok() {
+                    diagnostics.report(line_number, "EXT-X-TARGETDURATION 应为整数")?;
+                }
+                playlist.target_duration = diagnostics
+                    .parse_value(line_number, name, value)?
+                    .unwrap_or(0.0)
+            }
+            "#EXT-X-MEDIA-SEQUENCE" => {
+                playlist.media_sequence = diagnostics
+                    .parse_value(line_number, name, value)?
+                    .unwrap_or(0)
+            }
+            "#EXT-X-DISCONTINUITY-SEQUENCE" => {
+                playlist.discontinuity_sequence = diagnostics
+                    .parse_value(line_number, name, value)?
+                    .unwrap_or(0)
+            }
+            "#EXT-X-PLAYLIST-TYPE" => {
+                playlist.media_playlist_type = match value {
+                    "VOD" => Some(MediaPlaylistType::Vod),
+                    "EVENT" => Some(MediaPlaylistType::Event),
+                    _ => {
+                        diagnostics.report(
+                            line_number,
+                            format!("未知的 EXT-X-PLAYLIST-TYPE: {:?}", value),
+                        )?;
+                        None
+                    }
+                };
+                playlist.is_live = value != "VOD";
+            }
+            "#EXT-X-ENDLIST" => {
+                playlist.end_list = true;
                 playlist.is_live = false;
-            } else if line.starts_with("#EXTINF:") {
-                // 解析片段信息
-                let (duration, title) = self.content_parser.parse_extinf_line(line);
+            }
+            "#EXT-X-I-FRAMES-ONLY" => playlist.i_frames_only = true,
+            "#EXT-X-INDEPENDENT-SEGMENTS" => playlist.independent_segments = true,
+            "#EXT-X-START" => playlist.start = self.parse_start(value),
+            // 低延迟、缓存控制等不影响下载的播放列表标签
+            "#EXT-X-SERVER-CONTROL"
+            | "#EXT-X-PART-INF"
+            | "#EXT-X-PRELOAD-HINT"
+            | "#EXT-X-RENDITION-REPORT"
+            | "#EXT-X-ALLOW-CACHE"
+            | "#EXT-X-DEFINE" => playlist.unknown_tags.push(line.to_string()),
+            "#EXT-X-SKIP" => {
+                // 增量更新中被跳过的片段仍占用媒体序号
+                for (name, value) in self.content_parser.parse_attribute_list(value) {
+                    if name == "SKIPPED-SEGMENTS" {
+                        let skipped = diagnostics
+                            .parse_value(line_number, "SKIPPED-SEGMENTS", &value)?
+                            .unwrap_or(0);
+                        playlist.skipped_segments += skipped;
+                        state.sequence += skipped;
+                    }
+                }
+            }
 
-                // 下一行应该是 URL
-                if i + 1 < lines.len() {
-                    let segment_url = lines[i + 1].trim();
-                    if !segment_url.starts_with('#') && !segment_url.is_empty() {
-                        let full_url = self.content_parser.build_full_url(segment_url, base_url)?;
-
-                        // 检查是否匹配广告过滤规则
-                        let is_ad = self.content_parser.is_ad_url(&full_url);
-                        if is_ad {
-                            ads_count += 1;
-                        } else {
-                            let segment = M3u8Segment {
-                                url: full_url,
-                                duration,
-                                sequence,
-                                title,
-                                byte_range: None,
-                            };
-                            playlist.segments.push(segment);
-                        }
-                        sequence += 1;
-                        i += 1; // 跳过 URL 行
+            // 作用于下一个片段的标签
+            "#EXTINF" => {
+                let (duration, title) = self.content_parser.parse_extinf_line(line);
+                let duration = match duration {
+                    Some(duration) => duration,
+                    None => {
+                        let duration = value.split(',').next().unwrap_or(value).trim();
+                        diagnostics
+                            .report(line_number, format!("EXTINF 的时长无效: {:?}", duration))?;
+                        Self::leading_number(value)
                     }
+                };
+                if pending.duration.is_some() {
+                    diagnostics.report(state.extinf_line, "EXTINF 之后缺少片段 URI")?;
+                }
+                pending.duration = Some(duration);
+                pending.title = title;
+                state.extinf_line = line_number;
+            }
+            "#EXT-X-BYTERANGE" => {
+                pending.byte_range = self.parse_byte_range_value(value);
+                if pending.byte_range.is_none() {
+                    diagnostics.report(
+                        line_number,
+                        format!("EXT-X-BYTERANGE 的值无效: {:?}", value),
+                    )?;
+                }
+            }
+            "#EXT-X-DISCONTINUITY" => pending.discontinuity = true,
+            "#EXT-X-GAP" => pending.gap = true,
+            "#EXT-X-PROGRAM-DATE-TIME" => {
+                pending.date_time = self.content_parser.parse_date_time(value);
+                if pending.date_time.is_none() {
+                    diagnostics.report(
+                        line_number,
+                        format!("EXT-X-PROGRAM-DATE-TIME 的值无效: {:?}", value),
+                    )?;
+                }
+            }
+            "#EXT-OATCLS-SCTE35" => state.splice = SpliceInfo::from_base64(value).ok(),
+            "#EXT-X-CUE-OUT" | "#EXT-X-CUE-OUT-CONT" | "#EXT-X-CUE-IN" => {
+                let marker = match name {
+                    "#EXT-X-CUE-OUT" => CueMarker::Out,
+                    "#EXT-X-CUE-IN" => CueMarker::In,
+                    _ => CueMarker::Cont,
+                };
+                let event = self.parse_cue(marker, value, state.sequence, state.splice.take());
+                // CUE-OUT-CONT 不覆盖同一片段前更明确的开始/结束标记
+                pending.cue = match marker {
+                    CueMarker::Cont => pending.cue.or(Some(marker)),
+                    _ => Some(marker),
+                };
+                playlist.cue_events.push(event);
+            }
+            "#EXT-X-DATERANGE" => {
+                let date_range = self.parse_date_range(value, state.sequence);
+                if date_range.scte35_out.is_some() {
+                    pending.cue = Some(CueMarker::Out);
+                } else if date_range.scte35_in.is_some() {
+                    pending.cue = Some(CueMarker::In);
+                }
+                playlist.date_ranges.push(date_range);
+            }
+
+            // 作用于之后所有片段的标签
+            "#EXT-X-KEY" => {
+                state.key = self.parse_key(value, base_url)?;
+                if state.key.as_ref().is_some_and(|key| key.uri.is_none()) {
+                    diagnostics.report(line_number, "EXT-X-KEY 缺少 URI 属性")?;
+                }
+            }
+            "#EXT-X-MAP" => {
+                state.map = self.parse_map(value, base_url)?;
+                if state.map.is_none() {
+                    diagnostics.report(line_number, "EXT-X-MAP 缺少 URI 属性")?;
+                }
+            }
+            "#EXT-X-BITRATE" => {
+                state.bitrate = diagnostics.parse_value(line_number, name, value)?
+            }
+
+            // 第一个 EXTINF 之前的未知标签属于播放列表
+            _ if state.extinf_line == 0 => playlist.unknown_tags.push(line.to_string()),
+            _ => pending.unknown_tags.push(line.to_string()),
+        }
+        Ok(())
+    }
+
+    // 遇到 URI 行时，用累积的标签信息生成片段；前面没有 EXTINF 的 URI 行忽略
+    fn push_segment(
+        &self,
+        uri: &str,
+        state: &mut ParseState,
+        playlist: &mut M3u8Playlist,
+        base_url: Option<&Url>,
+    ) -> Result<(), M3u8Error> {
+        let Some(duration) = state.segment.duration else {
+            return state
+                .diagnostics
+                .report(state.line, format!("片段 URI 之前缺少 EXTINF: {}", uri));
+        };
+        let pending = std::mem::take(&mut state.segment);
+        let full_url = self.content_parser.build_full_url(uri, base_url)?;
+
+        // 下一个片段的时间：显式标注的 EXT-X-PROGRAM-DATE-TIME，或由上一个片段推算
+        let program_date_time = pending.date_time.or(state.next_date_time);
+        state.next_date_time = program_date_time
+            .map(|time| time + Duration::microseconds((duration * 1_000_000.0) as i64));
+
+        // 未指定偏移量的字节范围紧接在同一资源的上一个字节范围之后
+        let byte_range = match pending.byte_range {
+            Some((Some(offset), length)) => Some((offset, length)),
+            Some((None, length)) => match &state.last_byte_range {
+                Some((url, end)) if *url == full_url => Some((*end, length)),
+                _ => {
+                    state.diagnostics.report(
+                        state.line,
+                        "EXT-X-BYTERANGE 未指定偏移量，且上一个片段不是同一资源的字节范围",
+                    )?;
+                    Some((0, length))
                 }
-            } else if line.starts_with("#EXT-X-BYTERANGE:") {
-                // 处理字节范围（需要与前面的片段关联）
-                if let Some(last_segment) = playlist.segments.last_mut() {
-                    if let Some(byte_range) = self.parse_byte_range(line) {
-                        last_segment.byte_range = Some(byte_range);
+            },
+            None => None,
+        };
+        state.last_byte_range =
+            byte_range.map(|(offset, length)| (full_url.clone(), offset + length));
+
+        let sequence = state.sequence;
+        state.sequence += 1;
+
+        // 检查是否匹配广告过滤规则，被过滤片段上的不连续标记、广告标记与未知标签顺延到下一个片段
+        if self.content_parser.is_ad_url(&full_url) {
+            state.ads_count += 1;
+            state.segment.discontinuity = pending.discontinuity;
+            state.segment.cue = pending.cue;
+            state.segment.unknown_tags = pending.unknown_tags;
+            return Ok(());
+        }
+
+        playlist.segments.push(M3u8Segment {
+            url: full_url,
+            duration,
+            sequence,
+            title: pending.title,
+            byte_range,
+            backup_urls: Vec::new(),
+            key: state.key.clone(),
+            map: state.map.clone(),
+            discontinuity: pending.discontinuity,
+            cue: pending.cue,
+            program_date_time,
+            gap: pending.gap,
+            bitrate: state.bitrate,
+            unknown_tags: pending.unknown_tags,
+            line: state.extinf_line,
+        });
+        Ok(())
+    }
+
+    // 宽松模式下取时长开头的数字部分，如 "10.0s" 视为 10.0
+    fn leading_number(value: &str) -> f64 {
+        let end = value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len());
+        value[..end].parse().unwrap_or(0.0)
+    }
+
+    // 解析 EXT-X-START
+    fn parse_start(&self, attrs: &str) -> Option<M3u8Start> {
+        let mut time_offset = None;
+        let mut precise = false;
+        for (name, value) in self.content_parser.parse_attribute_list(attrs) {
+            match name.as_str() {
+                "TIME-OFFSET" => time_offset = value.parse().ok(),
+                "PRECISE" => precise = value == "YES",
+                _ => {}
+            }
+        }
+        time_offset.map(|time_offset| M3u8Start {
+            time_offset,
+            precise,
+        })
+    }
+
+    // 移除检测到的广告段落，并在被移除段落之后的片段上保留不连续标记
+    fn remove_ad_breaks(playlist: &mut M3u8Playlist, ad_breaks: Vec<AdBreak>) {
+        for ad_break in ad_breaks.iter().rev() {
+            let Some(start) = playlist
+                .segments
+                .iter()
+                .position(|s| s.sequence == ad_break.start_sequence)
+            else {
+                continue;
+            };
+            let end = start + ad_break.segment_count;
+            let discontinuity = playlist.segments[start].discontinuity;
+            playlist.segments.drain(start..end);
+            if start > 0 {
+                if let Some(next) = playlist.segments.get_mut(start) {
+                    next.discontinuity |= discontinuity;
+                }
+            }
+            playlist.ads_count += ad_break.segment_count;
+        }
+        playlist.ad_breaks = ad_breaks;
+    }
+
+    // 解析 CUE-OUT/CUE-OUT-CONT/CUE-IN 的值，支持 "30"、"DURATION=30"、
+    // "ElapsedTime=5,Duration=30,SCTE35=..." 与 "5/30" 等常见写法
+    fn parse_cue(
+        &self,
+        marker: CueMarker,
+        value: &str,
+        segment_sequence: usize,
+        splice_info: Option<SpliceInfo>,
+    ) -> CueEvent {
+        let mut event = CueEvent {
+            marker,
+            duration: None,
+            elapsed: None,
+            splice_info,
+            segment_sequence,
+        };
+
+        let value = value.trim();
+        if !value.contains('=') {
+            match value.split_once('/') {
+                Some((elapsed, duration)) => {
+                    event.elapsed = elapsed.trim().parse().ok();
+                    event.duration = duration.trim().parse().ok();
+                }
+                None => event.duration = value.parse().ok(),
+            }
+        } else {
+            for (name, value) in self.content_parser.parse_attribute_list(value) {
+                match name.to_ascii_uppercase().as_str() {
+                    "DURATION" => event.duration = value.parse().ok(),
+                    "ELAPSEDTIME" => event.elapsed = value.parse().ok(),
+                    "SCTE35" | "CUE" => {
+                        event.splice_info = SpliceInfo::from_base64(&value)
+                            .or_else(|_| SpliceInfo::from_hex(&value))
+                            .ok()
+                            .or(event.splice_info);
                     }
+                    _ => {}
                 }
             }
+        }
 
-            i += 1;
+        if event.duration.is_none() {
+            event.duration = event.splice_info.as_ref().and_then(SpliceInfo::duration);
         }
+        event
+    }
 
-        playlist.ads_count = ads_count;
+    // 解析 EXT-X-DATERANGE，无法解码的 SCTE35 数据视为缺失
+    fn parse_date_range(&self, attrs: &str, segment_sequence: usize) -> DateRange {
+        let mut date_range = DateRange {
+            id: String::new(),
+            class: None,
+            start_date: None,
+            end_date: None,
+            duration: None,
+            planned_duration: None,
+            end_on_next: false,
+            scte35_cmd: None,
+            scte35_out: None,
+            scte35_in: None,
+            client_attributes: Vec::new(),
+            segment_sequence,
+        };
 
-        if playlist.segments.is_empty() {
-            return Err(M3u8Error::EmptyError("未找到有效的视频片段".to_string()));
+        for (name, value) in self.content_parser.parse_attribute_list(attrs) {
+            match name.as_str() {
+                "ID" => date_range.id = value,
+                "CLASS" => date_range.class = Some(value),
+                "START-DATE" => date_range.start_date = self.content_parser.parse_date_time(&value),
+                "END-DATE" => date_range.end_date = self.content_parser.parse_date_time(&value),
+                "DURATION" => date_range.duration = value.parse().ok(),
+                "PLANNED-DURATION" => date_range.planned_duration = value.parse().ok(),
+                "END-ON-NEXT" => date_range.end_on_next = value == "YES",
+                "SCTE35-CMD" => date_range.scte35_cmd = SpliceInfo::from_hex(&value).ok(),
+                "SCTE35-OUT" => date_range.scte35_out = SpliceInfo::from_hex(&value).ok(),
+                "SCTE35-IN" => date_range.scte35_in = SpliceInfo::from_hex(&value).ok(),
+                _ if name.starts_with("X-") => date_range.client_attributes.push((name, value)),
+                _ => {}
+            }
         }
 
-        Ok(playlist)
+        date_range
     }
 
-    // 解析字节范围
-    fn parse_byte_range(&self, line: &str) -> Option<(usize, usize)> {
-        let content = line.strip_prefix("#EXT-X-BYTERANGE:")?;
-        let parts: Vec<&str> = content.split('@').collect();
-
-        if parts.len() == 1 {
-            // 只有长度
-            let length = parts[0].parse().ok()?;
-            Some((0, length))
-        } else if parts.len() == 2 {
-            // 长度@偏移量
-            let length = parts[0].parse().ok()?;
-            let offset = parts[1].parse().ok()?;
-            Some((offset, length))
+    // 解析 EXT-X-KEY，METHOD=NONE 表示后续片段不加密
+    fn parse_key(&self, attrs: &str, base_url: Option<&Url>) -> Result<Option<M3u8Key>, M3u8Error> {
+        let mut key = M3u8Key {
+            method: "NONE".to_string(),
+            uri: None,
+            iv: None,
+            key_format: None,
+        };
+
+        for (name, value) in self.content_parser.parse_attribute_list(attrs) {
+            match name.as_str() {
+                "METHOD" => key.method = value,
+                "URI" => key.uri = Some(self.content_parser.build_full_url(&value, base_url)?),
+                "IV" => key.iv = Some(value),
+                "KEYFORMAT" => key.key_format = Some(value),
+                _ => {}
+            }
+        }
+
+        if key.method == "NONE" {
+            Ok(None)
         } else {
-            None
+            Ok(Some(key))
+        }
+    }
+
+    // 解析 EXT-X-MAP
+    fn parse_map(&self, attrs: &str, base_url: Option<&Url>) -> Result<Option<M3u8Map>, M3u8Error> {
+        let mut uri = None;
+        let mut byte_range = None;
+
+        for (name, value) in self.content_parser.parse_attribute_list(attrs) {
+            match name.as_str() {
+                "URI" => uri = Some(self.content_parser.build_full_url(&value, base_url)?),
+                "BYTERANGE" => {
+                    byte_range = self
+                        .parse_byte_range_value(&value)
+                        .map(|(offset, length)| (offset.unwrap_or(0), length))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(uri.map(|uri| M3u8Map { uri, byte_range }))
+    }
+
+    // 解析 "长度[@偏移量]" 格式的字节范围，返回 (偏移量, 长度)
+    fn parse_byte_range_value(&self, content: &str) -> Option<(Option<usize>, usize)> {
+        match content.trim().split_once('@') {
+            Some((length, offset)) => Some((Some(offset.parse().ok()?), length.parse().ok()?)),
+            None => Some((None, content.trim().parse().ok()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> M3u8Playlist {
+        let base_url = Url::parse("https://example.com/live/index.m3u8").unwrap();
+        MediaParser::new(Vec::new())
+            .unwrap()
+            .with_mode(ParseMode::Strict)
+            .parse(content, Some(&base_url))
+            .unwrap()
+    }
+
+    #[test]
+    fn tags_and_comments_between_extinf_and_uri_apply_to_the_segment() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\n\
+             #EXT-X-BYTERANGE:1000@0\n\
+             # 注释\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+             #EXT-X-CUSTOM:1\n\
+             media.ts\n\
+             #EXTINF:8,\n\
+             #EXT-X-BYTERANGE:500\n\
+             media.ts\n\
+             #EXT-X-ENDLIST\n",
+        );
+
+        assert_eq!(playlist.segments.len(), 2);
+        let first = &playlist.segments[0];
+        assert_eq!(first.url, "https://example.com/live/media.ts");
+        assert_eq!(first.duration, 10.0);
+        assert_eq!(first.byte_range, Some((0, 1000)));
+        assert_eq!(
+            first.key.as_ref().and_then(|key| key.uri.as_deref()),
+            Some("https://example.com/live/key.bin")
+        );
+        assert_eq!(first.unknown_tags, vec!["#EXT-X-CUSTOM:1".to_string()]);
+
+        // 未指定偏移量时接续上一个片段的字节范围，密钥作用于之后的所有片段
+        let second = &playlist.segments[1];
+        assert_eq!(second.duration, 8.0);
+        assert_eq!(second.byte_range, Some((1000, 500)));
+        assert!(second.key.is_some());
+        assert!(second.unknown_tags.is_empty());
+    }
+
+    #[test]
+    fn implicit_byte_range_offset_follows_the_same_resource() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\n\
+             #EXT-X-BYTERANGE:100@50\n\
+             a.mp4\n\
+             #EXTINF:10,\n\
+             #EXT-X-BYTERANGE:200\n\
+             a.mp4\n\
+             #EXTINF:10,\n\
+             #EXT-X-BYTERANGE:300@0\n\
+             b.mp4\n\
+             #EXTINF:10,\n\
+             #EXT-X-BYTERANGE:400\n\
+             b.mp4\n",
+        );
+        let ranges: Vec<_> = playlist.segments.iter().map(|s| s.byte_range).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                Some((50, 100)),
+                Some((150, 200)),
+                Some((0, 300)),
+                Some((300, 400))
+            ]
+        );
+
+        // 上一个片段不是同一资源时，严格模式报错
+        let result = MediaParser::new(Vec::new())
+            .unwrap()
+            .with_mode(ParseMode::Strict)
+            .parse(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:10\n\
+                 #EXTINF:10,\n#EXT-X-BYTERANGE:100@0\nhttps://example.com/a.mp4\n\
+                 #EXTINF:10,\n#EXT-X-BYTERANGE:100\nhttps://example.com/b.mp4\n",
+                None,
+            );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn skipped_segments_keep_their_sequence_numbers() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:4\n\
+             #EXT-X-MEDIA-SEQUENCE:100\n\
+             #EXT-X-SKIP:SKIPPED-SEGMENTS=3\n\
+             #EXTINF:4,\n\
+             a.ts\n\
+             #EXTINF:4,\n\
+             b.ts\n",
+        );
+        assert_eq!(playlist.skipped_segments, 3);
+        let sequences: Vec<_> = playlist.segments.iter().map(|s| s.sequence).collect();
+        assert_eq!(sequences, vec![3, 4]);
+    }
+
+    #[test]
+    fn playlist_level_tags_are_not_attached_to_segments() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:4\n\
+             #EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES\n\
+             #EXT-X-VENDOR:1\n\
+             #EXTINF:4,\n\
+             a.ts\n\
+             #EXT-X-PART-INF:PART-TARGET=1.0\n\
+             #EXTINF:4,\n\
+             b.ts\n\
+             #EXT-X-TRAILER:1\n",
+        );
+        assert_eq!(
+            playlist.unknown_tags,
+            vec![
+                "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES".to_string(),
+                "#EXT-X-VENDOR:1".to_string(),
+                "#EXT-X-PART-INF:PART-TARGET=1.0".to_string(),
+                "#EXT-X-TRAILER:1".to_string(),
+            ]
+        );
+        assert!(playlist.segments.iter().all(|s| s.unknown_tags.is_empty()));
+    }
+
+    #[test]
+    fn filtered_segment_passes_its_tags_to_the_next_segment() {
+        let base_url = Url::parse("https://example.com/live/index.m3u8").unwrap();
+        let playlist = MediaParser::new(vec!["/ad/".to_string()])
+            .unwrap()
+            .parse(
+                "#EXTM3U\n\
+                 #EXT-X-TARGETDURATION:4\n\
+                 #EXTINF:4,\n\
+                 a.ts\n\
+                 #EXT-X-DISCONTINUITY\n\
+                 #EXT-X-CUSTOM:1\n\
+                 #EXTINF:4,\n\
+                 ad/1.ts\n\
+                 #EXTINF:4,\n\
+                 b.ts\n",
+                Some(&base_url),
+            )
+            .unwrap();
+        assert_eq!(playlist.ads_count, 1);
+        let next = &playlist.segments[1];
+        assert_eq!(next.url, "https://example.com/live/b.ts");
+        assert!(next.discontinuity);
+        assert_eq!(next.unknown_tags, vec!["#EXT-X-CUSTOM:1".to_string()]);
+    }
+
+    #[test]
+    fn warnings_report_the_offending_line() {
+        // 只用 CR 换行的播放列表，每一段按单独的行编号
+        let playlist = MediaParser::new(Vec::new())
+            .unwrap()
+            .parse(
+                "#EXTM3U\r#EXT-X-TARGETDURATION:10\r#EXTINF:abc,\rhttps://example.com/a.ts\r",
+                None,
+            )
+            .unwrap();
+        let warnings: Vec<_> = playlist
+            .warnings
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "第 1 行: 使用单独的 CR 作为换行符".to_string(),
+                "第 3 行: EXTINF 的时长无效: \"abc\"".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_scte35_attribute_is_ignored() {
+        let playlist = parse(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:4\n\
+             #EXT-X-DATERANGE:ID=\"ad\",START-DATE=\"2024-05-01T14:00:00Z\",SCTE35-OUT=0x1é1\n\
+             #EXTINF:4,\n\
+             a.ts\n",
+        );
+        assert_eq!(playlist.date_ranges.len(), 1);
+        assert!(playlist.date_ranges[0].scte35_out.is_none());
+    }
+
+    #[test]
+    fn strict_mode_error_carries_the_line() {
+        let result = MediaParser::new(Vec::new())
+            .unwrap()
+            .with_mode(ParseMode::Strict)
+            .parse(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:abc,\nhttps://example.com/a.ts\n",
+                None,
+            );
+        match result {
+            Err(M3u8Error::InvalidPlaylist(warning)) => {
+                assert_eq!(warning.line, 3);
+                assert_eq!(warning.message, "EXTINF 的时长无效: \"abc\"");
+            }
+            other => panic!("应返回带行号的错误: {:?}", other),
         }
     }
 }
@@ -1,9 +1,13 @@
+pub mod ad_detector;
 pub mod content_parser;
+pub mod diagnostics;
 pub mod master_parser;
 pub mod media_parser;
 pub mod nested_parser;
 
-pub use content_parser::ContentParser;
+pub use ad_detector::{AdDetector, DEFAULT_AD_SCORE_THRESHOLD};
+pub use content_parser::{ContentParser, QueryPropagation};
+pub use diagnostics::{ParseMode, ParseWarning};
 pub use master_parser::MasterParser;
 pub use media_parser::MediaParser;
 pub use nested_parser::NestedParser;
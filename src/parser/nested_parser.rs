@@ -1,6 +1,11 @@
 use crate::error::M3u8Error;
-use crate::parser::{ContentParser, MasterParser, MediaParser};
-use crate::types::{M3u8Playlist, M3u8Segment, NestedM3u8, PlaylistType};
+use crate::fetcher::PlaylistFetcher;
+use crate::parser::content_parser::{resolve_base_url, QueryPropagation};
+use crate::parser::{AdDetector, ContentParser, MasterParser, MediaParser, ParseMode};
+use crate::source::SegmentSource;
+use crate::types::{M3u8Playlist, M3u8Segment, M3u8Variant, NestedM3u8, PlaylistType};
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
 use url::Url;
 
 // 嵌套播放列表解析器 - 负责处理包含主播放列表和多个媒体播放列表的嵌套结构
@@ -8,6 +13,8 @@ pub struct NestedParser {
     content_parser: ContentParser,
     master_parser: MasterParser,
     media_parser: MediaParser,
+    // 请求播放列表时附加的请求头
+    headers: HeaderMap,
 }
 
 impl NestedParser {
@@ -16,19 +23,67 @@ impl NestedParser {
             content_parser: ContentParser::new(ad_filters.clone())?,
             master_parser: MasterParser::new(ad_filters.clone())?,
             media_parser: MediaParser::new(ad_filters)?,
+            headers: HeaderMap::new(),
         })
     }
 
+    // 设置查询参数传递方式，作用于变体流、片段、密钥与初始化片段地址
+    pub fn with_query_propagation(mut self, query_propagation: QueryPropagation) -> Self {
+        self.master_parser = self
+            .master_parser
+            .with_query_propagation(query_propagation.clone());
+        self.media_parser = self.media_parser.with_query_propagation(query_propagation);
+        self
+    }
+
+    // 设置解析模式，作用于主播放列表与所有媒体播放列表
+    pub fn with_mode(mut self, mode: ParseMode) -> Self {
+        self.master_parser = self.master_parser.with_mode(mode);
+        self.media_parser = self.media_parser.with_mode(mode);
+        self
+    }
+
+    // 设置基于不连续点的广告检测，作用于所有媒体播放列表
+    pub fn with_ad_detector(mut self, ad_detector: Option<AdDetector>) -> Self {
+        self.media_parser = self.media_parser.with_ad_detector(ad_detector);
+        self
+    }
+
+    // 设置请求播放列表时附加的请求头
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    // 按地址类型从 URL 或本地文件解析嵌套播放列表，base_url 只作用于本地文件
+    pub async fn parse_location(
+        &self,
+        location: &str,
+        base_url: Option<&str>,
+        fetcher: &impl PlaylistFetcher,
+    ) -> Result<NestedM3u8, M3u8Error> {
+        match SegmentSource::parse(location)? {
+            SegmentSource::Http(url) => self.parse_from_url(&url, fetcher).await,
+            SegmentSource::File(path) => {
+                self.parse_from_file(&path.to_string_lossy(), base_url, fetcher)
+                    .await
+            }
+            SegmentSource::Data(data) => {
+                self.parse_content(&playlist_text(data)?, base_url, fetcher)
+                    .await
+            }
+        }
+    }
+
     // 从 URL 解析嵌套播放列表
     pub async fn parse_from_url(
         &self,
         url: &str,
-        client: &reqwest::Client,
+        fetcher: &impl PlaylistFetcher,
     ) -> Result<NestedM3u8, M3u8Error> {
-        let response = client.get(url).send().await?;
-        let content = response.text().await?;
+        let content = fetcher.fetch_playlist(url, &self.headers).await?;
 
-        self.parse_content(&content, Some(url), client).await
+        self.parse_content(&content, Some(url), fetcher).await
     }
 
     // 从文件解析嵌套播放列表
@@ -36,13 +91,20 @@ impl NestedParser {
         &self,
         file_path: &str,
         base_url: Option<&str>,
-        client: &reqwest::Client,
+        fetcher: &impl PlaylistFetcher,
     ) -> Result<NestedM3u8, M3u8Error> {
         let content = tokio::fs::read_to_string(file_path)
             .await
-            .map_err(|e| M3u8Error::IoError(e))?;
+            .map_err(M3u8Error::IoError)?;
 
-        self.parse_content(&content, base_url, &client).await
+        // 未指定 base_url 时以播放列表文件所在位置作为基础地址，相对路径片段解析为本地文件
+        let inferred_base = match base_url {
+            Some(_) => None,
+            None => resolve_base_url(file_path).map(|url| url.to_string()),
+        };
+        let base_url = base_url.or(inferred_base.as_deref());
+
+        self.parse_content(&content, base_url, fetcher).await
     }
 
     // 解析嵌套播放列表内容
@@ -50,9 +112,9 @@ impl NestedParser {
         &self,
         content: &str,
         base_url: Option<&str>,
-        client: &reqwest::Client,
+        fetcher: &impl PlaylistFetcher,
     ) -> Result<NestedM3u8, M3u8Error> {
-        let base_url_obj = base_url.and_then(|url| Url::parse(url).ok());
+        let base_url_obj = base_url.and_then(resolve_base_url);
 
         // 首先确定播放列表类型
         let playlist_type = self.content_parser.parse_content_type(content);
@@ -60,7 +122,7 @@ impl NestedParser {
         match playlist_type {
             PlaylistType::Master => {
                 // 这是主播放列表，需要递归解析所有变体流
-                self.parse_master_playlist(content, &base_url_obj, client)
+                self.parse_master_playlist(content, &base_url_obj, fetcher)
                     .await
             }
             PlaylistType::Media => {
@@ -79,7 +141,7 @@ impl NestedParser {
         &self,
         content: &str,
         base_url: &Option<Url>,
-        client: &reqwest::Client,
+        fetcher: &impl PlaylistFetcher,
     ) -> Result<NestedM3u8, M3u8Error> {
         let mut nested = NestedM3u8::new();
 
@@ -88,7 +150,9 @@ impl NestedParser {
 
         // 递归解析所有变体流
         for variant in &nested.master_playlist.variants {
-            let media_playlist = self.parse_variant_playlist(&variant.url, client).await?;
+            let mut media_playlist = self.parse_variant_playlist(&variant.url, fetcher).await?;
+            self.attach_backup_segments(&mut media_playlist, variant, fetcher)
+                .await;
             nested.media_playlists.push(media_playlist);
         }
 
@@ -101,28 +165,57 @@ impl NestedParser {
         Ok(nested)
     }
 
-    // 解析单个变体流播放列表
-    async fn parse_variant_playlist(
+    // 解析单个变体流（媒体）播放列表
+    pub async fn parse_variant_playlist(
         &self,
         url: &str,
-        client: &reqwest::Client,
+        fetcher: &impl PlaylistFetcher,
     ) -> Result<M3u8Playlist, M3u8Error> {
-        let response = client.get(url).send().await?;
-
-        if !response.status().is_success() {
-            return Err(M3u8Error::ParseError(format!(
-                "无法获取变体流播放列表: {} - {}",
-                url,
-                response.status()
-            )));
+        // 本地主播放列表中的变体流解析为 file:// 地址，直接读取文件
+        let content = match SegmentSource::parse(url)? {
+            SegmentSource::File(path) => tokio::fs::read_to_string(&path)
+                .await
+                .map_err(M3u8Error::IoError),
+            SegmentSource::Data(data) => playlist_text(data),
+            SegmentSource::Http(_) => fetcher.fetch_playlist(url, &self.headers).await,
         }
-
-        let content = response.text().await?;
+        .map_err(|e| M3u8Error::ParseError(format!("无法获取变体流播放列表: {} - {}", url, e)))?;
         let base_url_obj = Url::parse(url).ok();
 
         self.media_parser.parse(&content, base_url_obj.as_ref())
     }
 
+    // 解析冗余备份流，并按媒体序号把备份片段地址关联到主播放列表的片段上
+    async fn attach_backup_segments(
+        &self,
+        playlist: &mut M3u8Playlist,
+        variant: &M3u8Variant,
+        fetcher: &impl PlaylistFetcher,
+    ) {
+        for backup_url in &variant.backup_urls {
+            let backup = match self.parse_variant_playlist(backup_url, fetcher).await {
+                Ok(backup) => backup,
+                Err(e) => {
+                    eprintln!("⚠️  备份流不可用，已忽略: {}", e);
+                    continue;
+                }
+            };
+
+            let backup_segments: HashMap<usize, &str> = backup
+                .segments
+                .iter()
+                .map(|s| (backup.segment_media_sequence(s), s.url.as_str()))
+                .collect();
+
+            let media_sequence = playlist.media_sequence;
+            for segment in &mut playlist.segments {
+                if let Some(url) = backup_segments.get(&(media_sequence + segment.sequence)) {
+                    segment.backup_urls.push(url.to_string());
+                }
+            }
+        }
+    }
+
     // 解析单个媒体播放列表并包装为嵌套结构
     async fn parse_single_media_playlist(
         &self,
@@ -171,3 +264,10 @@ impl NestedParser {
             .collect()
     }
 }
+
+// data: URI 内嵌的播放列表内容
+fn playlist_text(data: Vec<u8>) -> Result<String, M3u8Error> {
+    String::from_utf8(data).map_err(|_| {
+        M3u8Error::ParseError("data: URI 中的播放列表不是有效的 UTF-8 文本".to_string())
+    })
+}
@@ -1,6 +1,20 @@
 use rand::Rng;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::time::timeout;
+use url::Url;
+
+// 支持的代理协议
+const SUPPORTED_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+// 连续失败达到该次数后暂时停用代理
+const EJECT_AFTER_FAILURES: u32 = 3;
+// 代理停用时长，到期后重新探测
+const EJECT_COOLDOWN: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 pub struct ProxyInfo {
@@ -8,30 +22,183 @@ pub struct ProxyInfo {
     pub weight: u32,
 }
 
+impl ProxyInfo {
+    // 解析代理地址，未指定协议时默认为 http，支持 user:pass@ 认证信息
+    pub fn parse(raw: &str, weight: u32) -> Result<Self, String> {
+        let raw = raw.trim();
+        let full_url = if raw.contains("://") {
+            raw.to_string()
+        } else {
+            format!("http://{}", raw)
+        };
+
+        let url = Url::parse(&full_url).map_err(|e| format!("代理URL解析失败: {}", e))?;
+        if !SUPPORTED_SCHEMES.contains(&url.scheme()) {
+            return Err(format!(
+                "不支持的代理协议: {}，可选 {}",
+                url.scheme(),
+                SUPPORTED_SCHEMES.join("/")
+            ));
+        }
+        if url.host_str().is_none() {
+            return Err("代理URL缺少主机名".to_string());
+        }
+
+        Ok(Self {
+            url: url.to_string(),
+            weight,
+        })
+    }
+
+    // 隐藏认证信息后的地址，用于日志输出
+    pub fn masked_url(&self) -> String {
+        mask_proxy_url(&self.url)
+    }
+}
+
+// 将代理地址中的密码替换为 ***
+pub fn mask_proxy_url(proxy_url: &str) -> String {
+    match Url::parse(proxy_url) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("***"));
+            url.to_string()
+        }
+        _ => proxy_url.to_string(),
+    }
+}
+
+// 各代理协议的默认端口
+fn default_port(scheme: &str) -> u16 {
+    match scheme {
+        "https" => 443,
+        "socks5" | "socks5h" => 1080,
+        _ => 80,
+    }
+}
+
+// 代理运行时统计
+#[derive(Debug, Clone, Default)]
+pub struct ProxyStats {
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    // 正在进行的请求数
+    pub in_flight: u32,
+    // 平均响应延迟（指数滑动平均）
+    pub avg_latency: Option<Duration>,
+    // 停用截止时间，为空表示代理可用
+    pub ejected_until: Option<Instant>,
+}
+
+impl ProxyStats {
+    pub fn is_ejected(&self) -> bool {
+        self.ejected_until.is_some()
+    }
+
+    // 根据成功率降低失败较多代理的权重，最低为 1
+    fn effective_weight(&self, weight: u32) -> u64 {
+        let total = self.successes + self.failures + 1;
+        (weight as u64 * (self.successes + 1) / total).max(1)
+    }
+}
+
+// 代理轮换策略
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ProxyStrategy {
+    // 按权重随机选择
+    #[default]
+    Weighted,
+    // 依次轮换
+    RoundRobin,
+    // 选择正在进行请求最少的代理
+    LeastInFlight,
+    // 同一主机固定使用同一代理
+    Sticky,
+    // 每次请求使用新连接并按权重随机选择代理，适用于按连接切换出口的代理网关
+    PerRequest,
+}
+
+impl FromStr for ProxyStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weighted" => Ok(ProxyStrategy::Weighted),
+            "round-robin" => Ok(ProxyStrategy::RoundRobin),
+            "least-in-flight" => Ok(ProxyStrategy::LeastInFlight),
+            "sticky" => Ok(ProxyStrategy::Sticky),
+            "per-request" => Ok(ProxyStrategy::PerRequest),
+            _ => Err(format!(
+                "未知的代理轮换策略: {}，可选 weighted/round-robin/least-in-flight/sticky/per-request",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ProxyStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyStrategy::Weighted => write!(f, "weighted"),
+            ProxyStrategy::RoundRobin => write!(f, "round-robin"),
+            ProxyStrategy::LeastInFlight => write!(f, "least-in-flight"),
+            ProxyStrategy::Sticky => write!(f, "sticky"),
+            ProxyStrategy::PerRequest => write!(f, "per-request"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProxyConfig {
     proxies: Vec<ProxyInfo>,
-    total_weight: u32,
+    stats: Arc<Mutex<Vec<ProxyStats>>>,
+    health_check: ProxyHealthCheck,
+    strategy: ProxyStrategy,
+    round_robin: Arc<AtomicUsize>,
+    sticky_hosts: Arc<Mutex<HashMap<String, usize>>>,
 }
 
-async fn is_proxy_valid(proxy_url: &str) -> Result<(), String> {
-    let full_url = if proxy_url.starts_with("http://") || proxy_url.starts_with("https://") {
-        proxy_url.to_string()
-    } else {
-        format!("http://{}", proxy_url)
-    };
+// 代理健康检查配置
+#[derive(Debug, Clone)]
+pub struct ProxyHealthCheck {
+    // 是否在添加代理时进行检查
+    pub enabled: bool,
+    // 检查时请求的地址，为空时仅测试代理端口是否可达
+    pub target: Option<String>,
+    // 期望的响应状态码，为空时收到任意 HTTP 响应即视为代理可用，
+    // 与下载时的判断一致：4xx/5xx 来自目标服务器，说明代理本身工作正常
+    pub expect_status: Option<u16>,
+    // 期望响应内容中包含的文本
+    pub expect_body: Option<String>,
+    pub timeout: Duration,
+}
+
+impl Default for ProxyHealthCheck {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            target: None,
+            expect_status: None,
+            expect_body: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+async fn is_proxy_valid(proxy: &ProxyInfo, check: &ProxyHealthCheck) -> Result<(), String> {
+    if !check.enabled {
+        return Ok(());
+    }
 
     // 首先测试代理服务器是否可达
-    let proxy_url = url::Url::parse(&full_url).map_err(|e| format!("代理URL解析失败: {}", e))?;
+    let proxy_url = Url::parse(&proxy.url).map_err(|e| format!("代理URL解析失败: {}", e))?;
     let host = proxy_url.host_str().ok_or("代理URL缺少主机名")?;
-    let port = proxy_url.port().unwrap_or(80);
+    let port = proxy_url
+        .port()
+        .unwrap_or_else(|| default_port(proxy_url.scheme()));
 
     // 尝试TCP连接到代理服务器
-    let tcp_result = timeout(
-        Duration::from_secs(5),
-        tokio::net::TcpStream::connect((host, port)),
-    )
-    .await;
+    let tcp_result = timeout(check.timeout, tokio::net::TcpStream::connect((host, port))).await;
 
     match tcp_result {
         Ok(Ok(_stream)) => {
@@ -45,69 +212,74 @@ async fn is_proxy_valid(proxy_url: &str) -> Result<(), String> {
         }
     }
 
-    let proxy = reqwest::Proxy::all(&full_url).map_err(|e| format!("代理配置错误: {}", e))?;
+    // 未配置检查地址时，端口可达即视为可用
+    let target = match &check.target {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+
+    let proxy = reqwest::Proxy::all(&proxy.url).map_err(|e| format!("代理配置错误: {}", e))?;
     let client = reqwest::Client::builder()
         .proxy(proxy)
-        // .timeout(Duration::from_secs(5))
         .build()
         .map_err(|e| format!("创建代理客户端失败: {}", e))?;
 
-    // 使用一个简单的请求测试代理
-    let http_result = timeout(
-        Duration::from_secs(5),
-        client.get("https://www.baidu.com").send(),
-    )
-    .await;
-
-    match http_result {
-        Ok(Ok(response)) => {
-            if response.status().is_success() {
-                let text = response
-                    .text()
-                    .await
-                    .map_err(|e| format!("读取响应失败: {}", e))?;
-
-                // 检查响应
-                if text.contains("百度一下") {
-                    Ok(())
-                } else {
-                    Err("代理响应格式异常".to_string())
-                }
-            } else {
-                Err(format!("代理响应错误: {}", response.status()))
-            }
+    // 通过代理请求检查地址
+    let http_result = timeout(check.timeout, client.get(target).send()).await;
+
+    let response = match http_result {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => return Err(format!("代理HTTP请求失败: {}", e)),
+        Err(_) => return Err("代理HTTP请求超时".to_string()),
+    };
+
+    let status = response.status();
+    if check
+        .expect_status
+        .is_some_and(|expected| status.as_u16() != expected)
+    {
+        return Err(format!("代理响应错误: {}", status));
+    }
+
+    if let Some(expect_body) = &check.expect_body {
+        let text = timeout(check.timeout, response.text())
+            .await
+            .map_err(|_| "读取响应超时".to_string())?
+            .map_err(|e| format!("读取响应失败: {}", e))?;
+
+        if !text.contains(expect_body.as_str()) {
+            return Err("代理响应格式异常".to_string());
         }
-        Ok(Err(e)) => Err(format!("代理HTTP请求失败: {}", e)),
-        Err(_) => Err("代理HTTP请求超时".to_string()),
     }
+
+    Ok(())
 }
 
 impl ProxyConfig {
     pub fn new() -> Self {
+        Self::with_health_check(ProxyHealthCheck::default())
+    }
+
+    pub fn with_health_check(health_check: ProxyHealthCheck) -> Self {
         Self {
             proxies: Vec::new(),
-            total_weight: 0,
+            stats: Arc::new(Mutex::new(Vec::new())),
+            health_check,
+            strategy: ProxyStrategy::default(),
+            round_robin: Arc::new(AtomicUsize::new(0)),
+            sticky_hosts: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    pub async fn from_args(proxy_args: &[String]) -> Result<Self, String> {
-        let mut config = Self::new();
+    pub async fn from_args(
+        proxy_args: &[String],
+        health_check: ProxyHealthCheck,
+    ) -> Result<Self, String> {
+        let mut config = Self::with_health_check(health_check);
 
         for proxy_arg in proxy_args {
-            let parts: Vec<&str> = proxy_arg.trim().split(',').collect();
-            if parts.len() != 2 {
-                return Err(format!(
-                    "代理格式错误: {}，应为 'weight,proxy_url'",
-                    proxy_arg
-                ));
-            }
-
-            let weight: u32 = parts[0]
-                .parse()
-                .map_err(|_| format!("权重解析错误: {}", parts[0]))?;
-
-            let url = parts[1].to_string();
-            config.add_proxy(url, weight).await;
+            let proxy = Self::parse_proxy_arg(proxy_arg)?;
+            config.add_proxy(proxy).await;
         }
 
         if config.proxies.is_empty() {
@@ -117,39 +289,416 @@ impl ProxyConfig {
         Ok(config)
     }
 
-    pub async fn add_proxy(&mut self, url: String, weight: u32) {
-        match is_proxy_valid(&url).await {
+    // 读取代理列表文件，每行一个代理，格式同命令行参数，# 开头为注释
+    pub async fn read_proxy_list(path: &Path) -> Result<Vec<String>, String> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("读取代理列表文件失败 {:?}: {}", path, e))?;
+
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    // 解析单个代理参数: "weight,proxy_url" 或 "proxy_url"（权重默认为 1）
+    fn parse_proxy_arg(proxy_arg: &str) -> Result<ProxyInfo, String> {
+        let proxy_arg = proxy_arg.trim();
+        let (weight, url) = match proxy_arg.split_once(',') {
+            Some((weight, url)) => {
+                let weight: u32 = weight
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("权重解析错误: {}", weight))?;
+                (weight, url)
+            }
+            None => (1, proxy_arg),
+        };
+
+        if weight == 0 {
+            return Err(format!(
+                "代理权重必须大于 0: {}",
+                mask_proxy_url(url.trim())
+            ));
+        }
+
+        ProxyInfo::parse(url, weight).map_err(|e| {
+            format!(
+                "代理格式错误: {}，应为 '[weight,]proxy_url'，{}",
+                mask_proxy_url(url.trim()),
+                e
+            )
+        })
+    }
+
+    pub async fn add_proxy(&mut self, proxy: ProxyInfo) {
+        match is_proxy_valid(&proxy, &self.health_check).await {
             Ok(()) => {
-                self.proxies.push(ProxyInfo { url, weight });
-                self.total_weight += weight;
+                self.proxies.push(proxy);
+                self.stats.lock().unwrap().push(ProxyStats::default());
             }
             Err(e) => {
-                eprintln!("❌ 代理[{}]配置错误: {}", url, e);
-                return;
+                eprintln!("❌ 代理[{}]配置错误: {}", proxy.masked_url(), e);
             }
         }
     }
 
     pub fn get_random_proxy(&self) -> Option<&str> {
+        self.select_proxy(None, None)
+            .map(|index| self.proxies[index].url.as_str())
+    }
+
+    pub fn proxies(&self) -> &[ProxyInfo] {
+        &self.proxies
+    }
+
+    pub fn strategy(&self) -> ProxyStrategy {
+        self.strategy
+    }
+
+    pub fn set_strategy(&mut self, strategy: ProxyStrategy) {
+        self.strategy = strategy;
+    }
+
+    // 按轮换策略选择代理，跳过已停用的代理和 exclude 指定的代理
+    // host 为请求目标主机，用于按主机固定代理
+    pub fn select_proxy(&self, host: Option<&str>, exclude: Option<usize>) -> Option<usize> {
+        let stats = self.stats.lock().unwrap();
+        self.select_from(&stats, host, exclude)
+    }
+
+    // 选择代理并记录一次开始的请求，两者在同一次加锁内完成，
+    // 避免并发请求看到相同的并发数而集中到同一个代理
+    pub fn begin_request(&self, host: Option<&str>, exclude: Option<usize>) -> Option<usize> {
+        let mut stats = self.stats.lock().unwrap();
+        let index = self.select_from(&stats, host, exclude)?;
+        if let Some(stat) = stats.get_mut(index) {
+            stat.in_flight += 1;
+        }
+        Some(index)
+    }
+
+    fn select_from(
+        &self,
+        stats: &[ProxyStats],
+        host: Option<&str>,
+        exclude: Option<usize>,
+    ) -> Option<usize> {
         if self.proxies.is_empty() {
             return None;
         }
 
-        let mut rng = rand::thread_rng();
-        let mut random_weight = rng.gen_range(0..self.total_weight);
+        let available = |allow_excluded: bool| -> Vec<usize> {
+            (0..self.proxies.len())
+                .filter(|index| !stats[*index].is_ejected())
+                .filter(|index| allow_excluded || Some(*index) != exclude)
+                .collect()
+        };
+
+        // 优先换用其他可用代理，其次是刚失败的代理，全部停用时仍需返回一个
+        let mut candidates = available(false);
+        if candidates.is_empty() {
+            candidates = available(true);
+        }
+        if candidates.is_empty() {
+            return Some(rand::thread_rng().gen_range(0..self.proxies.len()));
+        }
+
+        let index = match self.strategy {
+            ProxyStrategy::Weighted | ProxyStrategy::PerRequest => {
+                self.pick_weighted(&candidates, stats)
+            }
+            ProxyStrategy::RoundRobin => {
+                let next = self.round_robin.fetch_add(1, Ordering::Relaxed);
+                candidates[next % candidates.len()]
+            }
+            ProxyStrategy::LeastInFlight => *candidates
+                .iter()
+                .min_by_key(|index| stats[**index].in_flight)
+                .unwrap_or(&candidates[0]),
+            ProxyStrategy::Sticky => {
+                let host = host.unwrap_or_default().to_string();
+                let mut sticky = self.sticky_hosts.lock().unwrap();
+                match sticky.get(&host) {
+                    Some(index) if candidates.contains(index) => *index,
+                    _ => {
+                        let index = self.pick_weighted(&candidates, stats);
+                        sticky.insert(host, index);
+                        index
+                    }
+                }
+            }
+        };
+
+        Some(index)
+    }
+
+    // 按健康度加权随机选择
+    fn pick_weighted(&self, candidates: &[usize], stats: &[ProxyStats]) -> usize {
+        let weights: Vec<u64> = candidates
+            .iter()
+            .map(|index| stats[*index].effective_weight(self.proxies[*index].weight))
+            .collect();
 
-        for proxy in &self.proxies {
-            if random_weight < proxy.weight {
-                return Some(&proxy.url);
+        let total_weight: u64 = weights.iter().sum();
+        let mut random_weight = rand::thread_rng().gen_range(0..total_weight);
+        for (index, weight) in candidates.iter().zip(&weights) {
+            if random_weight < *weight {
+                return *index;
             }
-            random_weight -= proxy.weight;
+            random_weight -= weight;
         }
 
-        // 备用方案，返回第一个代理
-        Some(&self.proxies[0].url)
+        // 备用方案，返回第一个候选代理
+        candidates[0]
+    }
+
+    // 记录一次成功请求及其延迟
+    pub fn record_success(&self, index: usize, latency: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(stat) = stats.get_mut(index) {
+            stat.in_flight = stat.in_flight.saturating_sub(1);
+            stat.successes += 1;
+            stat.consecutive_failures = 0;
+            stat.avg_latency = Some(match stat.avg_latency {
+                Some(avg) => (avg * 4 + latency) / 5,
+                None => latency,
+            });
+        }
+    }
+
+    // 记录一次失败请求，连续失败过多时暂时停用该代理
+    pub fn record_failure(&self, index: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        if let Some(stat) = stats.get_mut(index) {
+            stat.in_flight = stat.in_flight.saturating_sub(1);
+            stat.failures += 1;
+            stat.consecutive_failures += 1;
+            if stat.consecutive_failures >= EJECT_AFTER_FAILURES && !stat.is_ejected() {
+                stat.ejected_until = Some(Instant::now() + EJECT_COOLDOWN);
+                eprintln!(
+                    "⚠️  代理[{}]连续失败 {} 次，暂时停用",
+                    self.proxies[index].masked_url(),
+                    stat.consecutive_failures
+                );
+            }
+        }
+    }
+
+    // 重新探测停用期已满的代理，探测通过则恢复使用
+    pub async fn reprobe_ejected(&self) {
+        let now = Instant::now();
+        let due: Vec<usize> = {
+            let stats = self.stats.lock().unwrap();
+            stats
+                .iter()
+                .enumerate()
+                .filter(|(_, stat)| stat.ejected_until.is_some_and(|until| until <= now))
+                .map(|(index, _)| index)
+                .collect()
+        };
+
+        for index in due {
+            let result = is_proxy_valid(&self.proxies[index], &self.health_check).await;
+            let mut stats = self.stats.lock().unwrap();
+            let stat = &mut stats[index];
+            if result.is_ok() {
+                stat.consecutive_failures = 0;
+                stat.ejected_until = None;
+            } else {
+                stat.ejected_until = Some(Instant::now() + EJECT_COOLDOWN);
+            }
+        }
+    }
+
+    // 获取各代理的运行时统计
+    pub fn stats(&self) -> Vec<(String, ProxyStats)> {
+        let stats = self.stats.lock().unwrap();
+        self.proxies
+            .iter()
+            .zip(stats.iter())
+            .map(|(proxy, stat)| (proxy.masked_url(), stat.clone()))
+            .collect()
     }
 
     pub fn len(&self) -> usize {
         self.proxies.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 不做健康检查，直接按权重添加代理
+    fn config(weights: &[u32], strategy: ProxyStrategy) -> ProxyConfig {
+        let mut config = ProxyConfig::new();
+        config.set_strategy(strategy);
+        for (index, weight) in weights.iter().enumerate() {
+            let url = format!("http://127.0.0.1:{}", 8000 + index);
+            config
+                .proxies
+                .push(ProxyInfo::parse(&url, *weight).unwrap());
+            config.stats.lock().unwrap().push(ProxyStats::default());
+        }
+        config
+    }
+
+    // 统计 times 次选择中各代理被选中的次数
+    fn picks(config: &ProxyConfig, times: usize, exclude: Option<usize>) -> Vec<usize> {
+        let mut counts = vec![0; config.len()];
+        for _ in 0..times {
+            counts[config.select_proxy(Some("example.com"), exclude).unwrap()] += 1;
+        }
+        counts
+    }
+
+    fn eject(config: &ProxyConfig, index: usize) {
+        for _ in 0..EJECT_AFTER_FAILURES {
+            config.record_failure(index);
+        }
+    }
+
+    #[test]
+    fn round_robin_cycles_through_available_proxies() {
+        let config = config(&[1, 1, 1], ProxyStrategy::RoundRobin);
+        let order: Vec<usize> = (0..6)
+            .map(|_| config.select_proxy(None, None).unwrap())
+            .collect();
+        assert_eq!(order, vec![0, 1, 2, 0, 1, 2]);
+
+        eject(&config, 1);
+        assert_eq!(picks(&config, 10, None), vec![5, 0, 5]);
+    }
+
+    #[test]
+    fn weighted_selection_follows_weights() {
+        let config = config(&[1, 9], ProxyStrategy::Weighted);
+        let counts = picks(&config, 2000, None);
+        assert!(counts[1] > 1600 && counts[0] > 50, "{:?}", counts);
+
+        // 失败较多的代理权重降低
+        for _ in 0..30 {
+            config.record_failure(1);
+            config.record_failure(1);
+            config.record_success(1, Duration::from_millis(10));
+        }
+        assert_eq!(config.stats()[1].1.effective_weight(9), 3);
+        let counts = picks(&config, 2000, None);
+        assert!(counts[1] < 1600, "{:?}", counts);
+    }
+
+    #[test]
+    fn per_request_picks_randomly_and_avoids_excluded() {
+        let config = config(&[1, 1, 1], ProxyStrategy::PerRequest);
+        let counts = picks(&config, 300, None);
+        assert!(counts.iter().all(|count| *count > 0), "{:?}", counts);
+        assert_eq!(picks(&config, 100, Some(2))[2], 0);
+    }
+
+    #[test]
+    fn sticky_keeps_a_host_on_one_proxy() {
+        let config = config(&[1, 1, 1, 1], ProxyStrategy::Sticky);
+        let first = config.select_proxy(Some("a.example.com"), None).unwrap();
+        for _ in 0..20 {
+            assert_eq!(
+                config.select_proxy(Some("a.example.com"), None),
+                Some(first)
+            );
+        }
+
+        // 固定的代理停用后改用其他代理并记住新的选择
+        eject(&config, first);
+        let second = config.select_proxy(Some("a.example.com"), None).unwrap();
+        assert_ne!(second, first);
+        for _ in 0..20 {
+            assert_eq!(
+                config.select_proxy(Some("a.example.com"), None),
+                Some(second)
+            );
+        }
+    }
+
+    #[test]
+    fn ejects_after_consecutive_failures() {
+        let config = config(&[1, 1], ProxyStrategy::RoundRobin);
+        for _ in 0..EJECT_AFTER_FAILURES - 1 {
+            config.record_failure(0);
+        }
+        // 成功后重新计数
+        config.record_success(0, Duration::from_millis(10));
+        for _ in 0..EJECT_AFTER_FAILURES - 1 {
+            config.record_failure(0);
+        }
+        assert!(!config.stats()[0].1.is_ejected());
+
+        config.record_failure(0);
+        let stats = config.stats();
+        assert!(stats[0].1.is_ejected());
+        assert_eq!(stats[0].1.failures, 2 * EJECT_AFTER_FAILURES as u64 - 1);
+        assert_eq!(picks(&config, 10, None), vec![0, 10]);
+
+        // 全部停用时仍返回一个代理
+        eject(&config, 1);
+        assert!(config.select_proxy(None, None).is_some());
+    }
+
+    #[test]
+    fn excluded_proxy_is_used_only_as_last_resort() {
+        let config = config(&[1, 1], ProxyStrategy::Weighted);
+        assert_eq!(picks(&config, 50, Some(0)), vec![0, 50]);
+        eject(&config, 1);
+        assert_eq!(picks(&config, 50, Some(0)), vec![50, 0]);
+    }
+
+    #[test]
+    fn least_in_flight_spreads_concurrent_requests() {
+        let config = config(&[1, 1, 1], ProxyStrategy::LeastInFlight);
+        let indices: Vec<usize> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..6)
+                .map(|_| scope.spawn(|| config.begin_request(Some("example.com"), None).unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut counts = vec![0; 3];
+        for index in indices {
+            counts[index] += 1;
+        }
+        assert_eq!(counts, vec![2, 2, 2]);
+        assert!(config.stats().iter().all(|(_, stats)| stats.in_flight == 2));
+    }
+
+    #[test]
+    fn finished_requests_release_in_flight() {
+        let config = config(&[1, 1], ProxyStrategy::LeastInFlight);
+        let first = config.begin_request(None, None).unwrap();
+        let second = config.begin_request(None, None).unwrap();
+        assert_ne!(first, second);
+
+        config.record_success(first, Duration::from_millis(10));
+        assert_eq!(config.stats()[first].1.in_flight, 0);
+        // 空闲的代理优先
+        assert_eq!(config.begin_request(None, None), Some(first));
+
+        config.record_failure(second);
+        config.record_failure(first);
+        assert!(config.stats().iter().all(|(_, stats)| stats.in_flight == 0));
+
+        // 多余的上报不会使计数下溢
+        config.record_success(first, Duration::from_millis(10));
+        assert_eq!(config.stats()[first].1.in_flight, 0);
+    }
 }
@@ -0,0 +1,542 @@
+// SCTE-35 splice_info_section 解码，用于 EXT-X-DATERANGE 的 SCTE35-CMD/OUT/IN
+// 及 EXT-X-CUE-OUT-CONT、EXT-OATCLS-SCTE35 等标签中携带的二进制数据
+use crate::error::M3u8Error;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+// SCTE-35 时间刻度（90kHz）
+pub const SCTE35_TIMESCALE: f64 = 90000.0;
+
+const TABLE_ID: u8 = 0xFC;
+const SEGMENTATION_DESCRIPTOR: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpliceInfo {
+    pub protocol_version: u8,
+    pub encrypted: bool,
+    pub pts_adjustment: u64,
+    pub tier: u16,
+    pub command: SpliceCommand,
+    pub descriptors: Vec<SpliceDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SpliceCommand {
+    Null,
+    Schedule,
+    Insert(SpliceInsert),
+    // 时间信号，具体含义由分段描述符给出
+    TimeSignal { pts_time: Option<u64> },
+    BandwidthReservation,
+    Private { identifier: u32 },
+    Unknown { command_type: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpliceInsert {
+    pub event_id: u32,
+    pub cancel: bool,
+    // true 表示离开正片进入广告，false 表示回到正片
+    pub out_of_network: bool,
+    pub program_splice: bool,
+    pub splice_immediate: bool,
+    pub pts_time: Option<u64>,
+    pub break_duration: Option<BreakDuration>,
+    pub unique_program_id: u16,
+    pub avail_num: u8,
+    pub avails_expected: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakDuration {
+    pub auto_return: bool,
+    // 90kHz
+    pub duration: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SpliceDescriptor {
+    Segmentation(SegmentationDescriptor),
+    Other {
+        tag: u8,
+        identifier: u32,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentationDescriptor {
+    pub event_id: u32,
+    pub cancel: bool,
+    // 90kHz
+    pub duration: Option<u64>,
+    pub upid_type: u8,
+    pub upid: Vec<u8>,
+    pub type_id: u8,
+    pub segment_num: u8,
+    pub segments_expected: u8,
+}
+
+impl SegmentationDescriptor {
+    // 分段类型名称（SCTE-35 表 23 中与广告相关的常用类型）
+    pub fn type_name(&self) -> &'static str {
+        match self.type_id {
+            0x10 => "Program Start",
+            0x11 => "Program End",
+            0x20 => "Chapter Start",
+            0x21 => "Chapter End",
+            0x22 => "Break Start",
+            0x23 => "Break End",
+            0x30 => "Provider Advertisement Start",
+            0x31 => "Provider Advertisement End",
+            0x32 => "Distributor Advertisement Start",
+            0x33 => "Distributor Advertisement End",
+            0x34 => "Provider Placement Opportunity Start",
+            0x35 => "Provider Placement Opportunity End",
+            0x36 => "Distributor Placement Opportunity Start",
+            0x37 => "Distributor Placement Opportunity End",
+            0x40 => "Unscheduled Event Start",
+            0x41 => "Unscheduled Event End",
+            _ => "Other",
+        }
+    }
+
+    // 是否为广告或广告位开始
+    pub fn is_ad_start(&self) -> bool {
+        matches!(self.type_id, 0x22 | 0x30 | 0x32 | 0x34 | 0x36)
+    }
+
+    // 是否为广告或广告位结束
+    pub fn is_ad_end(&self) -> bool {
+        matches!(self.type_id, 0x23 | 0x31 | 0x33 | 0x35 | 0x37)
+    }
+}
+
+impl SpliceInfo {
+    // 解码 0x 开头的十六进制字符串（EXT-X-DATERANGE 中的格式）
+    pub fn from_hex(value: &str) -> Result<Self, M3u8Error> {
+        let hex = value
+            .trim()
+            .trim_start_matches("0x")
+            .trim_start_matches("0X");
+        let invalid = || M3u8Error::ParseError(format!("无效的 SCTE-35 数据: {}", value));
+        // 按字节处理，非 ASCII 字符不能按字符串下标切分
+        if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let data = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                let digit = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+                Some(digit(pair[0])? << 4 | digit(pair[1])?)
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        Self::decode(&data)
+    }
+
+    // 解码 Base64 字符串（EXT-X-CUE-OUT-CONT、EXT-OATCLS-SCTE35 中的格式）
+    pub fn from_base64(value: &str) -> Result<Self, M3u8Error> {
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(value.trim())
+            .map_err(|_| M3u8Error::ParseError(format!("无效的 SCTE-35 数据: {}", value)))?;
+        Self::decode(&data)
+    }
+
+    // 按 SCTE-35 规范解码 splice_info_section，不校验 CRC
+    pub fn decode(data: &[u8]) -> Result<Self, M3u8Error> {
+        Self::decode_section(data)
+            .ok_or_else(|| M3u8Error::ParseError("SCTE-35 数据不完整或格式错误".to_string()))
+    }
+
+    // 广告时长（秒），取自 splice_insert 的 break_duration 或分段描述符的时长
+    pub fn duration(&self) -> Option<f64> {
+        let duration = match &self.command {
+            SpliceCommand::Insert(insert) => insert.break_duration.as_ref().map(|b| b.duration),
+            _ => None,
+        };
+        duration
+            .or_else(|| {
+                self.segmentation_descriptors()
+                    .find_map(|descriptor| descriptor.duration)
+            })
+            .map(|duration| duration as f64 / SCTE35_TIMESCALE)
+    }
+
+    // 是否表示进入广告
+    pub fn is_out(&self) -> bool {
+        match &self.command {
+            SpliceCommand::Insert(insert) => !insert.cancel && insert.out_of_network,
+            _ => self
+                .segmentation_descriptors()
+                .any(SegmentationDescriptor::is_ad_start),
+        }
+    }
+
+    // 是否表示回到正片
+    pub fn is_in(&self) -> bool {
+        match &self.command {
+            SpliceCommand::Insert(insert) => !insert.cancel && !insert.out_of_network,
+            _ => self
+                .segmentation_descriptors()
+                .any(SegmentationDescriptor::is_ad_end),
+        }
+    }
+
+    pub fn segmentation_descriptors(&self) -> impl Iterator<Item = &SegmentationDescriptor> {
+        self.descriptors
+            .iter()
+            .filter_map(|descriptor| match descriptor {
+                SpliceDescriptor::Segmentation(segmentation) => Some(segmentation),
+                SpliceDescriptor::Other { .. } => None,
+            })
+    }
+
+    fn decode_section(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(data);
+        if reader.read(8)? as u8 != TABLE_ID {
+            return None;
+        }
+        // section_length 与实际长度不符的数据较常见，按实际内容解析
+        reader.skip(16)?;
+
+        let protocol_version = reader.read(8)? as u8;
+        let encrypted = reader.read(1)? == 1;
+        reader.skip(6)?;
+        let pts_adjustment = reader.read(33)?;
+        reader.skip(8)?;
+        let tier = reader.read(12)? as u16;
+        let command_length = reader.read(12)? as usize;
+        let command_type = reader.read(8)? as u8;
+
+        // 加密的数据无法继续解析命令与描述符
+        if encrypted {
+            return Some(Self {
+                protocol_version,
+                encrypted,
+                pts_adjustment,
+                tier,
+                command: SpliceCommand::Unknown { command_type },
+                descriptors: Vec::new(),
+            });
+        }
+
+        let command_start = reader.position();
+        let command = match command_type {
+            0x00 => SpliceCommand::Null,
+            0x04 => SpliceCommand::Schedule,
+            0x05 => SpliceCommand::Insert(Self::decode_insert(&mut reader)?),
+            0x06 => SpliceCommand::TimeSignal {
+                pts_time: Self::decode_splice_time(&mut reader)?,
+            },
+            0x07 => SpliceCommand::BandwidthReservation,
+            0xFF => SpliceCommand::Private {
+                identifier: reader.read(32)? as u32,
+            },
+            _ => SpliceCommand::Unknown { command_type },
+        };
+        // 旧版本中 splice_command_length 可能为 0xFFF，表示未指定
+        if command_length != 0xFFF {
+            reader.seek(command_start + command_length)?;
+        }
+
+        let descriptor_loop_length = reader.read(16)? as usize;
+        let descriptors_end = reader.position() + descriptor_loop_length;
+        // 描述符被截断时无法判断广告信号，按数据不完整处理
+        if descriptors_end > data.len() {
+            return None;
+        }
+        let mut descriptors = Vec::new();
+        while reader.position() + 2 <= descriptors_end {
+            let tag = reader.read(8)? as u8;
+            let length = reader.read(8)? as usize;
+            // 长度超出数据时按剩余内容解析，无法解析的描述符之后不再继续
+            let end = (reader.position() + length).min(data.len());
+            let Some(descriptor) = Self::decode_descriptor(tag, &data[reader.position()..end])
+            else {
+                break;
+            };
+            descriptors.push(descriptor);
+            reader.seek(end)?;
+        }
+
+        Some(Self {
+            protocol_version,
+            encrypted,
+            pts_adjustment,
+            tier,
+            command,
+            descriptors,
+        })
+    }
+
+    fn decode_splice_time(reader: &mut BitReader) -> Option<Option<u64>> {
+        if reader.read(1)? == 1 {
+            reader.skip(6)?;
+            Some(Some(reader.read(33)?))
+        } else {
+            reader.skip(7)?;
+            Some(None)
+        }
+    }
+
+    fn decode_insert(reader: &mut BitReader) -> Option<SpliceInsert> {
+        let mut insert = SpliceInsert {
+            event_id: reader.read(32)? as u32,
+            cancel: reader.read(1)? == 1,
+            out_of_network: false,
+            program_splice: false,
+            splice_immediate: false,
+            pts_time: None,
+            break_duration: None,
+            unique_program_id: 0,
+            avail_num: 0,
+            avails_expected: 0,
+        };
+        reader.skip(7)?;
+        if insert.cancel {
+            return Some(insert);
+        }
+
+        insert.out_of_network = reader.read(1)? == 1;
+        insert.program_splice = reader.read(1)? == 1;
+        let duration_flag = reader.read(1)? == 1;
+        insert.splice_immediate = reader.read(1)? == 1;
+        reader.skip(4)?;
+
+        if insert.program_splice && !insert.splice_immediate {
+            insert.pts_time = Self::decode_splice_time(reader)?;
+        }
+        if !insert.program_splice {
+            let component_count = reader.read(8)?;
+            for _ in 0..component_count {
+                reader.skip(8)?;
+                if !insert.splice_immediate {
+                    Self::decode_splice_time(reader)?;
+                }
+            }
+        }
+        if duration_flag {
+            let auto_return = reader.read(1)? == 1;
+            reader.skip(6)?;
+            insert.break_duration = Some(BreakDuration {
+                auto_return,
+                duration: reader.read(33)?,
+            });
+        }
+        insert.unique_program_id = reader.read(16)? as u16;
+        insert.avail_num = reader.read(8)? as u8;
+        insert.avails_expected = reader.read(8)? as u8;
+
+        Some(insert)
+    }
+
+    fn decode_descriptor(tag: u8, body: &[u8]) -> Option<SpliceDescriptor> {
+        let mut reader = BitReader::new(body);
+        let identifier = reader.read(32)? as u32;
+        if tag != SEGMENTATION_DESCRIPTOR {
+            return Some(SpliceDescriptor::Other {
+                tag,
+                identifier,
+                data: body[4..].to_vec(),
+            });
+        }
+
+        let event_id = reader.read(32)? as u32;
+        let cancel = reader.read(1)? == 1;
+        reader.skip(7)?;
+        let mut descriptor = SegmentationDescriptor {
+            event_id,
+            cancel,
+            duration: None,
+            upid_type: 0,
+            upid: Vec::new(),
+            type_id: 0,
+            segment_num: 0,
+            segments_expected: 0,
+        };
+        if cancel {
+            return Some(SpliceDescriptor::Segmentation(descriptor));
+        }
+
+        let program_segmentation = reader.read(1)? == 1;
+        let duration_flag = reader.read(1)? == 1;
+        reader.skip(6)?;
+        if !program_segmentation {
+            let component_count = reader.read(8)?;
+            reader.skip(component_count as usize * 48)?;
+        }
+        if duration_flag {
+            descriptor.duration = Some(reader.read(40)?);
+        }
+        descriptor.upid_type = reader.read(8)? as u8;
+        let upid_length = reader.read(8)? as usize;
+        let upid_start = reader.position();
+        descriptor.upid = body.get(upid_start..upid_start + upid_length)?.to_vec();
+        reader.seek(upid_start + upid_length)?;
+        descriptor.type_id = reader.read(8)? as u8;
+        descriptor.segment_num = reader.read(8)? as u8;
+        descriptor.segments_expected = reader.read(8)? as u8;
+
+        Some(SpliceDescriptor::Segmentation(descriptor))
+    }
+}
+
+// 按位读取，字段最长 40 位
+struct BitReader<'a> {
+    data: &'a [u8],
+    // 位偏移
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, count: usize) -> Option<u64> {
+        if self.position + count > self.data.len() * 8 {
+            return None;
+        }
+        let mut value = 0u64;
+        for _ in 0..count {
+            let byte = self.data[self.position / 8];
+            value = (value << 1) | ((byte >> (7 - self.position % 8)) & 1) as u64;
+            self.position += 1;
+        }
+        Some(value)
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        if self.position + count > self.data.len() * 8 {
+            return None;
+        }
+        self.position += count;
+        Some(())
+    }
+
+    // 当前字节位置
+    fn position(&self) -> usize {
+        self.position / 8
+    }
+
+    // 跳到指定字节位置
+    fn seek(&mut self, byte: usize) -> Option<()> {
+        if byte > self.data.len() {
+            return None;
+        }
+        self.position = byte * 8;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SCTE-35 规范 14.2 节示例：带分段描述符的 time_signal
+    const TIME_SIGNAL: &str =
+        "/DA0AAAAAAAA///wBQb+cr0AUAAeAhxDVUVJSAAAjn/PAAGlmbAICAAAAAAsoKGKNAIAmsnRfg==";
+    // SCTE-35 规范 14.1 节示例：带 break_duration 的 splice_insert
+    const SPLICE_INSERT: &str =
+        "/DAvAAAAAAAA///wFAVIAACPf+/+c2nALv4AUsz1AAAAAAAKAAhDVUVJAAABNWLbowo=";
+
+    fn bytes(value: &str) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .unwrap()
+    }
+
+    #[test]
+    fn decodes_time_signal_with_segmentation_descriptor() {
+        let info = SpliceInfo::from_base64(TIME_SIGNAL).unwrap();
+        assert_eq!(
+            info.command,
+            SpliceCommand::TimeSignal {
+                pts_time: Some(0x072BD0050)
+            }
+        );
+
+        let descriptors: Vec<_> = info.segmentation_descriptors().collect();
+        assert_eq!(descriptors.len(), 1);
+        let descriptor = descriptors[0];
+        assert_eq!(descriptor.event_id, 0x4800008E);
+        assert!(!descriptor.cancel);
+        assert_eq!(descriptor.duration, Some(0x0001A599B0));
+        assert_eq!(descriptor.upid_type, 0x08);
+        assert_eq!(descriptor.upid, vec![0, 0, 0, 0, 0x2C, 0xA0, 0xA1, 0x8A]);
+        assert_eq!(descriptor.type_id, 0x34);
+        assert_eq!(descriptor.segment_num, 2);
+        assert_eq!(descriptor.segments_expected, 0);
+
+        assert!(info.is_out());
+        assert!(!info.is_in());
+        assert_eq!(info.duration(), Some(307.0));
+    }
+
+    #[test]
+    fn decodes_splice_insert_with_break_duration() {
+        let info = SpliceInfo::from_base64(SPLICE_INSERT).unwrap();
+        let SpliceCommand::Insert(insert) = &info.command else {
+            panic!("应解码为 splice_insert: {:?}", info.command);
+        };
+        assert_eq!(insert.event_id, 0x4800008F);
+        assert!(!insert.cancel);
+        assert!(insert.out_of_network);
+        assert!(insert.program_splice);
+        assert!(!insert.splice_immediate);
+        assert_eq!(insert.pts_time, Some(0x07369C02E));
+        assert_eq!(
+            insert.break_duration,
+            Some(BreakDuration {
+                auto_return: true,
+                duration: 0x00052CCF5,
+            })
+        );
+
+        // avail_descriptor 不是分段描述符
+        assert_eq!(info.descriptors.len(), 1);
+        assert!(matches!(
+            info.descriptors[0],
+            SpliceDescriptor::Other { tag: 0, .. }
+        ));
+        assert!(info.is_out());
+        assert_eq!(info.duration(), Some(5426421.0 / SCTE35_TIMESCALE));
+    }
+
+    #[test]
+    fn hex_and_base64_decode_the_same_section() {
+        let hex: String = bytes(SPLICE_INSERT)
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        assert_eq!(
+            SpliceInfo::from_hex(&format!("0x{}", hex)).unwrap(),
+            SpliceInfo::from_base64(SPLICE_INSERT).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncated_input_returns_error() {
+        for sample in [TIME_SIGNAL, SPLICE_INSERT] {
+            let data = bytes(sample);
+            // 末尾 4 字节为 CRC，不参与解析
+            for length in 0..data.len() - 4 {
+                assert!(
+                    SpliceInfo::decode(&data[..length]).is_err(),
+                    "截断到 {} 字节应返回错误",
+                    length
+                );
+            }
+            assert!(SpliceInfo::decode(&data[..data.len() - 4]).is_ok());
+        }
+        assert!(SpliceInfo::from_hex("0xFC30").is_err());
+        assert!(SpliceInfo::from_hex("0xFC3").is_err());
+        // 多字节字符不能导致按字节下标切分字符串时越界
+        assert!(SpliceInfo::from_hex("0x1é1").is_err());
+        assert!(SpliceInfo::from_hex("0xé").is_err());
+        assert!(SpliceInfo::from_hex("0xFC30zz").is_err());
+        assert!(SpliceInfo::from_base64("不是base64").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::error::M3u8Error;
+use base64::Engine;
+use std::path::PathBuf;
+use url::Url;
+
+// 片段/播放列表来源：远程 HTTP(S) 地址、本地文件或 data: URI 内嵌的数据
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentSource {
+    Http(String),
+    File(PathBuf),
+    // data: URI 解码后的内容
+    Data(Vec<u8>),
+}
+
+impl SegmentSource {
+    // 根据地址判断来源：http/https 为远程地址，file:// 与无协议的路径为本地文件，
+    // data: URI 直接解码出内容
+    pub fn parse(location: &str) -> Result<Self, M3u8Error> {
+        match Url::parse(location) {
+            Ok(url) if url.scheme() == "data" => decode_data_uri(location).map(SegmentSource::Data),
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                Ok(SegmentSource::Http(location.to_string()))
+            }
+            Ok(url) if url.scheme() == "file" => url
+                .to_file_path()
+                .map(SegmentSource::File)
+                .map_err(|_| M3u8Error::ParseError(format!("无效的本地文件地址: {}", location))),
+            // 单字母协议为 Windows 盘符路径
+            Ok(url) if url.scheme().len() > 1 => Err(M3u8Error::ParseError(format!(
+                "不支持的地址协议: {}",
+                url.scheme()
+            ))),
+            _ => Ok(SegmentSource::File(PathBuf::from(location))),
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        matches!(self, SegmentSource::File(_) | SegmentSource::Data(_))
+    }
+}
+
+// 解码 RFC 2397 data: URI（data:[<媒体类型>][;base64],<数据>），支持 base64 与百分号编码
+fn decode_data_uri(location: &str) -> Result<Vec<u8>, M3u8Error> {
+    let invalid = || M3u8Error::ParseError(format!("无效的 data: URI: {}", location));
+    let (_, rest) = location.split_once(':').ok_or_else(invalid)?;
+    let (media_type, data) = rest.split_once(',').ok_or_else(invalid)?;
+    let data: Vec<u8> = percent_encoding::percent_decode_str(data).collect();
+
+    let is_base64 = media_type
+        .rsplit(';')
+        .next()
+        .is_some_and(|param| param.trim().eq_ignore_ascii_case("base64"));
+    if !is_base64 {
+        return Ok(data);
+    }
+
+    // base64 内容中可能夹带空白
+    let data: Vec<u8> = data
+        .into_iter()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(&data)
+        .map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_base64_data_uri() {
+        assert_eq!(
+            SegmentSource::parse("data:application/octet-stream;base64,AAECAw==").unwrap(),
+            SegmentSource::Data(vec![0, 1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn decodes_percent_encoded_data_uri() {
+        assert_eq!(
+            SegmentSource::parse("data:text/plain,%23EXTM3U%0A").unwrap(),
+            SegmentSource::Data(b"#EXTM3U\n".to_vec())
+        );
+        assert_eq!(
+            SegmentSource::parse("data:,a%2Cb").unwrap(),
+            SegmentSource::Data(b"a,b".to_vec())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_data_uri() {
+        assert!(SegmentSource::parse("data:text/plain;base64").is_err());
+        assert!(SegmentSource::parse("data:;base64,@@@").is_err());
+    }
+
+    #[test]
+    fn classifies_other_locations() {
+        assert!(matches!(
+            SegmentSource::parse("https://example.com/a.ts").unwrap(),
+            SegmentSource::Http(_)
+        ));
+        assert_eq!(
+            SegmentSource::parse("file:///tmp/a.ts").unwrap(),
+            SegmentSource::File(PathBuf::from("/tmp/a.ts"))
+        );
+        assert!(SegmentSource::parse("ftp://example.com/a.ts").is_err());
+    }
+}
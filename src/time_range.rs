@@ -0,0 +1,446 @@
+use crate::error::M3u8Error;
+use crate::types::M3u8Segment;
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, NaiveTime, TimeZone};
+use std::fmt;
+use std::str::FromStr;
+
+// 时间点：相对播放列表开头的媒体时间，或依据 EXT-X-PROGRAM-DATE-TIME 的绝对时间
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimePoint {
+    // 秒
+    Offset(f64),
+    // HH:MM 或 HH:MM:SS 形式的时间，播放列表带 EXT-X-PROGRAM-DATE-TIME 时按当天的时刻处理，
+    // 否则按媒体时间处理（offset 为按 MM:SS 或 HH:MM:SS 解析出的秒数）
+    Clock { offset: f64, time: NaiveTime },
+    DateTime(DateTime<FixedOffset>),
+}
+
+impl TimePoint {
+    // 解析 HH:MM:SS[.fff]、MM:SS 或秒数
+    fn parse_offset(s: &str) -> Option<f64> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        let mut seconds = 0.0;
+        for (index, part) in parts.iter().enumerate() {
+            let value: f64 = part.parse().ok()?;
+            // 只有最后一段可以带小数
+            if value < 0.0 || (index + 1 < parts.len() && value.fract() != 0.0) {
+                return None;
+            }
+            seconds = seconds * 60.0 + value;
+        }
+        Some(seconds)
+    }
+
+    // 解析带日期的时间，未指定时区时按本地时间处理
+    fn parse_date_time(s: &str) -> Option<DateTime<FixedOffset>> {
+        if let Ok(date_time) = DateTime::parse_from_rfc3339(s) {
+            return Some(date_time);
+        }
+        if let Ok(date_time) = DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f%z") {
+            return Some(date_time);
+        }
+        [
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%Y-%m-%d %H:%M",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map(|date_time| date_time.fixed_offset())
+    }
+}
+
+impl FromStr for TimePoint {
+    type Err = M3u8Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // + 开头的时间始终是媒体时间
+        if let Some(seconds) = s.strip_prefix('+').and_then(Self::parse_offset) {
+            return Ok(TimePoint::Offset(seconds));
+        }
+        if let Some(seconds) = Self::parse_offset(s) {
+            let time = match s.matches(':').count() {
+                1 => NaiveTime::parse_from_str(s, "%H:%M").ok(),
+                2 => NaiveTime::parse_from_str(s, "%H:%M:%S%.f").ok(),
+                _ => None,
+            };
+            return Ok(match time {
+                Some(time) => TimePoint::Clock {
+                    offset: seconds,
+                    time,
+                },
+                None => TimePoint::Offset(seconds),
+            });
+        }
+        Self::parse_date_time(s)
+            .map(TimePoint::DateTime)
+            .ok_or_else(|| {
+                M3u8Error::ParseError(format!(
+                    "无效的时间: {}，可使用 +HH:MM:SS、HH:MM 或 2024-01-01T14:00:00+08:00",
+                    s
+                ))
+            })
+    }
+}
+
+impl fmt::Display for TimePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimePoint::Offset(seconds) => {
+                let total = *seconds as u64;
+                write!(
+                    f,
+                    "{:02}:{:02}:{:02}",
+                    total / 3600,
+                    total / 60 % 60,
+                    total % 60
+                )?;
+                let fraction = seconds.fract();
+                if fraction > 0.0 {
+                    write!(f, ".{:03}", (fraction * 1000.0).round() as u32)?;
+                }
+                Ok(())
+            }
+            TimePoint::Clock { time, .. } => write!(f, "{}", time),
+            TimePoint::DateTime(date_time) => write!(f, "{}", date_time.to_rfc3339()),
+        }
+    }
+}
+
+// 精确裁剪的时间窗口，相对选中的第一个片段开头的秒数
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClipWindow {
+    pub start: f64,
+    pub end: Option<f64>,
+}
+
+impl ClipWindow {
+    // 起点位于第一个片段开头且不限制结尾时无需裁剪
+    pub fn is_empty(&self) -> bool {
+        self.start <= 0.0 && self.end.is_none()
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        self.end.map(|end| (end - self.start).max(0.0))
+    }
+}
+
+impl fmt::Display for ClipWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = TimePoint::Offset(self.start);
+        match self.end {
+            Some(end) => write!(f, "{} - {}", start, TimePoint::Offset(end)),
+            None => write!(f, "{} - 结尾", start),
+        }
+    }
+}
+
+// 下载的时间范围，未指定的一端不限制
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimeRange {
+    pub from: Option<TimePoint>,
+    pub to: Option<TimePoint>,
+}
+
+impl TimeRange {
+    pub fn new(from: Option<TimePoint>, to: Option<TimePoint>) -> Self {
+        Self { from, to }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
+
+    // 按播放列表确定时间点的含义并检查起止顺序：
+    // 带 EXT-X-PROGRAM-DATE-TIME 时 HH:MM[:SS] 取第一个片段所在日期的时刻（早于开头时顺延一天），
+    // 否则按媒体时间处理
+    pub fn resolve(&self, segments: &[M3u8Segment]) -> Result<TimeRange, M3u8Error> {
+        let anchor = Self::first_date_time(segments);
+        let resolve = |point: Option<TimePoint>| match (point, anchor) {
+            (Some(TimePoint::Clock { time, .. }), Some((first, start, end))) => {
+                let offset = *first.offset();
+                let naive = first.date_naive().and_time(time)
+                    - Duration::seconds(offset.local_minus_utc() as i64);
+                let mut date_time = DateTime::from_naive_utc_and_offset(naive, offset);
+                let playlist_start = first - Self::duration(start);
+                let playlist_end = first + Self::duration(end - start);
+                if date_time < playlist_start && date_time + Duration::days(1) < playlist_end {
+                    date_time += Duration::days(1);
+                }
+                Some(TimePoint::DateTime(date_time))
+            }
+            (Some(TimePoint::Clock { offset, .. }), None) => Some(TimePoint::Offset(offset)),
+            (point, _) => point,
+        };
+        let resolved = TimeRange::new(resolve(self.from), resolve(self.to));
+
+        if let (Some(from), Some(to)) = (resolved.from, resolved.to) {
+            if Self::position(from, segments)? >= Self::position(to, segments)? {
+                return Err(M3u8Error::ParseError(format!(
+                    "起始时间 {} 必须早于结束时间 {}",
+                    from, to
+                )));
+            }
+        }
+        Ok(resolved)
+    }
+
+    // 选出与时间范围有重叠的片段
+    pub fn select(&self, segments: &[M3u8Segment]) -> Result<Vec<M3u8Segment>, M3u8Error> {
+        if self.is_empty() {
+            return Ok(segments.to_vec());
+        }
+        let range = self.resolve(segments)?;
+
+        let anchor = Self::anchor(segments);
+        let mut selected = Vec::new();
+        let mut start = 0.0;
+        for segment in segments {
+            let after_from = match range.from {
+                Some(from) => {
+                    Self::seconds_since(from, segment, start, anchor)? + segment.duration > 0.0
+                }
+                None => true,
+            };
+            let before_to = match range.to {
+                Some(to) => Self::seconds_since(to, segment, start, anchor)? < 0.0,
+                None => true,
+            };
+            if after_from && before_to {
+                selected.push(segment.clone());
+            }
+            start += segment.duration;
+        }
+
+        if selected.is_empty() {
+            return Err(M3u8Error::EmptyError(format!(
+                "时间范围 {} 内没有视频片段",
+                range
+            )));
+        }
+        Ok(selected)
+    }
+
+    // 计算选中片段中需要保留的时间窗口，用于把首尾片段裁剪到精确的时间点
+    pub fn clip_window(
+        &self,
+        segments: &[M3u8Segment],
+        selected: &[M3u8Segment],
+    ) -> Result<ClipWindow, M3u8Error> {
+        let Some(first) = selected.first() else {
+            return Ok(ClipWindow::default());
+        };
+        let range = self.resolve(segments)?;
+        let anchor = Self::anchor(segments);
+        let start: f64 = segments
+            .iter()
+            .take_while(|segment| segment.sequence != first.sequence)
+            .map(|segment| segment.duration)
+            .sum();
+
+        let clip_start = match range.from {
+            Some(from) => (-Self::seconds_since(from, first, start, anchor)?).max(0.0),
+            None => 0.0,
+        };
+        let clip_end = match range.to {
+            Some(to) => Some(-Self::seconds_since(to, first, start, anchor)?),
+            None => None,
+        };
+        Ok(ClipWindow {
+            start: clip_start,
+            end: clip_end,
+        })
+    }
+
+    // 第一个带时间的片段的时间、它相对开头的秒数，以及播放列表总时长
+    fn first_date_time(segments: &[M3u8Segment]) -> Option<(DateTime<FixedOffset>, f64, f64)> {
+        let mut start = 0.0;
+        let mut first = None;
+        for segment in segments {
+            if first.is_none() {
+                first = segment
+                    .program_date_time
+                    .map(|date_time| (date_time, start));
+            }
+            start += segment.duration;
+        }
+        first.map(|(date_time, offset)| (date_time, offset, start))
+    }
+
+    // 第一个带时间的片段的时间及它相对开头的秒数，用于推算此前片段的时间
+    fn anchor(segments: &[M3u8Segment]) -> Option<(DateTime<FixedOffset>, f64)> {
+        Self::first_date_time(segments).map(|(date_time, start, _)| (date_time, start))
+    }
+
+    fn duration(seconds: f64) -> Duration {
+        Duration::microseconds((seconds * 1_000_000.0) as i64)
+    }
+
+    // 时间点相对播放列表开头的秒数
+    fn position(point: TimePoint, segments: &[M3u8Segment]) -> Result<f64, M3u8Error> {
+        match point {
+            TimePoint::Offset(seconds)
+            | TimePoint::Clock {
+                offset: seconds, ..
+            } => Ok(seconds),
+            TimePoint::DateTime(date_time) => {
+                let (first, start, _) =
+                    Self::first_date_time(segments).ok_or_else(Self::missing_date_time)?;
+                Ok(start + Self::seconds_between(first, date_time))
+            }
+        }
+    }
+
+    // 片段开头相对时间点的秒数（片段在时间点之后为正）；
+    // 第一个带时间的片段之前的片段没有时间，由第一个时间减去其间的时长推算
+    fn seconds_since(
+        point: TimePoint,
+        segment: &M3u8Segment,
+        start: f64,
+        anchor: Option<(DateTime<FixedOffset>, f64)>,
+    ) -> Result<f64, M3u8Error> {
+        match point {
+            TimePoint::Offset(seconds)
+            | TimePoint::Clock {
+                offset: seconds, ..
+            } => Ok(start - seconds),
+            TimePoint::DateTime(date_time) => {
+                let segment_time = match (segment.program_date_time, anchor) {
+                    (Some(segment_time), _) => segment_time,
+                    (None, Some((first, first_start))) => {
+                        first - Self::duration(first_start - start)
+                    }
+                    (None, None) => return Err(Self::missing_date_time()),
+                };
+                Ok(Self::seconds_between(date_time, segment_time))
+            }
+        }
+    }
+
+    fn seconds_between(from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> f64 {
+        (to - from).num_microseconds().unwrap_or(0) as f64 / 1_000_000.0
+    }
+
+    fn missing_date_time() -> M3u8Error {
+        M3u8Error::ParseError(
+            "播放列表缺少 EXT-X-PROGRAM-DATE-TIME，无法按绝对时间选择片段".to_string(),
+        )
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = self
+            .from
+            .map_or("开头".to_string(), |from| from.to_string());
+        let to = self.to.map_or("结尾".to_string(), |to| to.to_string());
+        write!(f, "{} - {}", from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::MediaParser;
+
+    // 10 个 10 秒的片段，带时间时从 2024-05-01 13:55:00+08:00 开始
+    fn segments(program_date_time: bool) -> Vec<M3u8Segment> {
+        let mut content = String::from("#EXTM3U\n#EXT-X-TARGETDURATION:10\n");
+        if program_date_time {
+            content.push_str("#EXT-X-PROGRAM-DATE-TIME:2024-05-01T13:55:00+08:00\n");
+        }
+        for index in 0..10 {
+            content.push_str(&format!("#EXTINF:10,\nhttps://example.com/{}.ts\n", index));
+        }
+        content.push_str("#EXT-X-ENDLIST\n");
+        MediaParser::new(Vec::new())
+            .unwrap()
+            .parse(&content, None)
+            .unwrap()
+            .segments
+    }
+
+    fn range(from: &str, to: &str) -> TimeRange {
+        TimeRange::new(Some(from.parse().unwrap()), Some(to.parse().unwrap()))
+    }
+
+    fn sequences(range: &TimeRange, segments: &[M3u8Segment]) -> Vec<usize> {
+        range
+            .select(segments)
+            .unwrap()
+            .iter()
+            .map(|segment| segment.sequence)
+            .collect()
+    }
+
+    #[test]
+    fn clock_time_is_anchored_to_program_date_time() {
+        let segments = segments(true);
+        // 13:56:00 - 13:56:30 对应序号 6 到 8 的片段
+        assert_eq!(
+            sequences(&range("13:56", "13:56:30"), &segments),
+            vec![6, 7, 8]
+        );
+        assert_eq!(
+            range("13:56", "13:56:30")
+                .clip_window(&segments, &segments[6..9])
+                .unwrap(),
+            ClipWindow {
+                start: 0.0,
+                end: Some(30.0),
+            }
+        );
+        // + 开头的时间仍按媒体时间处理
+        assert_eq!(
+            sequences(&range("+00:00:15", "+00:00:25"), &segments),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn clock_time_without_program_date_time_is_media_offset() {
+        let segments = segments(false);
+        // MM:SS
+        assert_eq!(sequences(&range("00:15", "00:25"), &segments), vec![1, 2]);
+    }
+
+    #[test]
+    fn from_must_be_before_to() {
+        for (from, to) in [("13:57", "13:56"), ("+60", "+30"), ("13:56", "13:56")] {
+            assert!(range(from, to).resolve(&segments(true)).is_err());
+        }
+        assert!(range("00:30", "00:10").resolve(&segments(false)).is_err());
+    }
+
+    #[test]
+    fn segments_before_first_program_date_time_are_back_filled() {
+        // 前两个片段没有时间，由 13:55:20 的第三个片段倒推为 13:55:00 与 13:55:10
+        let content = "#EXTM3U\n#EXT-X-TARGETDURATION:10\n\
+                       #EXTINF:10,\nhttps://example.com/0.ts\n\
+                       #EXTINF:10,\nhttps://example.com/1.ts\n\
+                       #EXT-X-PROGRAM-DATE-TIME:2024-05-01T13:55:20+08:00\n\
+                       #EXTINF:10,\nhttps://example.com/2.ts\n\
+                       #EXTINF:10,\nhttps://example.com/3.ts\n#EXT-X-ENDLIST\n";
+        let segments = MediaParser::new(Vec::new())
+            .unwrap()
+            .parse(content, None)
+            .unwrap()
+            .segments;
+        assert!(segments[0].program_date_time.is_none());
+
+        let range = range("13:55:05", "13:55:25");
+        assert_eq!(sequences(&range, &segments), vec![0, 1, 2]);
+        assert_eq!(
+            range.clip_window(&segments, &segments[..3]).unwrap(),
+            ClipWindow {
+                start: 5.0,
+                end: Some(25.0),
+            }
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use crate::parser::ParseWarning;
+use crate::scte35::SpliceInfo;
+use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -30,18 +33,172 @@ pub struct M3u8Segment {
     pub sequence: usize,
     pub title: Option<String>,
     pub byte_range: Option<(usize, usize)>,
+    // 备份流中相同媒体序号片段的 URL，主地址下载失败时依次尝试
+    #[serde(default)]
+    pub backup_urls: Vec<String>,
+    // 片段使用的加密信息（EXT-X-KEY）
+    #[serde(default)]
+    pub key: Option<M3u8Key>,
+    // 片段使用的初始化片段（EXT-X-MAP）
+    #[serde(default)]
+    pub map: Option<M3u8Map>,
+    // 片段前是否有 EXT-X-DISCONTINUITY，此处起时间戳或编码参数可能变化
+    #[serde(default)]
+    pub discontinuity: bool,
+    // 片段前的广告标记（EXT-X-CUE-OUT/CUE-OUT-CONT/CUE-IN 或带 SCTE35 的 EXT-X-DATERANGE）
+    #[serde(default)]
+    pub cue: Option<CueMarker>,
+    // 片段第一帧的时间（EXT-X-PROGRAM-DATE-TIME），未标注的片段由前一个片段的时间加时长推算
+    #[serde(default)]
+    pub program_date_time: Option<DateTime<FixedOffset>>,
+    // EXT-X-GAP：片段缺失，不应下载
+    #[serde(default)]
+    pub gap: bool,
+    // EXT-X-BITRATE 标注的码率（kbps）
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+    // 片段前未识别的标签，按原样保留
+    #[serde(default)]
+    pub unknown_tags: Vec<String>,
+    // EXTINF 所在的行号，用于定位问题
+    #[serde(default)]
+    pub line: usize,
+}
+
+// 广告标记类型
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CueMarker {
+    // 广告开始
+    Out,
+    // 广告进行中
+    Cont,
+    // 广告结束，回到正片
+    In,
+}
+
+// EXT-X-DATERANGE 日期区间
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DateRange {
+    pub id: String,
+    pub class: Option<String>,
+    pub start_date: Option<DateTime<FixedOffset>>,
+    pub end_date: Option<DateTime<FixedOffset>>,
+    pub duration: Option<f64>,
+    pub planned_duration: Option<f64>,
+    pub end_on_next: bool,
+    pub scte35_cmd: Option<SpliceInfo>,
+    pub scte35_out: Option<SpliceInfo>,
+    pub scte35_in: Option<SpliceInfo>,
+    // X- 开头的自定义属性
+    pub client_attributes: Vec<(String, String)>,
+    // 标签之后第一个片段的序号
+    pub segment_sequence: usize,
+}
+
+// EXT-X-CUE-OUT/CUE-OUT-CONT/CUE-IN 等广告标记
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CueEvent {
+    pub marker: CueMarker,
+    // 广告总时长（秒）
+    pub duration: Option<f64>,
+    // CUE-OUT-CONT 中已播放的时长（秒）
+    pub elapsed: Option<f64>,
+    // 标签或 EXT-OATCLS-SCTE35 中携带的 SCTE-35 数据
+    pub splice_info: Option<SpliceInfo>,
+    // 标签之后第一个片段的序号
+    pub segment_sequence: usize,
+}
+
+// 被识别为广告并移除的一段连续片段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdBreak {
+    // 第一个片段的序号（播放列表中的位置）
+    pub start_sequence: usize,
+    pub segment_count: usize,
+    pub duration: f64,
+    pub score: u32,
+    // 得分依据
+    pub reasons: Vec<String>,
+}
+
+impl fmt::Display for AdBreak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "片段 #{}-#{}（{} 个, {:.1}s, 得分 {}）: {}",
+            self.start_sequence,
+            self.start_sequence + self.segment_count - 1,
+            self.segment_count,
+            self.duration,
+            self.score,
+            self.reasons.join("; ")
+        )
+    }
+}
+
+// EXT-X-KEY 加密信息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct M3u8Key {
+    pub method: String,
+    pub uri: Option<String>,
+    pub iv: Option<String>,
+    pub key_format: Option<String>,
+}
+
+// EXT-X-START 建议的播放起点
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct M3u8Start {
+    // 相对播放列表开头（负数为相对结尾）的秒数
+    pub time_offset: f64,
+    pub precise: bool,
+}
+
+// EXT-X-PLAYLIST-TYPE
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MediaPlaylistType {
+    // 只会在末尾追加片段
+    Event,
+    // 点播，内容不再变化
+    Vod,
+}
+
+// EXT-X-MAP 初始化片段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct M3u8Map {
+    pub uri: String,
+    pub byte_range: Option<(usize, usize)>,
 }
 
 // M3U8 变体流信息（用于主播放列表）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct M3u8Variant {
     pub url: String,
+    // 冗余备份流地址（同一码率、不同 CDN）
+    #[serde(default)]
+    pub backup_urls: Vec<String>,
     pub bandwidth: Option<u32>,
     pub resolution: Option<(u32, u32)>,
     pub codecs: Option<String>,
     pub audio: Option<String>,
     pub video: Option<String>,
     pub subtitles: Option<String>,
+    // EXT-X-STREAM-INF 所在的行号，用于定位问题
+    #[serde(default)]
+    pub line: usize,
+}
+
+// EXT-X-MEDIA 描述的备选媒体（音轨、视角、字幕等）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct M3u8Rendition {
+    // AUDIO/VIDEO/SUBTITLES/CLOSED-CAPTIONS
+    pub media_type: String,
+    pub group_id: String,
+    pub name: String,
+    pub language: Option<String>,
+    // 媒体播放列表地址，为空时包含在变体流中
+    pub uri: Option<String>,
+    pub default: bool,
+    pub autoselect: bool,
 }
 
 // M3U8 播放列表信息
@@ -49,13 +206,46 @@ pub struct M3u8Variant {
 pub struct M3u8Playlist {
     pub segments: Vec<M3u8Segment>,
     pub variants: Vec<M3u8Variant>,
+    // 主播放列表中的 EXT-X-MEDIA
+    #[serde(default)]
+    pub renditions: Vec<M3u8Rendition>,
     pub playlist_type: PlaylistType,
     pub target_duration: f64,
     pub version: u32,
     pub is_live: bool,
     pub ads_count: usize,
+    // 广告检测移除的片段段落
+    #[serde(default)]
+    pub ad_breaks: Vec<AdBreak>,
+    #[serde(default)]
+    pub date_ranges: Vec<DateRange>,
+    #[serde(default)]
+    pub cue_events: Vec<CueEvent>,
     pub media_sequence: usize,
     pub discontinuity_sequence: usize,
+    #[serde(default)]
+    pub media_playlist_type: Option<MediaPlaylistType>,
+    // 是否有 EXT-X-ENDLIST
+    #[serde(default)]
+    pub end_list: bool,
+    // EXT-X-I-FRAMES-ONLY：每个片段只包含一个关键帧
+    #[serde(default)]
+    pub i_frames_only: bool,
+    // EXT-X-INDEPENDENT-SEGMENTS：每个片段都可以独立解码
+    #[serde(default)]
+    pub independent_segments: bool,
+    #[serde(default)]
+    pub start: Option<M3u8Start>,
+    // EXT-X-SKIP 跳过的片段数（增量更新的播放列表）
+    #[serde(default)]
+    pub skipped_segments: usize,
+    // 播放列表级的标签：第一个 EXTINF 之前或最后一个片段之后未识别的标签，
+    // 以及 EXT-X-SERVER-CONTROL 等不影响下载的播放列表标签，按原样保留
+    #[serde(default)]
+    pub unknown_tags: Vec<String>,
+    // 宽松模式下解析时发现的问题
+    #[serde(default)]
+    pub warnings: Vec<ParseWarning>,
 }
 
 // 嵌套播放列表结构
@@ -71,13 +261,25 @@ impl M3u8Playlist {
         Self {
             segments: Vec::new(),
             variants: Vec::new(),
+            renditions: Vec::new(),
             playlist_type,
             target_duration: 0.0,
             version: 1,
             is_live: true,
             ads_count: 0,
+            ad_breaks: Vec::new(),
+            date_ranges: Vec::new(),
+            cue_events: Vec::new(),
             media_sequence: 0,
             discontinuity_sequence: 0,
+            media_playlist_type: None,
+            end_list: false,
+            i_frames_only: false,
+            independent_segments: false,
+            start: None,
+            skipped_segments: 0,
+            unknown_tags: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
@@ -91,6 +293,11 @@ impl M3u8Playlist {
         self.segments.len()
     }
 
+    // 获取片段的媒体序号（EXT-X-MEDIA-SEQUENCE + 片段位置）
+    pub fn segment_media_sequence(&self, segment: &M3u8Segment) -> usize {
+        self.media_sequence + segment.sequence
+    }
+
     // 检查是否为嵌套播放列表
     pub fn is_nested(&self) -> bool {
         !self.variants.is_empty() && self.playlist_type == PlaylistType::Master
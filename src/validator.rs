@@ -0,0 +1,710 @@
+use crate::error::M3u8Error;
+use crate::fetcher::PlaylistFetcher;
+use crate::parser::content_parser::resolve_base_url;
+use crate::parser::diagnostics::{split_lines, Diagnostics};
+use crate::parser::{ContentParser, MasterParser, MediaParser, ParseMode};
+use crate::source::SegmentSource;
+use crate::types::{M3u8Playlist, MediaPlaylistType, PlaylistType};
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use url::Url;
+
+// 问题的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Severity {
+    // 规范中的 SHOULD 要求，或不影响播放的问题
+    Warning,
+    // 违反规范中的 MUST 要求
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "警告"),
+            Severity::Error => write!(f, "错误"),
+        }
+    }
+}
+
+// 一条检查结果，line 为 0 时表示与具体行无关
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub playlist: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.severity, self.playlist)?;
+        if self.line > 0 {
+            write!(f, " 第 {} 行", self.line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// 检查报告
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    // 检查过的播放列表数量
+    pub playlists: usize,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn error_count(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == severity)
+            .count()
+    }
+
+    fn push(&mut self, severity: Severity, playlist: &str, line: usize, message: String) {
+        self.issues.push(ValidationIssue {
+            severity,
+            playlist: playlist.to_string(),
+            line,
+            message,
+        });
+    }
+}
+
+// 已检查的直播媒体播放列表，用于重新获取后比较
+struct LivePlaylist {
+    location: String,
+    playlist: M3u8Playlist,
+}
+
+// 播放列表合规性检查：以严格模式解析主播放列表、所有变体流及备选媒体播放列表，并检查
+// 目标时长、媒体序号、版本与特性兼容性、CODECS 属性及点播的 EXT-X-ENDLIST
+pub struct PlaylistValidator {
+    headers: HeaderMap,
+    base_url: Option<String>,
+    // 是否在一个目标时长后重新获取直播播放列表，检查媒体序号是否递增
+    reload_live: bool,
+}
+
+impl PlaylistValidator {
+    pub fn new(headers: HeaderMap, base_url: Option<String>, reload_live: bool) -> Self {
+        Self {
+            headers,
+            base_url,
+            reload_live,
+        }
+    }
+
+    pub async fn validate(
+        &self,
+        location: &str,
+        fetcher: &impl PlaylistFetcher,
+    ) -> Result<ValidationReport, M3u8Error> {
+        let mut report = ValidationReport::default();
+        let (content, base_url) = self.load(location, fetcher).await?;
+        let mut live = Vec::new();
+
+        match ContentParser::new(Vec::new())?.parse_content_type(&content) {
+            PlaylistType::Master => {
+                report.playlists += 1;
+                let parsed = Self::parse_checked(&mut report, location, |mode| {
+                    MasterParser::new(Vec::new())?
+                        .with_mode(mode)
+                        .parse(&content, base_url.as_ref())
+                });
+                let Some(master) = parsed else {
+                    return Ok(report);
+                };
+                Self::check_master(&mut report, location, &master);
+
+                // 变体流、备份流与 EXT-X-MEDIA 引用的播放列表，同一地址只检查一次
+                let mut locations: Vec<&String> = Vec::new();
+                let media = master
+                    .variants
+                    .iter()
+                    .flat_map(|variant| std::iter::once(&variant.url).chain(&variant.backup_urls))
+                    .chain(master.renditions.iter().filter_map(|r| r.uri.as_ref()));
+                for url in media {
+                    if !locations.contains(&url) {
+                        locations.push(url);
+                    }
+                }
+                for url in locations {
+                    self.validate_media(&mut report, &mut live, url, fetcher)
+                        .await;
+                }
+            }
+            PlaylistType::Media => {
+                report.playlists += 1;
+                if let Some(playlist) =
+                    Self::parse_media(&mut report, location, &content, base_url.as_ref())
+                {
+                    Self::check_media(&mut report, location, &content, &playlist);
+                    if !playlist.end_list && location.starts_with("http") {
+                        live.push(LivePlaylist {
+                            location: location.to_string(),
+                            playlist,
+                        });
+                    }
+                }
+            }
+            PlaylistType::Unknown => report.push(
+                Severity::Error,
+                location,
+                0,
+                "无法识别的播放列表类型".to_string(),
+            ),
+        }
+
+        if self.reload_live && !live.is_empty() {
+            self.check_reload(&mut report, &live, fetcher).await;
+        }
+        Ok(report)
+    }
+
+    // 读取播放列表内容及解析相对地址使用的基础地址
+    async fn load(
+        &self,
+        location: &str,
+        fetcher: &impl PlaylistFetcher,
+    ) -> Result<(String, Option<Url>), M3u8Error> {
+        match SegmentSource::parse(location)? {
+            SegmentSource::Http(url) => {
+                let content = fetcher.fetch_playlist(&url, &self.headers).await?;
+                let base_url = match &self.base_url {
+                    Some(base) => resolve_base_url(base),
+                    None => Url::parse(&url).ok(),
+                };
+                Ok((content, base_url))
+            }
+            SegmentSource::Data(data) => {
+                let content = String::from_utf8(data).map_err(|_| {
+                    M3u8Error::ParseError("data: URI 中的播放列表不是有效的 UTF-8 文本".to_string())
+                })?;
+                let base_url = self.base_url.as_deref().and_then(resolve_base_url);
+                Ok((content, base_url))
+            }
+            SegmentSource::File(path) => {
+                let content = tokio::fs::read_to_string(&path).await?;
+                let base = self
+                    .base_url
+                    .clone()
+                    .unwrap_or_else(|| path.to_string_lossy().to_string());
+                Ok((content, resolve_base_url(&base)))
+            }
+        }
+    }
+
+    async fn validate_media(
+        &self,
+        report: &mut ValidationReport,
+        live: &mut Vec<LivePlaylist>,
+        location: &str,
+        fetcher: &impl PlaylistFetcher,
+    ) {
+        report.playlists += 1;
+        let (content, _) = match self.load(location, fetcher).await {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                report.push(
+                    Severity::Error,
+                    location,
+                    0,
+                    format!("无法获取媒体播放列表: {}", e),
+                );
+                return;
+            }
+        };
+
+        // 媒体播放列表中的相对地址以其自身地址为基础
+        let base_url = Url::parse(location).ok();
+        if let Some(playlist) = Self::parse_media(report, location, &content, base_url.as_ref()) {
+            Self::check_media(report, location, &content, &playlist);
+            if !playlist.end_list && location.starts_with("http") {
+                live.push(LivePlaylist {
+                    location: location.to_string(),
+                    playlist,
+                });
+            }
+        }
+    }
+
+    fn parse_media(
+        report: &mut ValidationReport,
+        location: &str,
+        content: &str,
+        base_url: Option<&Url>,
+    ) -> Option<M3u8Playlist> {
+        Self::parse_checked(report, location, |mode| {
+            MediaParser::new(Vec::new())?
+                .with_mode(mode)
+                .parse(content, base_url)
+        })
+    }
+
+    // 先以严格模式解析；失败时改用宽松模式继续检查，宽松模式记录的每条警告都是违反规范之处
+    fn parse_checked(
+        report: &mut ValidationReport,
+        location: &str,
+        parse: impl Fn(ParseMode) -> Result<M3u8Playlist, M3u8Error>,
+    ) -> Option<M3u8Playlist> {
+        let strict_error = match parse(ParseMode::Strict) {
+            Ok(playlist) => return Some(playlist),
+            Err(e) => e,
+        };
+        let (line, message) = match &strict_error {
+            M3u8Error::InvalidPlaylist(warning) => (warning.line, warning.message.clone()),
+            e => (0, e.to_string()),
+        };
+
+        match parse(ParseMode::Lenient) {
+            Ok(playlist) => {
+                if playlist.warnings.is_empty() {
+                    report.push(Severity::Error, location, line, message);
+                }
+                for warning in &playlist.warnings {
+                    report.push(
+                        Severity::Error,
+                        location,
+                        warning.line,
+                        warning.message.clone(),
+                    );
+                }
+                Some(playlist)
+            }
+            Err(_) => {
+                report.push(Severity::Error, location, line, message);
+                None
+            }
+        }
+    }
+
+    fn check_master(report: &mut ValidationReport, location: &str, master: &M3u8Playlist) {
+        for variant in &master.variants {
+            if variant.codecs.is_none() {
+                report.push(
+                    Severity::Warning,
+                    location,
+                    variant.line,
+                    format!("变体流缺少 CODECS 属性: {}", variant.url),
+                );
+            }
+        }
+    }
+
+    fn check_media(
+        report: &mut ValidationReport,
+        location: &str,
+        content: &str,
+        playlist: &M3u8Playlist,
+    ) {
+        // 每个片段的时长四舍五入后不得超过目标时长
+        if playlist.target_duration > 0.0 {
+            for segment in &playlist.segments {
+                if segment.duration.round() > playlist.target_duration {
+                    report.push(
+                        Severity::Error,
+                        location,
+                        segment.line,
+                        format!(
+                            "片段 #{} 时长 {:.3}s 超过 EXT-X-TARGETDURATION {}",
+                            segment.sequence, segment.duration, playlist.target_duration
+                        ),
+                    );
+                }
+            }
+        }
+
+        for (feature, required, line) in Self::required_versions(content, playlist) {
+            if playlist.version < required {
+                report.push(
+                    Severity::Error,
+                    location,
+                    line,
+                    format!(
+                        "使用 {} 需要 EXT-X-VERSION {} 以上，当前为 {}",
+                        feature, required, playlist.version
+                    ),
+                );
+            }
+        }
+
+        if playlist.media_playlist_type == Some(MediaPlaylistType::Vod) && !playlist.end_list {
+            report.push(
+                Severity::Error,
+                location,
+                0,
+                "EXT-X-PLAYLIST-TYPE 为 VOD，但缺少 EXT-X-ENDLIST".to_string(),
+            );
+        }
+    }
+
+    // 播放列表用到的特性、要求的最低版本（RFC 8216 §7）及第一次使用所在的行号
+    fn required_versions(
+        content: &str,
+        playlist: &M3u8Playlist,
+    ) -> Vec<(&'static str, u32, usize)> {
+        let parser = ContentParser::new(Vec::new()).ok();
+        let key_attribute = |attribute: &str| {
+            Self::tag_lines(content, "#EXT-X-KEY")
+                .into_iter()
+                .find(|(_, value)| {
+                    parser.as_ref().is_some_and(|parser| {
+                        parser
+                            .parse_attribute_list(value)
+                            .iter()
+                            .any(|(name, _)| name == attribute)
+                    })
+                })
+        };
+        let first_tag = |tag: &str| Self::tag_lines(content, tag).into_iter().next();
+
+        let mut features = Vec::new();
+        if let Some((line, _)) = key_attribute("IV") {
+            features.push(("EXT-X-KEY 的 IV 属性", 2, line));
+        }
+        // 解析后的时长可能是整数（如 10.0），需检查原始文本
+        let decimal_extinf = Self::tag_lines(content, "#EXTINF")
+            .into_iter()
+            .find(|(_, value)| value.split(',').next().is_some_and(|d| d.contains('.')));
+        if let Some((line, _)) = decimal_extinf {
+            features.push(("小数形式的 EXTINF 时长", 3, line));
+        }
+        if let Some((line, _)) = first_tag("#EXT-X-BYTERANGE") {
+            features.push(("EXT-X-BYTERANGE", 4, line));
+        }
+        if let Some((line, _)) = first_tag("#EXT-X-I-FRAMES-ONLY") {
+            features.push(("EXT-X-I-FRAMES-ONLY", 4, line));
+        }
+        if let Some((line, _)) = key_attribute("KEYFORMAT") {
+            features.push(("EXT-X-KEY 的 KEYFORMAT 属性", 5, line));
+        }
+        if let Some((line, _)) = first_tag("#EXT-X-MAP") {
+            // 非 I 帧播放列表中使用 EXT-X-MAP 需要版本 6
+            let required = if playlist.i_frames_only { 5 } else { 6 };
+            features.push(("EXT-X-MAP", required, line));
+        }
+        if let Some((line, _)) = first_tag("#EXT-X-SKIP") {
+            features.push(("EXT-X-SKIP", 9, line));
+        }
+        features
+    }
+
+    // 原始文本中指定标签所在的 (行号, 冒号后的内容)，行号与解析器报告的一致
+    fn tag_lines<'a>(content: &'a str, tag: &str) -> Vec<(usize, &'a str)> {
+        let mut diagnostics = Diagnostics::new(ParseMode::Lenient);
+        split_lines(content, &mut diagnostics)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(line_number, line)| {
+                let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                name.eq_ignore_ascii_case(tag)
+                    .then_some((line_number, value))
+            })
+            .collect()
+    }
+
+    // 一个目标时长后重新获取直播播放列表，媒体序号与不连续序号不得减小，
+    // 同一媒体序号的片段地址不得改变
+    async fn check_reload(
+        &self,
+        report: &mut ValidationReport,
+        live: &[LivePlaylist],
+        fetcher: &impl PlaylistFetcher,
+    ) {
+        let wait = live
+            .iter()
+            .map(|live| live.playlist.target_duration)
+            .fold(1.0, f64::max);
+        println!("⏳ {:.0} 秒后重新获取直播播放列表，检查媒体序号", wait);
+        tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+
+        for LivePlaylist { location, playlist } in live {
+            let reloaded = match self.load(location, fetcher).await {
+                Ok((content, _)) => MediaParser::new(Vec::new())
+                    .and_then(|parser| parser.parse(&content, Url::parse(location).ok().as_ref())),
+                Err(e) => Err(e),
+            };
+            let reloaded = match reloaded {
+                Ok(reloaded) => reloaded,
+                Err(e) => {
+                    report.push(
+                        Severity::Error,
+                        location,
+                        0,
+                        format!("重新获取直播播放列表失败: {}", e),
+                    );
+                    continue;
+                }
+            };
+
+            if reloaded.media_sequence < playlist.media_sequence {
+                report.push(
+                    Severity::Error,
+                    location,
+                    0,
+                    format!(
+                        "重新获取后 EXT-X-MEDIA-SEQUENCE 由 {} 减小为 {}",
+                        playlist.media_sequence, reloaded.media_sequence
+                    ),
+                );
+            }
+            if reloaded.discontinuity_sequence < playlist.discontinuity_sequence {
+                report.push(
+                    Severity::Error,
+                    location,
+                    0,
+                    format!(
+                        "重新获取后 EXT-X-DISCONTINUITY-SEQUENCE 由 {} 减小为 {}",
+                        playlist.discontinuity_sequence, reloaded.discontinuity_sequence
+                    ),
+                );
+            }
+            for segment in &playlist.segments {
+                let media_sequence = playlist.segment_media_sequence(segment);
+                let changed = reloaded.segments.iter().find(|s| {
+                    reloaded.segment_media_sequence(s) == media_sequence && s.url != segment.url
+                });
+                if let Some(changed) = changed {
+                    report.push(
+                        Severity::Error,
+                        location,
+                        0,
+                        format!(
+                            "媒体序号 {} 的片段地址在重新获取后改变: {} -> {}",
+                            media_sequence, segment.url, changed.url
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // 按地址返回预设内容，同一地址有多份内容时依次返回，最后一份重复使用
+    #[derive(Default)]
+    struct Playlists(Mutex<HashMap<String, Vec<String>>>);
+
+    impl Playlists {
+        fn with(self, url: &str, content: &str) -> Self {
+            self.0
+                .lock()
+                .unwrap()
+                .entry(url.to_string())
+                .or_default()
+                .push(content.to_string());
+            self
+        }
+    }
+
+    impl PlaylistFetcher for Playlists {
+        async fn fetch_playlist(&self, url: &str, _: &HeaderMap) -> Result<String, M3u8Error> {
+            let mut playlists = self.0.lock().unwrap();
+            let contents = playlists
+                .get_mut(url)
+                .ok_or_else(|| M3u8Error::DownloadError(format!("not found: {}", url)))?;
+            Ok(if contents.len() > 1 {
+                contents.remove(0)
+            } else {
+                contents[0].clone()
+            })
+        }
+    }
+
+    const MEDIA: &str = "https://example.com/media.m3u8";
+
+    async fn validate(content: &str) -> ValidationReport {
+        let fetcher = Playlists::default().with(MEDIA, content);
+        PlaylistValidator::new(HeaderMap::new(), None, false)
+            .validate(MEDIA, &fetcher)
+            .await
+            .unwrap()
+    }
+
+    // (严重程度, 行号, 信息) 列表
+    fn issues(report: &ValidationReport) -> Vec<(Severity, usize, String)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.severity, issue.line, issue.message.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn valid_playlist_has_no_issues() {
+        let report = validate(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:10\n#EXT-X-PLAYLIST-TYPE:VOD\n\
+             #EXTINF:9.5,\na.ts\n#EXTINF:10,\nb.ts\n#EXT-X-ENDLIST\n",
+        )
+        .await;
+        assert_eq!(report.playlists, 1);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+    }
+
+    #[tokio::test]
+    async fn segment_longer_than_target_duration_reports_its_line() {
+        let report = validate(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:10\n\
+             #EXTINF:10,\na.ts\n#EXTINF:12,\nb.ts\n#EXT-X-ENDLIST\n",
+        )
+        .await;
+        assert_eq!(
+            issues(&report),
+            vec![(
+                Severity::Error,
+                6,
+                "片段 #1 时长 12.000s 超过 EXT-X-TARGETDURATION 10".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn decimal_extinf_requires_version_3() {
+        // 10.0 解析后是整数，仍需按原始文本判断
+        let content = "#EXTM3U\n#EXT-X-VERSION:2\n#EXT-X-TARGETDURATION:10\n\
+                       #EXTINF:10,\na.ts\n#EXTINF:10.0,\nb.ts\n#EXT-X-ENDLIST\n";
+        assert_eq!(
+            issues(&validate(content).await),
+            vec![(
+                Severity::Error,
+                6,
+                "使用 小数形式的 EXTINF 时长 需要 EXT-X-VERSION 3 以上，当前为 2".to_string()
+            )]
+        );
+        let report = validate(&content.replace("VERSION:2", "VERSION:3")).await;
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+    }
+
+    #[tokio::test]
+    async fn version_requirements_point_at_the_tag() {
+        let report = validate(
+            "#EXTM3U\n#EXT-X-VERSION:1\n#EXT-X-TARGETDURATION:10\n\
+             #EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x1\n\
+             #EXTINF:10,\n#EXT-X-BYTERANGE:100@0\na.mp4\n#EXT-X-ENDLIST\n",
+        )
+        .await;
+        let lines: Vec<(usize, &str)> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.line, issue.message.split(' ').nth(1).unwrap()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(5, "EXT-X-KEY"), (7, "EXT-X-BYTERANGE"), (4, "EXT-X-MAP")]
+        );
+    }
+
+    #[tokio::test]
+    async fn vod_playlist_requires_endlist() {
+        let report = validate(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXTINF:10,\na.ts\n",
+        )
+        .await;
+        assert_eq!(
+            issues(&report),
+            vec![(
+                Severity::Error,
+                0,
+                "EXT-X-PLAYLIST-TYPE 为 VOD，但缺少 EXT-X-ENDLIST".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn spec_violations_report_the_parser_line() {
+        let report =
+            validate("#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:abc,\na.ts\n#EXT-X-ENDLIST\n")
+                .await;
+        assert_eq!(
+            issues(&report),
+            vec![(Severity::Error, 3, "EXTINF 的时长无效: \"abc\"".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn master_checks_variants_and_renditions() {
+        let media = "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:10\n\
+                     #EXTINF:10,\na.ts\n#EXT-X-ENDLIST\n";
+        let fetcher = Playlists::default()
+            .with(
+                "https://example.com/master.m3u8",
+                "#EXTM3U\n\
+                 #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"en\",URI=\"audio/en.m3u8\"\n\
+                 #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"fr\",URI=\"audio/fr.m3u8\"\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=1000000,CODECS=\"avc1.4d401f,mp4a.40.2\",AUDIO=\"aac\"\n\
+                 video/720.m3u8\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=2000000,AUDIO=\"aac\"\n\
+                 video/1080.m3u8\n",
+            )
+            .with("https://example.com/video/720.m3u8", media)
+            .with("https://example.com/video/1080.m3u8", media)
+            .with(
+                "https://example.com/audio/en.m3u8",
+                &media.replace("#EXTINF:10,", "#EXTINF:12,"),
+            );
+        let report = PlaylistValidator::new(HeaderMap::new(), None, false)
+            .validate("https://example.com/master.m3u8", &fetcher)
+            .await
+            .unwrap();
+
+        assert_eq!(report.playlists, 5);
+        let issues: Vec<(Severity, &str, usize)> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.severity, issue.playlist.as_str(), issue.line))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (Severity::Warning, "https://example.com/master.m3u8", 6),
+                (Severity::Error, "https://example.com/audio/en.m3u8", 4),
+                (Severity::Error, "https://example.com/audio/fr.m3u8", 0),
+            ]
+        );
+        assert!(report.issues[2].message.starts_with("无法获取媒体播放列表"));
+    }
+
+    #[tokio::test]
+    async fn live_reload_checks_media_sequence() {
+        let fetcher = Playlists::default()
+            .with(
+                MEDIA,
+                "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:5\n#EXTINF:1,\na.ts\n",
+            )
+            .with(
+                MEDIA,
+                "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:4\n#EXTINF:1,\nb.ts\n",
+            );
+        let report = PlaylistValidator::new(HeaderMap::new(), None, true)
+            .validate(MEDIA, &fetcher)
+            .await
+            .unwrap();
+        let messages: Vec<&str> = report.issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["重新获取后 EXT-X-MEDIA-SEQUENCE 由 5 减小为 4"]
+        );
+    }
+}